@@ -0,0 +1,31 @@
+//! Where a host listens, and what guests should type to reach it.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+
+/// Port a host listens on unless told otherwise.
+pub const DEFAULT_PORT: u16 = 9999;
+
+/// This machine's address on the local network, the one traffic to other machines
+/// leaves from. `None` when there is no network to reach.
+pub fn lan_address() -> Option<IpAddr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+    // Connecting a UDP socket only picks a route; nothing is sent
+    socket.connect((Ipv4Addr::new(10, 254, 254, 254), 1)).ok()?;
+    let ip = socket.local_addr().ok()?.ip();
+    (!ip.is_unspecified() && !ip.is_loopback()).then_some(ip)
+}
+
+/// Addresses a guest can connect to when the host listens on `bound`. Listening on
+/// all interfaces is reached through the LAN address from other machines and through
+/// loopback from this one.
+pub fn guest_addresses(bound: SocketAddr) -> Vec<SocketAddr> {
+    if !bound.ip().is_unspecified() {
+        return vec![bound];
+    }
+    let mut addresses = Vec::new();
+    if let Some(ip) = lan_address() {
+        addresses.push(SocketAddr::new(ip, bound.port()));
+    }
+    addresses.push(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), bound.port()));
+    addresses
+}
@@ -0,0 +1,100 @@
+//! The authority of a state sync match: the one place that steps every character and
+//! decides grabs and scores.
+//!
+//! Players whose characters it steps send their inputs, labelled with the tick they
+//! predicted them on (see [`Prediction`](crate::prediction::Prediction)). Each tick the
+//! session steps the next queued input of each of them, and reports the outcomes and
+//! where their characters ended up for them to reconcile with. A host plays one side
+//! itself and leaves the other to the guest; a dedicated server leaves both to players.
+
+use std::collections::VecDeque;
+
+use crate::character::Side;
+use crate::communication::Communication;
+use crate::input::Input;
+use crate::protocol::Message;
+use crate::world::{MatchEvent, World, TICK_DT};
+
+// Player inputs let queue up before skipping ahead
+const MAX_QUEUED_INPUTS: usize = 4;
+
+pub struct AuthoritySession {
+    world: World,
+    previous_world: World,
+    // Inputs received from the player of each side, not stepped yet
+    inputs: [VecDeque<(u64, Input)>; 2],
+    // Tick of the last input stepped for each side's player
+    stepped: [Option<u64>; 2],
+}
+
+impl AuthoritySession {
+    /// Starts from `world`, which may be mid-match as when resuming after a reconnect.
+    pub fn new(mut world: World) -> AuthoritySession {
+        world.resolves_grabs = true;
+        AuthoritySession {
+            previous_world: world.clone(),
+            world,
+            inputs: [VecDeque::new(), VecDeque::new()],
+            stepped: [None, None],
+        }
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    /// State one tick before [`world`](Self::world), to draw blended towards it.
+    pub fn previous_world(&self) -> &World {
+        &self.previous_world
+    }
+
+    /// Queues the input the player of `side` sent for `tick`.
+    pub fn add_input(&mut self, side: Side, tick: u64, input: Input) {
+        self.inputs[side.index()].push_back((tick, input));
+    }
+
+    // Input to step `side` with: its player's next one, or the last one seen.
+    fn next_input(&mut self, side: Side) -> Input {
+        let inputs = &mut self.inputs[side.index()];
+        // After a hiccup, skips ahead rather than trailing the player for good
+        while inputs.len() > MAX_QUEUED_INPUTS {
+            inputs.pop_front();
+        }
+        match inputs.pop_front() {
+            Some((tick, input)) => {
+                self.stepped[side.index()] = Some(tick);
+                input
+            }
+            None => self.world.character(side).input,
+        }
+    }
+
+    /// Steps one tick. A side given an input in `own` is played here with it, any other
+    /// with its player's next input. Returns the grabs and scores it led to, for every
+    /// player to apply.
+    pub fn advance(&mut self, own: [Option<Input>; 2]) -> Vec<MatchEvent> {
+        let mut inputs = [Input::NONE; 2];
+        for side in [Side::Top, Side::Bottom] {
+            inputs[side.index()] = match own[side.index()] {
+                Some(input) => input,
+                None => self.next_input(side),
+            };
+        }
+        self.previous_world = self.world.clone();
+        let scores = self.world.step(TICK_DT, inputs);
+
+        let mut events = self.previous_world.grab_events(&self.world);
+        events.extend(scores.into_iter().map(MatchEvent::from));
+        events
+    }
+
+    /// Where the character on `side` is now, for its player to reconcile its prediction
+    /// with, once one of its inputs has been stepped.
+    pub fn correction(&self, side: Side) -> Option<Message> {
+        let tick = self.stepped[side.index()]?;
+        Some(Message::Correction {
+            tick,
+            character: self.world.character(side).get_send_data(),
+        })
+    }
+}
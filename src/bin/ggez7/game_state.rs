@@ -1,1031 +1,612 @@
-use ggez::conf::{WindowMode, WindowSetup};
-use ggez::event::quit;
-use ggez::event::{run, EventHandler, KeyCode, KeyMods};
+use ggez::event::{KeyCode, KeyMods};
 use ggez::graphics::{clear, draw, Color, DrawParam, Image, Text};
-use ggez::input::keyboard::is_key_pressed;
 use ggez::mint::Point2;
-use ggez::timer::delta;
-use ggez::{Context, ContextBuilder, GameResult};
+use ggez::Context;
 
-use std::cell::RefCell;
-use std::f32::consts::PI;
+use std::collections::HashMap;
 use std::rc::Rc;
-use std::time;
+use std::time::Duration;
 
-use rand::rngs::ThreadRng;
-use rand::{thread_rng, Rng};
+use ggez_project::interpolation::show_as;
+use ggez_project::lockstep::{delay_for_rtt, DEFAULT_INPUT_DELAY};
+use ggez_project::{
+    open_transport, AuthoritySession, Communication, FixedTimestep, Handshake, Input,
+    KeyboardInput, LockstepSession, MatchSettings, Message, Netcode, NetworkConditions, Prediction,
+    Renderer, RollbackSession, Side, SimulatedNetwork, SnapshotBuffer, Transport, World, TICK_DT,
+};
 
-use std::collections::HashMap;
-use std::io::Read;
-use std::io::Write;
-use std::net::{TcpListener, TcpStream};
+use crate::helper::{EState, IState};
+use crate::rejoin::{Reconnect, Rejoin};
+use crate::settings::Settings;
 
-use crate::helper::{load_image, EState, IState};
+// Seconds between round trip measurements. The pings double as heartbeats.
+const PING_INTERVAL: f32 = 0.5;
 
-pub trait Communication {
-    fn get_send_data(&self) -> Vec<u8>;
-    fn set_recv_data(&mut self, buf: &mut Vec<u8>);
-}
-
-#[derive(Clone, Copy, PartialEq)]
-struct Transform {
-    position: Point2<f32>,
-    rotation: f32,
-    scale: Point2<f32>,
+pub struct GameState {
+    renderer: Renderer,
+    world: World,
+    // State before the latest step, drawn blended towards `world`
+    previous_world: World,
+    timestep: FixedTimestep,
+    keyboard: KeyboardInput,
+    local: Side,
+    // Dropped when the peer sends something malformed or goes away
+    connection: Option<Box<dyn Transport>>,
+    // Simulated on every connection when set, for testing
+    network: Option<NetworkConditions>,
+    last_recv: f32,
+    // Opponent states as received, to draw it smoothly a little in the past
+    snapshots: SnapshotBuffer,
+    // Under state sync the guest predicts its own character, which the host's session
+    // steps from the guest's inputs as they come in. Against a dedicated server both
+    // players are guests.
+    prediction: Option<Prediction>,
+    authority: Option<AuthoritySession>,
+    opponent_name: String,
+    // Set when the match exchanges inputs only; it then owns the simulation
+    rollback: Option<RollbackSession>,
+    lockstep: Option<LockstepSession>,
+    // Fixed lockstep input delay, or none to follow `rtt`
+    input_delay: Option<u64>,
+    // Smoothed round trip time to the opponent
+    rtt: Option<Duration>,
+    last_ping: f32,
+
+    // Our name and the agreed settings, to shake hands again after a disconnect
+    name: String,
+    settings: Option<MatchSettings>,
+    is_server: bool,
+    // Seconds since anything last arrived from the opponent, and how many make it count
+    // as disconnected
+    silence: f32,
+    heartbeat_timeout: f32,
+    // How long to wait for a disconnected opponent, and how much of that is left
+    timeout: Duration,
+    countdown: Option<f32>,
+    rejoin: Option<Rejoin>,
+    reconnect: Option<Reconnect>,
+    // Set on a guest that reconnected, until the host's world arrives
+    awaiting_resume: bool,
+    // Set on a player of a dedicated server until it is told its side
+    awaiting_seat: bool,
+    /// Why the match ended early, for the menu to show.
+    pub end_reason: Option<String>,
 }
 
-impl Transform {
-    fn new() -> Transform {
-        Transform {
-            position: Point2 { x: 0.0, y: 0.0 },
-            rotation: 0.0,
-            scale: Point2 { x: 1.0, y: 1.0 },
-        }
-    }
-
-    fn forward(&self) -> Point2<f32> {
-        Point2 {
-            x: self.rotation.cos(),
-            y: self.rotation.sin(),
-        }
-    }
-
-    fn back(&self) -> Point2<f32> {
-        Point2 {
-            x: -self.rotation.cos(),
-            y: -self.rotation.sin(),
-        }
-    }
-
-    fn right(&self) -> Point2<f32> {
-        Point2 {
-            x: (self.rotation + PI / 2.0).cos(),
-            y: (self.rotation + PI / 2.0).sin(),
-        }
-    }
-
-    fn left(&self) -> Point2<f32> {
-        Point2 {
-            x: (self.rotation - PI / 2.0).cos(),
-            y: (self.rotation - PI / 2.0).sin(),
-        }
-    }
-
-    fn rotate(&mut self, rotation: f32) {
-        self.rotation += rotation
-    }
-
-    fn move_offset(&mut self, offset: Point2<f32>) {
-        self.position.x += offset.x;
-        self.position.y += offset.y;
-    }
-
-    fn move_offset_x(&mut self, offset_x: f32) {
-        self.position.x += offset_x;
-    }
-
-    fn move_offset_y(&mut self, offset_y: f32) {
-        self.position.y += offset_y;
-    }
-
-    fn magnitude(&self) -> f32 {
-        (self.position.x.powf(2.0) + self.position.y.powf(2.0)).sqrt()
+impl GameState {
+    pub fn initialize(
+        &mut self,
+        is_server: bool,
+        connection: Option<Box<dyn Transport>>,
+        handshake: &Handshake,
+        rejoin: Option<Rejoin>,
+    ) {
+        self.connection = connection.map(|connection| self.simulate_network(connection));
+        self.last_recv = 0.0;
+        self.snapshots.clear();
+        self.opponent_name = handshake.peer.name.clone();
+        self.settings = Some(handshake.settings);
+        self.is_server = is_server;
+        self.silence = 0.0;
+        self.countdown = None;
+        self.rejoin = rejoin;
+        self.reconnect = None;
+        self.awaiting_resume = false;
+        self.end_reason = None;
+
+        // The host plays the top side, the guest the bottom side. A dedicated server
+        // seats its players itself once both are in.
+        let netcode = handshake.settings.netcode;
+        self.local = if is_server { Side::Top } else { Side::Bottom };
+        self.awaiting_seat = netcode == Netcode::Server;
+        self.world = World::with_rules(handshake.settings.seed, handshake.settings.rules);
+        // Rollback and lockstep peers step the same inputs, so each resolves grabs itself.
+        // Otherwise only the authority decides grabs and scores, and guests apply its events.
+        self.world.resolves_grabs = match netcode {
+            Netcode::StateSync => is_server,
+            Netcode::Rollback | Netcode::Lockstep => true,
+            Netcode::Server => false,
+        };
+        self.prediction = match netcode {
+            Netcode::StateSync if !is_server => Some(Prediction::new(self.local)),
+            _ => None,
+        };
+        self.authority = match netcode {
+            Netcode::StateSync if is_server => Some(AuthoritySession::new(self.world.clone())),
+            _ => None,
+        };
+        self.rollback = match netcode {
+            Netcode::Rollback => Some(RollbackSession::new(self.world.clone(), self.local)),
+            _ => None,
+        };
+        self.lockstep = match netcode {
+            Netcode::Lockstep => Some(LockstepSession::new(
+                self.world.clone(),
+                self.local,
+                self.input_delay.unwrap_or(DEFAULT_INPUT_DELAY),
+            )),
+            _ => None,
+        };
+        self.rtt = None;
+        self.last_ping = 0.0;
+        self.previous_world = self.world.clone();
+        self.timestep.reset();
     }
 
-    fn rotate_point(point: Point2<f32>, rotation: f32) -> Point2<f32> {
-        let magnitude = (point.x.powf(2.0) + point.y.powf(2.0)).sqrt();
-        let radian = point.y.atan2(point.x) + rotation;
-        Point2 {
-            x: magnitude * radian.cos(),
-            y: magnitude * radian.sin(),
+    // Game Setting
+    pub fn new(
+        ctx: &mut Context,
+        image_pool: &mut HashMap<String, Rc<Image>>,
+        settings: &Settings,
+    ) -> GameState {
+        GameState {
+            renderer: Renderer::new(ctx, image_pool),
+            world: World::new(0),
+            previous_world: World::new(0),
+            timestep: FixedTimestep::new(),
+            keyboard: KeyboardInput::new(),
+            local: Side::Bottom,
+            connection: None,
+            network: settings.network,
+            last_recv: 0.0,
+            snapshots: SnapshotBuffer::new(settings.interpolation_delay),
+            prediction: None,
+            authority: None,
+            opponent_name: String::new(),
+            rollback: None,
+            lockstep: None,
+            input_delay: settings.input_delay,
+            rtt: None,
+            last_ping: 0.0,
+            name: settings.name.clone(),
+            settings: None,
+            is_server: false,
+            silence: 0.0,
+            heartbeat_timeout: settings.heartbeat_timeout.as_secs_f32(),
+            timeout: settings.timeout,
+            countdown: None,
+            rejoin: None,
+            reconnect: None,
+            awaiting_resume: false,
+            awaiting_seat: false,
+            end_reason: None,
+        }
+    }
+
+    fn simulate_network(&self, connection: Box<dyn Transport>) -> Box<dyn Transport> {
+        match self.network {
+            Some(conditions) => {
+                println!("Simulating {}", conditions);
+                Box::new(SimulatedNetwork::new(connection, conditions))
+            }
+            None => connection,
         }
     }
-}
-
-struct GameObject {
-    transform: Transform,
-    global_transform: Transform,
-    rc_parent: Option<Rc<RefCell<GameObject>>>,
-}
 
-impl GameObject {
-    fn new() -> GameObject {
-        GameObject {
-            transform: Transform::new(),
-            global_transform: Transform::new(),
-            rc_parent: None,
+    fn send_data(&mut self, ctx: &mut Context) {
+        let message = Message::State {
+            time: ggez::timer::time_since_start(ctx).as_secs_f32(),
+            character: self.world.character(self.local).get_send_data(),
+        };
+        self.send(&message);
+    }
+
+    fn send(&mut self, message: &Message) {
+        if let Some(connection) = &mut self.connection {
+            if let Err(err) = connection.send(message) {
+                eprintln!("Connection lost: {}", err);
+                self.connection = None;
+            }
         }
     }
 
-    fn set_rc_parent(&mut self, rc_parent: &Rc<RefCell<GameObject>>) {
-        self.rc_parent = Some(Rc::clone(rc_parent));
-    }
-
-    fn update_global_transform(&mut self) {
-        if let Some(rc_parent) = self.rc_parent.clone() {
-            let parent = rc_parent.borrow();
-            self.global_transform.rotation =
-                self.transform.rotation + parent.global_transform.rotation;
-
-            self.global_transform.scale.x =
-                self.transform.scale.x * parent.global_transform.scale.x;
-            self.global_transform.scale.y =
-                self.transform.scale.y * parent.global_transform.scale.y;
-
-            let scaled_pos_x = self.transform.position.x * self.global_transform.scale.x;
-            let scaled_pos_y = self.transform.position.y * self.global_transform.scale.y;
-            let relative_position = Transform::rotate_point(
-                Point2 {
-                    x: scaled_pos_x,
-                    y: scaled_pos_y,
-                },
-                parent.global_transform.rotation,
-            );
-            self.global_transform.position.x =
-                parent.global_transform.position.x + relative_position.x;
-            self.global_transform.position.y =
-                parent.global_transform.position.y + relative_position.y;
-        } else {
-            self.global_transform = self.transform;
+    fn flush(&mut self) {
+        if let Some(connection) = &mut self.connection {
+            if let Err(err) = connection.flush() {
+                eprintln!("Connection lost: {}", err);
+                self.connection = None;
+            }
         }
     }
 
-    fn update_local_transform(&mut self) {
-        if let Some(rc_parent) = self.rc_parent.clone() {
-            let parent = rc_parent.borrow();
-            self.transform.rotation =
-                self.global_transform.rotation - parent.global_transform.rotation;
-
-            self.transform.scale.x =
-                self.global_transform.scale.x / parent.global_transform.scale.x;
-            self.global_transform.scale.y =
-                self.global_transform.scale.y / parent.global_transform.scale.y;
-
-            let relative_x = self.global_transform.position.x - parent.global_transform.position.x;
-            let relative_y = self.global_transform.position.y - parent.global_transform.position.y;
-            let local_scaled_position = Transform::rotate_point(
-                Point2 {
-                    x: relative_x,
-                    y: relative_y,
-                },
-                -parent.global_transform.rotation,
-            );
-            self.transform.position.x = local_scaled_position.x / self.transform.scale.x;
-            self.transform.position.y = local_scaled_position.y / self.transform.scale.y;
-        } else {
-            self.transform = self.global_transform;
+    // Measures the round trip every `PING_INTERVAL`, stamping pings with the time sent.
+    fn ping(&mut self, ctx: &mut Context) {
+        let now = ggez::timer::time_since_start(ctx);
+        if now.as_secs_f32() - self.last_ping >= PING_INTERVAL {
+            self.last_ping = now.as_secs_f32();
+            self.send(&Message::Ping(now.as_micros() as u64));
         }
     }
-}
-
-impl Communication for GameObject {
-    fn get_send_data(&self) -> Vec<u8> {
-        let mut data = vec![];
-
-        let px = self.transform.position.x.to_ne_bytes();
-        let py = self.transform.position.y.to_ne_bytes();
-        let r = self.transform.rotation.to_ne_bytes();
-        let sx = self.transform.scale.x.to_ne_bytes();
-        let sy = self.transform.scale.y.to_ne_bytes();
-
-        data.extend_from_slice(&px);
-        data.extend_from_slice(&py);
-        data.extend_from_slice(&r);
-        data.extend_from_slice(&sx);
-        data.extend_from_slice(&sy);
-        data
-    }
-
-    fn set_recv_data(&mut self, buf: &mut Vec<u8>) {
-        let data = buf.as_slice();
-        let px = f32::from_ne_bytes(data[0..4].try_into().unwrap());
-        let py = f32::from_ne_bytes(data[4..8].try_into().unwrap());
-        let r = f32::from_ne_bytes(data[8..12].try_into().unwrap());
-        let sx = f32::from_ne_bytes(data[12..16].try_into().unwrap());
-        let sy = f32::from_ne_bytes(data[16..20].try_into().unwrap());
-        self.transform.position.x = px;
-        self.transform.position.y = py;
-        self.transform.rotation = r;
-        self.transform.scale.x = sx;
-        self.transform.scale.y = sy;
-        self.update_global_transform();
-    }
-}
-
-//' state {0: nothing, 1: throw, -1: catched}
-struct Grab {
-    hand_image: Rc<Image>,
-    string_image: Rc<Image>,
-    rc_gameobject: Rc<RefCell<GameObject>>,
-    rc_target: Option<Rc<RefCell<Character>>>,
-    threshold: f32,
-    speed: f32,
-    state: f32,
-    check_grab_once: bool,
-}
 
-impl Grab {
-    fn new(ctx: &mut Context, image_pool: &mut HashMap<String, Rc<Image>>) -> Grab {
-        Grab {
-            hand_image: load_image(ctx, String::from("/grab_hand.png"), image_pool),
-            string_image: load_image(ctx, String::from("/grab_string.png"), image_pool),
-            rc_gameobject: Rc::new(RefCell::new(GameObject::new())),
-            rc_target: None,
-            threshold: 580.0,
-            speed: 800.0,
-            state: 0.0,
-            check_grab_once: false,
-        }
+    fn measure_rtt(&mut self, ctx: &mut Context, sent: u64) {
+        let now = ggez::timer::time_since_start(ctx).as_micros() as u64;
+        let sample = Duration::from_micros(now.saturating_sub(sent));
+        // Smoothed like TCP's round trip estimate, so one slow packet barely moves it
+        self.rtt = Some(match self.rtt {
+            Some(rtt) => (rtt * 7 + sample) / 8,
+            None => sample,
+        });
     }
 
-    fn set_rc_target(&mut self, rc_target: &Rc<RefCell<Character>>) {
-        self.rc_target = Some(Rc::clone(rc_target));
-    }
-
-    fn set_rotation(&mut self, rotation: f32) {
-        self.rc_gameobject.borrow_mut().transform.rotation = rotation;
-    }
-
-    fn set_position(&mut self, position: Point2<f32>) {
-        self.rc_gameobject.borrow_mut().transform.position = position;
-    }
-}
-
-impl Communication for Grab {
-    fn get_send_data(&self) -> Vec<u8> {
-        let mut data = Vec::new();
-        let sp = self.speed.to_ne_bytes();
-        let st = self.state.to_ne_bytes();
-        let go = self.rc_gameobject.borrow().get_send_data();
-
-        data.extend_from_slice(&sp);
-        data.extend_from_slice(&st);
-        data.extend(go);
-        data
-    }
-
-    fn set_recv_data(&mut self, buf: &mut Vec<u8>) {
-        let (data, buf) = buf.split_at(8);
-        let sp = f32::from_ne_bytes(data[0..4].try_into().unwrap());
-        let st = f32::from_ne_bytes(data[4..8].try_into().unwrap());
-        self.speed = sp;
-        self.state = st;
-
-        let mut buf = Vec::from(buf);
-        self.rc_gameobject.borrow_mut().set_recv_data(&mut buf);
-    }
-}
-
-impl EventHandler for Grab {
-    fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
-        let delta = delta(&ctx);
-        let dt = delta.as_secs() as f32 + delta.subsec_nanos() as f32 * 1e-9;
-        let speed = dt * self.speed * self.state;
-        {
-            let mut gameobject = self.rc_gameobject.borrow_mut();
-            let delta_vec = gameobject.transform.forward();
-            gameobject.transform.position.x += speed * delta_vec.x;
-            gameobject.transform.position.y += speed * delta_vec.y;
-
-            gameobject.update_global_transform();
-
-            self.rc_target.as_ref().map(|rc_target| {
-                let mut target = rc_target.borrow_mut();
-                if self.state == 1.0 && gameobject.transform.position.x > self.threshold {
-                    // Check target is in grab range (80.0)
-                    if (target.get_global_position().x - gameobject.global_transform.position.x)
-                        .abs()
-                        < 80.0
-                    {
-                        self.state = -1.0;
-                        target.set_global_rotation(gameobject.global_transform.rotation - PI);
-                        target.is_grabbed_by = true;
-                    } else {
-                        self.state = 0.0;
+    fn recv_data(&mut self, ctx: &mut Context) {
+        let messages = match self.connection.as_mut().map(|connection| connection.receive()) {
+            Some(Ok(messages)) => messages,
+            Some(Err(err)) => {
+                eprintln!("Connection lost: {}", err);
+                self.connection = None;
+                return;
+            }
+            None => return,
+        };
+        if !messages.is_empty() {
+            self.silence = 0.0;
+        }
+        for message in messages {
+            match message {
+                Message::State { time, character } if time > self.last_recv => {
+                    let remote = self.local.opponent();
+                    self.world.set_remote_character(remote, &character);
+                    self.last_recv = time;
+
+                    let mut seen = self.world.character(remote).clone();
+                    seen.set_recv_data(&character);
+                    let now = ggez::timer::time_since_start(ctx).as_secs_f32();
+                    self.snapshots.push(time, now, seen);
+                }
+                Message::Event(event) if !self.world.resolves_grabs => {
+                    self.world.apply_match_event(event);
+                }
+                Message::Input { tick, input } => {
+                    if let Some(session) = &mut self.rollback {
+                        session.add_remote_input(tick, input);
+                    } else if let Some(session) = &mut self.lockstep {
+                        session.add_remote_input(tick, input);
+                    } else if let Some(session) = &mut self.authority {
+                        session.add_input(self.local.opponent(), tick, input);
                     }
-                } else if self.state == -1.0 {
-                    if gameobject.transform.position.x < 0.0 {
-                        self.state = 0.0;
-                        self.check_grab_once = true;
-                    } else {
-                        // target position is same with grab position
-                        target.set_global_position(gameobject.global_transform.position);
+                }
+                Message::Correction { tick, character } => {
+                    if let Some(prediction) = &mut self.prediction {
+                        prediction.reconcile(&mut self.world, tick, &character);
+                        // The leftover correction is drawn from here on, not blended in
+                        let local = self.local.index();
+                        self.previous_world.characters[local] =
+                            self.world.characters[local].clone();
                     }
-                } else if self.state == 0.0 && target.is_grabbed_by {
-                    target.rebirth(true);
                 }
-            });
-        }
-        Ok(())
-    }
-
-    fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
-        if self.state != 0.0 {
-            let gameobject = self.rc_gameobject.borrow();
-
-            let scale_y =
-                gameobject.transform.magnitude() / (self.string_image.as_ref().height() as f32);
-
-            let string_draw_param = DrawParam::new()
-                .dest(gameobject.global_transform.position)
-                .rotation(gameobject.global_transform.rotation - PI / 2.0)
-                .offset(Point2 { x: 0.5, y: 1.0 })
-                .scale(Point2 { x: 1.0, y: scale_y });
-            draw(ctx, self.string_image.as_ref(), string_draw_param)?;
-
-            let hand_draw_param = DrawParam::new()
-                .dest(gameobject.global_transform.position)
-                .rotation(gameobject.global_transform.rotation - PI / 2.0)
-                .offset(Point2 { x: 0.5, y: 0.5 });
-            draw(ctx, self.hand_image.as_ref(), hand_draw_param)?;
+                Message::Ping(token) => self.send(&Message::Pong(token)),
+                Message::Pong(token) => self.measure_rtt(ctx, token),
+                // A dedicated server also restarts the player that stayed
+                Message::Resume(state) if self.awaiting_resume || self.has_server() => {
+                    let mut world = self.world.clone();
+                    world.restore_state(&state);
+                    self.restart_from(world);
+                    self.awaiting_resume = false;
+                }
+                Message::Seat { side, opponent } if self.awaiting_seat => {
+                    println!("Playing the {:?} side against {}", side, opponent);
+                    self.local = side;
+                    self.opponent_name = opponent;
+                    self.prediction = Some(Prediction::new(side));
+                    self.awaiting_seat = false;
+                }
+                _ => {}
+            }
         }
-        Ok(())
     }
-}
-
-struct Target {
-    image: Rc<Image>,
-    rc_gameobject: Rc<RefCell<GameObject>>,
-    speed: f32,
-    direction: f32,
-    look_at_x: f32,
-}
 
-impl Target {
-    fn new(ctx: &mut Context, image_pool: &mut HashMap<String, Rc<Image>>) -> Target {
-        Target {
-            image: load_image(ctx, String::from("/target.png"), image_pool),
-            rc_gameobject: Rc::new(RefCell::new(GameObject::new())),
-            direction: 1.0,
-            speed: 800.0,
-            look_at_x: 0.0,
+    // Looks for the opponent again while counting down, and gives up on the match once
+    // the time is up.
+    fn wait_for_opponent(&mut self, ctx: &mut Context) -> EState {
+        if self.world.is_game_end() {
+            return EState::None;
         }
-    }
-
-    fn get_rotation(&self) -> f32 {
-        self.rc_gameobject.borrow().transform.rotation
-    }
-}
-
-impl Communication for Target {
-    fn get_send_data(&self) -> Vec<u8> {
-        let mut data = vec![];
-        let la = self.look_at_x.to_ne_bytes();
-
-        data.extend_from_slice(&la);
-        data
-    }
-
-    fn set_recv_data(&mut self, buf: &mut Vec<u8>) {
-        let (data, buf) = buf.split_at(4);
-        self.look_at_x = f32::from_ne_bytes(data[0..4].try_into().unwrap());
-    }
-}
-
-impl EventHandler for Target {
-    fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
-        let delta = ggez::timer::delta(ctx);
-        let dt = delta.as_secs() as f32 + delta.subsec_nanos() as f32 * 1e-9;
-        let delta_dist = dt * self.direction * self.speed;
-
-        self.look_at_x += delta_dist;
-
-        if self.look_at_x > 440.0 {
-            self.direction = -1.0;
-            self.look_at_x = 440.0;
-        } else if self.look_at_x < -440.0 {
-            self.direction = 1.0;
-            self.look_at_x = -440.0;
+        let countdown = self.countdown.get_or_insert(self.timeout.as_secs_f32());
+        *countdown -= ggez::timer::delta(ctx).as_secs_f32();
+        if *countdown <= 0.0 {
+            self.reconnect = None;
+            self.rejoin = None;
+            self.end_reason = Some(format!(
+                "{} did not come back within {} seconds",
+                self.opponent_name,
+                self.timeout.as_secs()
+            ));
+            return EState::Menu;
         }
 
+        if let (None, Some(rejoin), Some(settings)) = (&self.reconnect, &self.rejoin, self.settings)
         {
-            let mut gameobject = self.rc_gameobject.borrow_mut();
-            if let Some(rc_parent) = gameobject.rc_parent.clone() {
-                let parent = rc_parent.borrow();
-                let offset = parent.transform.position.y / parent.transform.position.y.abs();
-                let dist_x = offset * (self.look_at_x - parent.transform.position.x);
-                gameobject.transform.rotation = dist_x.atan2(580.0_f32);
+            self.reconnect = Some(Reconnect::start(rejoin, &self.name, settings));
+        }
+        let found = self.reconnect.as_ref().and_then(Reconnect::poll);
+        if let Some((stream, handshake)) = found {
+            self.reconnect = None;
+            match open_transport(stream, handshake.settings.transport) {
+                Ok(connection) => self.resume(connection, &handshake),
+                Err(err) => eprintln!("Reconnect failed: {}", err),
             }
-            gameobject.update_global_transform();
-        }
-
-        Ok(())
-    }
-
-    fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
-        {
-            let gameobject = self.rc_gameobject.borrow();
-            let draw_param = DrawParam::new()
-                .dest(gameobject.global_transform.position)
-                .rotation(gameobject.global_transform.rotation - PI / 2.0)
-                .offset(Point2 { x: 0.5, y: 0.0 })
-                .color(if self.speed > 0.0 {
-                    Color::CYAN
-                } else {
-                    Color::BLACK
-                });
-            draw(ctx, self.image.as_ref(), draw_param)?;
-        }
-        Ok(())
-    }
-}
-
-struct Flash {
-    image: Rc<Image>,
-    cooldown_image: Rc<Image>,
-    effect_images: [Rc<Image>; 5],
-    rc_gameobject: Rc<RefCell<GameObject>>,
-    is_player: bool,
-    cooltime: f32,
-    cooldown: f32,
-    distance: f32,
-}
-
-impl Flash {
-    fn new(ctx: &mut Context, image_pool: &mut HashMap<String, Rc<Image>>) -> Flash {
-        Flash {
-            image: load_image(ctx, String::from("/flash.png"), image_pool),
-            effect_images: [
-                load_image(ctx, String::from("/flash0.png"), image_pool),
-                load_image(ctx, String::from("/flash1.png"), image_pool),
-                load_image(ctx, String::from("/flash2.png"), image_pool),
-                load_image(ctx, String::from("/flash3.png"), image_pool),
-                load_image(ctx, String::from("/flash4.png"), image_pool),
-            ],
-            cooldown_image: load_image(ctx, String::from("/cooldown.png"), image_pool),
-            rc_gameobject: Rc::new(RefCell::new(GameObject::new())),
-            is_player: true,
-            cooltime: 5.0,
-            cooldown: 0.0,
-            distance: 200.0,
         }
+        EState::None
     }
 
-    fn use_skill(&mut self, rc_subject: &Rc<RefCell<GameObject>>, direction: f32) {
-        if self.cooldown > 0.0 || direction == 0.0 {
+    // Picks the match up again over the opponent's new connection. The host's world is
+    // authoritative, so a guest waits for it before stepping on.
+    fn resume(&mut self, connection: Box<dyn Transport>, handshake: &Handshake) {
+        println!("{} is back", handshake.peer.name);
+        self.connection = Some(self.simulate_network(connection));
+        self.opponent_name = handshake.peer.name.clone();
+        self.silence = 0.0;
+        self.countdown = None;
+        if !self.is_server {
+            self.awaiting_resume = true;
+            // A dedicated server seats a returning player again before resuming
+            self.awaiting_seat = self.has_server();
             return;
         }
-        self.cooldown = self.cooltime;
-        {
-            let mut subject = rc_subject.borrow_mut();
-            let mut gameobject = self.rc_gameobject.borrow_mut();
-            gameobject.transform.position = subject.transform.position;
-            let direction = subject.transform.right().x * direction;
-            subject.transform.move_offset_x(direction * self.distance);
-
-            gameobject.update_global_transform();
-        }
-    }
-}
-
-impl Communication for Flash {
-    fn get_send_data(&self) -> Vec<u8> {
-        let mut data = vec![];
-        let position = self.rc_gameobject.borrow().transform.position;
-        let cd = self.cooldown.to_ne_bytes();
-        let px = position.x.to_ne_bytes();
-        let py = position.y.to_ne_bytes();
 
-        data.extend_from_slice(&cd);
-        data.extend_from_slice(&px);
-        data.extend_from_slice(&py);
-
-        data
-    }
-
-    fn set_recv_data(&mut self, buf: &mut Vec<u8>) {
-        let (data, buf) = buf.split_at(12);
-        let mut gameobject = self.rc_gameobject.borrow_mut();
-        self.cooldown = f32::from_ne_bytes(data[0..4].try_into().unwrap());
-        gameobject.transform.position.x = f32::from_ne_bytes(data[4..8].try_into().unwrap());
-        gameobject.transform.position.y = f32::from_ne_bytes(data[8..12].try_into().unwrap());
-        gameobject.update_global_transform();
+        let world = if let Some(session) = &mut self.rollback {
+            session.confirmed_world().clone()
+        } else if let Some(session) = &self.lockstep {
+            session.world().clone()
+        } else if let Some(session) = &self.authority {
+            session.world().clone()
+        } else {
+            self.world.clone()
+        };
+        self.send(&Message::Resume(world.state_bytes()));
+        self.restart_from(world);
     }
-}
 
-impl EventHandler for Flash {
-    fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
-        let delta = ggez::timer::delta(ctx);
-        let dt = delta.as_secs() as f32 + delta.subsec_nanos() as f32 * 1e-9;
-        self.cooldown = (self.cooldown - dt).max(0.0);
-        Ok(())
+    // Whether a dedicated server runs the match.
+    fn has_server(&self) -> bool {
+        self.settings
+            .is_some_and(|settings| settings.netcode == Netcode::Server)
     }
 
-    fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
-        if self.is_player {
-            let text_draw_params = DrawParam::new()
-                .dest(Point2 { x: 50.0, y: 685.0 })
-                .scale([1.4, 1.4])
-                .color(Color::YELLOW);
-            draw(ctx, &Text::new("Shift"), text_draw_params)?;
-
-            let draw_param = DrawParam::new()
-                .dest(Point2 { x: 80.0, y: 640.0 })
-                .offset(Point2 { x: 0.5, y: 0.5 })
-                .scale(Point2 { x: 0.2, y: 0.2 });
-            draw(ctx, self.image.as_ref(), draw_param)?;
-
-            if self.cooldown > 0.0 {
-                // Draw Cooldown
-                let cooldown_rect_draw_params = DrawParam::new()
-                    .dest(Point2 { x: 80.0, y: 605.0 })
-                    .offset(Point2 { x: 0.5, y: 0.0 })
-                    .scale([0.32, 0.32])
-                    .color(Color::from_rgba(0, 0, 0, 200));
-                draw(ctx, self.cooldown_image.as_ref(), cooldown_rect_draw_params)?;
-
-                let cooldown_text_draw_params = DrawParam::new()
-                    .dest(Point2 { x: 50.0, y: 620.0 })
-                    .scale([2.4, 2.4])
-                    .color(Color::WHITE);
-                draw(
-                    ctx,
-                    &Text::new(format!("{:.1}", self.cooldown)),
-                    cooldown_text_draw_params,
-                )?;
-            }
-        }
-
-        // Draw Effect
-        let inverse_cooldown = self.cooltime - self.cooldown;
-        if inverse_cooldown < 0.25 {
-            let effect_draw_params = DrawParam::new()
-                .dest(self.rc_gameobject.borrow().global_transform.position)
-                .offset([0.5, 0.5]);
-            draw(
-                ctx,
-                self.effect_images[(inverse_cooldown * 20.0) as usize].as_ref(),
-                effect_draw_params,
-            )?;
+    // Carries on from `world`, dropping the inputs and predictions made before it.
+    fn restart_from(&mut self, world: World) {
+        if let Some(session) = &mut self.rollback {
+            *session = RollbackSession::new(world.clone(), self.local);
         }
-        Ok(())
-    }
-}
-
-struct Character {
-    default_image: Rc<Image>,
-    motion_image: Rc<Image>,
-    rc_gameobject: Rc<RefCell<GameObject>>,
-    is_server: bool,
-    move_state: f32,
-    move_speed: f32,
-    score: i32,
-    is_opponent: bool,
-
-    target: Target,
-    grab: Grab,
-    is_grabbed_by: bool,
-    flash: Flash,
-}
-
-impl Character {
-    fn new(
-        ctx: &mut Context,
-        image_pool: &mut HashMap<String, Rc<Image>>,
-        is_server: bool,
-    ) -> Character {
-        let rc_gameobject = Rc::new(RefCell::new(GameObject::new()));
-        let target = Target::new(ctx, image_pool);
-        {
-            target
-                .rc_gameobject
-                .borrow_mut()
-                .set_rc_parent(&rc_gameobject);
+        if let Some(session) = &mut self.lockstep {
+            *session = LockstepSession::new(world.clone(), self.local, session.delay());
         }
-
-        let grab = Grab::new(ctx, image_pool);
-        {
-            grab.rc_gameobject
-                .borrow_mut()
-                .set_rc_parent(&rc_gameobject);
+        if let Some(session) = &mut self.authority {
+            *session = AuthoritySession::new(world.clone());
         }
-
-        let flash = Flash::new(ctx, image_pool);
-        Character {
-            default_image: load_image(ctx, String::from("/player.png"), image_pool),
-            motion_image: load_image(ctx, String::from("/player_grab.png"), image_pool),
-            rc_gameobject,
-            is_server,
-            move_state: 0.0,
-            move_speed: 300.0,
-            score: 0,
-            is_opponent: false,
-
-            target,
-            grab,
-            is_grabbed_by: false,
-            flash,
+        self.previous_world = world.clone();
+        self.world = world;
+        // A peer that was restarted has a new clock
+        self.last_recv = 0.0;
+        self.snapshots.clear();
+        if let Some(prediction) = &mut self.prediction {
+            prediction.clear();
         }
+        self.timestep.reset();
     }
 
-    fn rebirth(&mut self, randomize: bool) {
-        {
-            let mut gameobject = self.rc_gameobject.borrow_mut();
-            let mut rng = rand::thread_rng();
-            gameobject.transform.position = Point2 {
-                x: if randomize {
-                    rng.gen_range(-340.0..340.0)
-                } else {
-                    0.0
-                },
-                y: if self.is_server ^ self.is_opponent {
-                    -290.0
-                } else {
-                    290.0
-                },
-            };
-            gameobject.transform.rotation = if self.is_server ^ self.is_opponent {
-                PI / 2.0
-            } else {
-                -PI / 2.0
-            };
-            gameobject.update_global_transform();
+    // The host streams its character and its session steps the guest's from the guest's
+    // inputs, sending back where it ended up. The guest steps its own character right
+    // away and reconciles it with those corrections.
+    fn update_state_sync(&mut self, ctx: &mut Context) {
+        if self.is_server {
+            self.send_data(ctx);
+        }
+        self.recv_data(ctx);
+        let dt = ggez::timer::delta(ctx).as_secs_f32();
+        if let Some(prediction) = &mut self.prediction {
+            prediction.settle(dt);
+        }
+        if self.world.is_game_end() {
+            return;
         }
-        self.is_grabbed_by = false;
-    }
-
-    fn set_global_rotation(&self, rotation: f32) {
-        self.rc_gameobject.borrow_mut().global_transform.rotation = rotation;
-        self.rc_gameobject.borrow_mut().update_local_transform();
-    }
-
-    fn set_global_position(&self, position: Point2<f32>) {
-        self.rc_gameobject.borrow_mut().update_local_transform();
-        self.rc_gameobject.borrow_mut().global_transform.position = position;
-    }
-
-    fn get_global_position(&self) -> Point2<f32> {
-        self.rc_gameobject.borrow().global_transform.position
-    }
-}
-
-impl Communication for Character {
-    fn get_send_data(&self) -> Vec<u8> {
-        let mut data = vec![];
-        let ms = self.move_state.to_ne_bytes();
-        let sc = self.score.to_ne_bytes();
-        let go = self.rc_gameobject.borrow().get_send_data();
-
-        data.extend_from_slice(&ms);
-        data.extend_from_slice(&sc);
-        data.extend(go);
-
-        data.extend(self.target.get_send_data());
-        data.extend(self.grab.get_send_data());
-        data.extend(self.flash.get_send_data());
-
-        data
-    }
-
-    fn set_recv_data(&mut self, buf: &mut Vec<u8>) {
-        let (data, buf) = buf.split_at(8);
-        self.move_state = f32::from_ne_bytes(data[0..4].try_into().unwrap());
-        self.score = i32::from_ne_bytes(data[4..8].try_into().unwrap());
-
-        let (data, buf) = buf.split_at(20);
-        let mut data = Vec::from(data);
-        self.rc_gameobject.borrow_mut().set_recv_data(&mut data);
-
-        let (data, buf) = buf.split_at(4);
-        let mut data = Vec::from(data);
-        self.target.set_recv_data(&mut data);
-
-        let (data, buf) = buf.split_at(28);
-        let mut data = Vec::from(data);
-        self.grab.set_recv_data(&mut data);
-
-        let (data, buf) = buf.split_at(12);
-        let mut data = Vec::from(data);
-        self.flash.set_recv_data(&mut data);
-    }
-}
-
-impl EventHandler for Character {
-    fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
-        if !self.is_grabbed_by {
-            let delta = ggez::timer::delta(ctx);
-            let dt = delta.as_secs() as f32 + delta.subsec_nanos() as f32 * 1e-9;
-            let speed = dt * self.move_state * self.move_speed * (1.0 - self.grab.state.abs());
-            self.target.speed = 800.0 * (1.0 - self.grab.state.abs());
-            {
-                let mut gameobject = self.rc_gameobject.borrow_mut();
 
-                let delta_vec = gameobject.transform.right();
-                gameobject.transform.position.x += speed * delta_vec.x;
-                gameobject.transform.position.x =
-                    gameobject.transform.position.x.min(340.0).max(-340.0);
-                gameobject.update_global_transform();
+        let remote = self.local.opponent();
+        let steps = self.timestep.advance(dt);
+        for _ in 0..steps {
+            let tick = self.world.tick;
+            let input = self.keyboard.sample();
+            if let Some(session) = &mut self.authority {
+                let mut own = [None; 2];
+                own[self.local.index()] = Some(input);
+                for event in session.advance(own) {
+                    self.send(&Message::Event(event));
+                }
+                continue;
             }
 
-            if self.grab.check_grab_once {
-                self.score += 1;
-                self.grab.check_grab_once = false;
+            // The opponent goes on with the last input seen until its next state
+            self.previous_world = self.world.clone();
+            let mut inputs = [Input::NONE; 2];
+            inputs[self.local.index()] = input;
+            inputs[remote.index()] = self.world.character(remote).input;
+            self.world.step(TICK_DT, inputs);
+            if let Some(prediction) = &mut self.prediction {
+                prediction.record(tick, input);
+                self.send(&Message::Input { tick, input });
             }
-
-            self.target.update(ctx)?;
-            self.grab.update(ctx)?;
-        } else {
-            self.grab.state = 0.0;
-            self.move_state = 0.0;
         }
-        self.flash.update(ctx)?;
-
-        Ok(())
-    }
 
-    fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
-        if !self.is_grabbed_by {
-            self.target.draw(ctx)?;
-            self.grab.draw(ctx)?;
-            self.flash.draw(ctx)?;
-        }
-        {
-            let gameobject = self.rc_gameobject.borrow();
-            let (draw_image, rotation) = if self.grab.state == 0.0 {
-                (
-                    self.default_image.clone(),
-                    gameobject.global_transform.rotation - (2.0 - self.move_state) * PI / 4.0,
-                )
-            } else {
-                let target = self.target.rc_gameobject.borrow();
-                (
-                    self.motion_image.clone(),
-                    target.global_transform.rotation - PI / 2.0,
-                )
-            };
-            let draw_param = DrawParam::new()
-                .dest(gameobject.global_transform.position)
-                .rotation(rotation)
-                .offset(Point2 { x: 0.5, y: 0.5 });
-            draw(ctx, draw_image.as_ref(), draw_param)?;
+        if let Some(session) = &self.authority {
+            self.previous_world = session.previous_world().clone();
+            self.world = session.world().clone();
+            if let Some(correction) = session.correction(remote) {
+                self.send(&correction);
+            }
         }
-        Ok(())
     }
 
-    fn key_down_event(
-        &mut self,
-        ctx: &mut Context,
-        keycode: KeyCode,
-        keymods: KeyMods,
-        repeat: bool,
-    ) {
-        if keycode == KeyCode::Space && self.grab.state == 0.0 {
-            self.grab.state = 1.0;
+    // Sends only local inputs and lets the session predict and correct the opponent.
+    fn update_rollback(&mut self, ctx: &mut Context) {
+        self.recv_data(ctx);
 
-            self.grab.set_position(Transform::rotate_point(
-                Point2 { x: 35.0, y: 60.0 },
-                self.target.get_rotation(),
-            ));
-            self.grab.set_rotation(self.target.get_rotation());
+        let steps = self.timestep.advance(ggez::timer::delta(ctx).as_secs_f32());
+        if let Some(session) = &mut self.rollback {
+            session.roll_back();
         }
-
-        if ggez::input::keyboard::is_key_pressed(ctx, KeyCode::Left) {
-            self.move_state = -1.0
-        }
-        if ggez::input::keyboard::is_key_pressed(ctx, KeyCode::Right) {
-            self.move_state = 1.0
+        for _ in 0..steps {
+            let session = match &mut self.rollback {
+                Some(session) => session,
+                None => return,
+            };
+            // Waits for the opponent when too far ahead of its inputs
+            if session.world().is_game_end() || !session.can_advance() {
+                break;
+            }
+            let tick = session.world().tick;
+            let input = self.keyboard.sample();
+            session.advance(input);
+            self.send(&Message::Input { tick, input });
         }
 
-        if keycode == KeyCode::LShift {
-            self.flash.use_skill(&self.rc_gameobject, self.move_state);
+        if let Some(session) = &self.rollback {
+            self.previous_world = session.previous_world().clone();
+            self.world = session.world().clone();
         }
     }
 
-    fn key_up_event(&mut self, ctx: &mut Context, keycode: KeyCode, keymods: KeyMods) {
-        self.move_state = 0.0;
-        if is_key_pressed(ctx, KeyCode::Left) {
-            self.move_state = -1.0
-        }
-        if is_key_pressed(ctx, KeyCode::Right) {
-            self.move_state = 1.0
-        }
-    }
-}
+    // Sends local inputs ahead by the input delay and steps only once both are in.
+    fn update_lockstep(&mut self, ctx: &mut Context) {
+        self.recv_data(ctx);
 
-pub struct GameState {
-    background_image: Rc<Image>,
-    foreground_image: Rc<Image>,
-    rc_player: Rc<RefCell<Character>>,
-    rc_opponent: Rc<RefCell<Character>>,
-    rc_global: Rc<RefCell<GameObject>>,
-    stream: Option<Rc<RefCell<TcpStream>>>,
-    is_game_end: bool,
-    last_recv: f32,
-    time_start: f32,
-}
-
-impl GameState {
-    pub fn initialize(&mut self, is_server: bool, tcp_stream: &Option<Rc<RefCell<TcpStream>>>) {
-        if let Some(i) = tcp_stream {
-            self.stream = Some(Rc::clone(&i));
-        }
-
-        {
-            let mut global = self.rc_global.borrow_mut();
-            global.transform.position = Point2 { x: 640.0, y: 360.0 };
-            if is_server {
-                global.transform.rotation = PI;
+        let steps = self.timestep.advance(ggez::timer::delta(ctx).as_secs_f32());
+        for _ in 0..steps {
+            let session = match &mut self.lockstep {
+                Some(session) => session,
+                None => return,
+            };
+            if session.world().is_game_end() {
+                break;
             }
-            global.update_global_transform();
-        }
-
-        {
-            let mut player = self.rc_player.borrow_mut();
-            let mut opponent = self.rc_opponent.borrow_mut();
-
-            player.is_server = is_server;
-            opponent.is_server = is_server;
-
-            player.grab.set_rc_target(&self.rc_opponent);
-            opponent.grab.set_rc_target(&self.rc_player);
-            if is_server {
-                opponent.target.direction = -1.0;
-            } else {
-                player.target.direction = -1.0;
+            if let (None, Some(rtt)) = (self.input_delay, self.rtt) {
+                session.set_delay(delay_for_rtt(rtt));
             }
-            opponent.is_opponent = true;
-            {
-                let mut playerobject = player.rc_gameobject.borrow_mut();
-                playerobject.set_rc_parent(&self.rc_global);
-
-                let mut oppoentobject = opponent.rc_gameobject.borrow_mut();
-                oppoentobject.set_rc_parent(&self.rc_global);
+            if session.wants_local_input() {
+                let input = self.keyboard.sample();
+                for tick in session.add_local_input(input) {
+                    self.send(&Message::Input { tick, input });
+                }
             }
-            opponent.flash.is_player = false;
-            {
-                let mut playerflash = player.flash.rc_gameobject.borrow_mut();
-                playerflash.set_rc_parent(&self.rc_global);
-
-                let mut oppoentflash = opponent.flash.rc_gameobject.borrow_mut();
-                oppoentflash.set_rc_parent(&self.rc_global);
+            // Waits for the opponent's input for this tick
+            let stepped = self.lockstep.as_mut().and_then(LockstepSession::advance);
+            if stepped.is_none() {
+                break;
             }
-            player.rebirth(false);
-            opponent.rebirth(false);
-        }
-    }
-
-    // Game Setting
-    pub fn new(ctx: &mut Context, image_pool: &mut HashMap<String, Rc<Image>>) -> GameState {
-        let rc_global = Rc::new(RefCell::new(GameObject::new()));
-
-        let background_image = load_image(ctx, String::from("/background.png"), image_pool);
-        let foreground_image = load_image(ctx, String::from("/foreground.png"), image_pool);
-
-        let rc_player = Rc::new(RefCell::new(Character::new(ctx, image_pool, false)));
-        let rc_opponent = Rc::new(RefCell::new(Character::new(ctx, image_pool, false)));
-
-        GameState {
-            background_image,
-            foreground_image,
-            rc_player,
-            rc_opponent,
-            rc_global,
-            stream: None,
-            is_game_end: false,
-            last_recv: 0.0,
-            time_start: ggez::timer::time_since_start(ctx).as_secs_f32(),
         }
-    }
 
-    fn send_data(&mut self, ctx: &mut Context) {
-        let mut data = vec![];
-        let time_since_start = ggez::timer::time_since_start(ctx).as_secs_f32();
-        let sst = time_since_start.to_ne_bytes();
-        data.extend_from_slice(&sst);
-        data.extend(self.rc_player.borrow_mut().get_send_data()); // 24 bytes
-        if let Some(st) = &self.stream {
-            let mut _st = st.try_borrow_mut().unwrap();
-            _st.write(data.as_slice()).unwrap();
-            _st.flush().unwrap();
-        }
-    }
-
-    fn recv_data(&mut self) {
-        let mut buf = [0u8; 76];
-        if let Some(st) = &self.stream {
-            let mut _st = st.try_borrow_mut().unwrap();
-
-            match _st.read_exact(&mut buf) {
-                Ok(_) => {
-                    let (data, buf) = buf.split_at(4);
-                    let recv_time = f32::from_ne_bytes(data[0..4].try_into().unwrap());
-                    if recv_time > self.last_recv {
-                        self.rc_opponent
-                            .borrow_mut()
-                            .set_recv_data(&mut Vec::from(buf));
-                        self.last_recv = recv_time;
-                    }
-                }
-                Err(err) => {}
-            }
+        if let Some(session) = &self.lockstep {
+            self.previous_world = session.previous_world().clone();
+            self.world = session.world().clone();
         }
     }
 }
 
 impl IState for GameState {
-    fn update(&mut self, _ctx: &mut ggez::Context) -> EState {
-        self.send_data(_ctx);
-        self.recv_data();
-        if self.is_game_end {
-            return EState::None;
+    fn update(&mut self, ctx: &mut ggez::Context) -> EState {
+        self.silence += ggez::timer::delta(ctx).as_secs_f32();
+        if self.connection.is_some() && self.silence > self.heartbeat_timeout {
+            eprintln!(
+                "Connection lost: nothing heard for {} seconds",
+                self.heartbeat_timeout
+            );
+            self.connection = None;
+        }
+        if self.connection.is_none() {
+            return self.wait_for_opponent(ctx);
         }
 
-        self.rc_player
-            .borrow_mut()
-            .update(_ctx)
-            .expect("player update failed");
-        self.rc_opponent
-            .borrow_mut()
-            .update(_ctx)
-            .expect("opponent update failed");
-
-        self.is_game_end =
-            self.rc_player.borrow().score == 3 || self.rc_opponent.borrow().score == 3;
+        self.ping(ctx);
+        if self.awaiting_resume || self.awaiting_seat {
+            self.recv_data(ctx);
+        } else if self.rollback.is_some() {
+            self.update_rollback(ctx);
+        } else if self.lockstep.is_some() {
+            self.update_lockstep(ctx);
+        } else {
+            self.update_state_sync(ctx);
+        }
+        self.flush();
 
         EState::None
     }
 
     fn draw(&mut self, ctx: &mut ggez::Context) {
         clear(ctx, Color::WHITE);
-        draw(ctx, self.background_image.as_ref(), DrawParam::new()).expect("draw failed");
-        draw(ctx, self.foreground_image.as_ref(), DrawParam::new()).expect("draw failed");
+        let mut world = self.previous_world.lerp(&self.world, self.timestep.alpha());
+        // Only states streamed under the state sync netcode land here
+        let now = ggez::timer::time_since_start(ctx).as_secs_f32();
+        if let Some(seen) = self.snapshots.sample(now) {
+            show_as(world.character_mut(self.local.opponent()), &seen);
+        }
+        if let Some(prediction) = &self.prediction {
+            prediction.smooth(world.character_mut(self.local));
+        }
+        self.renderer
+            .draw(ctx, &world, self.local)
+            .expect("draw failed");
 
-        // Draw Score
-        let opponent_score_draw_params = DrawParam::new()
-            .dest(Point2 { x: 640.0, y: 220.0 })
+        let name_param = DrawParam::new()
+            .dest(Point2 { x: 640.0, y: 180.0 })
             .offset(Point2 { x: 0.5, y: 0.5 })
-            .scale([2.0, 2.0])
+            .scale([1.5, 1.5])
             .color(Color::WHITE);
-        draw(
-            ctx,
-            &Text::new(format!("{}", self.rc_opponent.borrow().score)),
-            opponent_score_draw_params,
-        )
-        .expect("draw failed");
+        draw(ctx, &Text::new(self.opponent_name.as_str()), name_param).expect("draw failed");
 
-        let player_score_draw_params = DrawParam::new()
-            .dest(Point2 { x: 640.0, y: 450.0 })
-            .offset(Point2 { x: 0.5, y: 0.5 })
-            .scale([3.0, 3.0])
-            .color(Color::WHITE);
-        draw(
-            ctx,
-            &Text::new(format!("{}", self.rc_player.borrow().score)),
-            player_score_draw_params,
-        )
-        .expect("draw failed");
+        if let Some(session) = &self.lockstep {
+            let rtt = match self.rtt {
+                Some(rtt) => format!("{} ms", rtt.as_millis()),
+                None => String::from("-"),
+            };
+            let param = DrawParam::new()
+                .dest(Point2 { x: 20.0, y: 20.0 })
+                .scale([1.2, 1.2])
+                .color(Color::BLACK);
+            let text = format!("Input delay {} ticks, RTT {}", session.delay(), rtt);
+            draw(ctx, &Text::new(text), param).expect("draw failed");
+        }
 
-        if self.is_game_end {
-            let game_end_draw_params = DrawParam::new()
-                .dest(Point2 { x: 640.0, y: 260.0 })
+        if self.awaiting_seat {
+            let param = DrawParam::new()
+                .dest(Point2 { x: 640.0, y: 320.0 })
                 .offset(Point2 { x: 0.5, y: 0.5 })
-                .scale([5.0, 5.0])
-                .color(Color::WHITE);
-            if self.rc_player.borrow().score == 3 {
-                draw(
-                    ctx,
-                    &Text::new("You Win!\nPress ESC to Exit"),
-                    game_end_draw_params,
-                )
-                .expect("draw failed");
-            } else {
-                draw(
-                    ctx,
-                    &Text::new("Opponent Win!\nPress ESC to Exit"),
-                    game_end_draw_params,
-                )
-                .expect("draw failed");
-            }
-        } else {
-            self.rc_player.borrow_mut().draw(ctx).expect("draw failed");
-            self.rc_opponent
-                .borrow_mut()
-                .draw(ctx)
-                .expect("draw failed");
+                .scale([2.0, 2.0])
+                .color(Color::BLACK);
+            draw(ctx, &Text::new("Waiting for another player"), param).expect("draw failed");
+        }
+
+        if let Some(countdown) = self.countdown {
+            let title_param = DrawParam::new()
+                .dest(Point2 { x: 640.0, y: 320.0 })
+                .offset(Point2 { x: 0.5, y: 0.5 })
+                .scale([3.0, 3.0])
+                .color(Color::BLACK);
+            draw(ctx, &Text::new("Opponent disconnected"), title_param).expect("draw failed");
+
+            let param = DrawParam::new()
+                .dest(Point2 { x: 640.0, y: 400.0 })
+                .offset(Point2 { x: 0.5, y: 0.5 })
+                .scale([1.5, 1.5])
+                .color(Color::BLACK);
+            let text = format!(
+                "Waiting for {} to reconnect... {}",
+                self.opponent_name,
+                countdown.ceil() as u32
+            );
+            draw(ctx, &Text::new(text), param).expect("draw failed");
         }
 
         // don't have to do this here. it makes flickering.
         // present(ctx).expect("draw failed");
     }
 
-    fn key_down_event(
-        &mut self,
-        ctx: &mut Context,
-        keycode: KeyCode,
-        keymods: KeyMods,
-        repeat: bool,
-    ) {
+    fn key_down_event(&mut self, ctx: &mut Context, keycode: KeyCode, _keymods: KeyMods, _repeat: bool) {
         if ggez::input::keyboard::is_key_pressed(ctx, KeyCode::Escape) {
             ggez::event::quit(ctx);
         }
-        self.rc_player
-            .borrow_mut()
-            .key_down_event(ctx, keycode, keymods, repeat);
+        self.keyboard.key_down(keycode);
     }
 
-    fn key_up_event(&mut self, ctx: &mut Context, keycode: KeyCode, keymods: KeyMods) {
-        self.rc_player
-            .borrow_mut()
-            .key_up_event(ctx, keycode, keymods);
+    fn key_up_event(&mut self, _ctx: &mut Context, keycode: KeyCode, _keymods: KeyMods) {
+        self.keyboard.key_up(keycode);
     }
 }
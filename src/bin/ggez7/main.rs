@@ -1,31 +1,19 @@
-use ggez::conf::{WindowMode, WindowSetup};
-use ggez::event::quit;
 use ggez::event::{run, EventHandler, KeyCode, KeyMods, MouseButton};
-use ggez::graphics::{clear, draw, present, Color, DrawParam, Image, Text};
-use ggez::input::gamepad::Event;
-use ggez::input::keyboard::is_key_pressed;
-use ggez::mint::Point2;
-use ggez::timer::delta;
-use ggez::{Context, ContextBuilder, GameResult, GameError};
-
-use std::cell::RefCell;
-use std::f32::consts::PI;
-use std::rc::Rc;
-
-use rand::rngs::ThreadRng;
-use rand::{thread_rng, Rng};
+use ggez::graphics::{clear, present, Color, Image};
+use ggez::{Context, GameResult};
 
 use std::collections::HashMap;
-use std::io::Read;
-use std::io::Write;
-use std::net::{TcpListener, TcpStream};
+use std::rc::Rc;
 
 mod helper;
-use helper::{EState, load_image, IState};
+use helper::{EState, IState};
 mod game_state;
 mod menu_state;
+mod rejoin;
+mod settings;
 use game_state::GameState;
 use menu_state::MenuState;
+use settings::Settings;
 
 struct Game {
     game_state: GameState, 
@@ -37,10 +25,11 @@ impl Game {
     fn new(
         ctx: &mut Context, 
         image_pool : &mut HashMap<String, Rc<Image>>,
+        settings: Settings,
     ) -> Game {
         Game {
-            menu_state : MenuState::new(ctx, image_pool), 
-            game_state : GameState::new(ctx, image_pool), 
+            menu_state : MenuState::new(ctx, image_pool, &settings), 
+            game_state : GameState::new(ctx, image_pool, &settings), 
             current_state : EState::Menu,
         }
     }
@@ -54,17 +43,21 @@ impl EventHandler for Game {
             EState::None => EState::None, 
         };
 
-        match ret {
-            EState::Game => {
-                self.current_state = EState::Game;
-                println!("IsServer: {}", self.menu_state.IsServer());
-                self.game_state.initialize(
-                    self.menu_state.IsServer(), 
-                    &self.menu_state.tcp_stream
-                );
-                println!("Game Started!");
-            },
-            (_) => {},
+        if let (EState::Game, Some(handshake)) = (&ret, &self.menu_state.handshake) {
+            self.current_state = EState::Game;
+            println!("IsServer: {}", self.menu_state.is_server());
+            self.game_state.initialize(
+                self.menu_state.is_server(), 
+                self.menu_state.connection.take(),
+                handshake,
+                self.menu_state.rejoin.take(),
+            );
+            println!("Game Started!");
+        }
+        if let (EState::Menu, Some(reason)) = (ret, self.game_state.end_reason.take()) {
+            self.current_state = EState::Menu;
+            println!("Game Ended: {}", reason);
+            self.menu_state.leave_game(reason);
         }
         Ok(())
     }
@@ -108,6 +101,14 @@ impl EventHandler for Game {
 }
 
 fn main() {
+    let settings = match Settings::from_args(std::env::args().skip(1)) {
+        Ok(settings) => settings,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
+
     let (mut ctx, event_loop) = match ggez::ContextBuilder::new("GGEZ", "GGEZ")
         .window_setup(ggez::conf::WindowSetup::default().title("GGEZ"))
         .window_mode(ggez::conf::WindowMode::default().dimensions(1280.0, 720.0))
@@ -119,6 +120,6 @@ fn main() {
 
     let mut image_pool: HashMap<String, Rc<Image>> = HashMap::new();
 
-    let ggez = Game::new(&mut ctx, &mut image_pool);
+    let ggez = Game::new(&mut ctx, &mut image_pool, settings);
     run(ctx, event_loop, ggez);
 }
\ No newline at end of file
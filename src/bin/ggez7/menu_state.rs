@@ -1,65 +1,179 @@
-use std::rc::Rc;
-use std::cell::RefCell;
 use std::collections::HashMap;
-use std::net::{TcpListener, TcpStream};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc;
 use std::sync::mpsc::{Receiver, SyncSender};
+use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant};
 
-use ggez::event::{EventHandler, KeyCode, KeyMods, Button, MouseButton};
-use ggez::{Context, GameError};
+use ggez::event::{KeyCode, KeyMods, MouseButton};
 use ggez::graphics::{draw, Color, DrawParam, Image, Text};
 use ggez::mint::Point2;
+use ggez::Context;
+
+use ggez_project::address::{guest_addresses, lan_address};
+use ggez_project::discovery::{Announcer, Browser};
+use ggez_project::lobby::{LobbyClient, RoomInfo};
+use ggez_project::protocol::ProtocolError;
+use ggez_project::relay::{join_room, parse_room_code, RelayError, RelayRoom};
+use ggez_project::{
+    guest_handshake, host_handshake, load_image, open_transport, Handshake, HandshakeError,
+    MatchRules, MatchSettings, Netcode, Transport, TransportKind, World,
+};
 
-use crate::helper::{load_image, IState, EState};
-use crate::game_state::GameState;
+use crate::helper::{EState, IState};
+use crate::rejoin::{wait_for_guest, wait_in_room, Rejoin};
+use crate::settings::Settings;
 
 enum EInnerState {
-    unkown,         // guest인지 host인지 선택하지 않은 상태
-    waiting_guest, // host로서 guest를 기다리는 상태
-    typing_host_ip, // guest로서 접속할 host의 ip를 입력하는 상태
+    Unknown,              // guest인지 host인지 선택하지 않은 상태
+    HostSetup,            // host로서 열 주소와 port를 고르는 상태
+    WaitingGuest,         // host로서 guest를 기다리는 상태
+    TypingHostIp,         // guest로서 접속할 host의 ip를 입력하는 상태
+    Incompatible(String), // 상대와 버전이 달라 게임을 시작할 수 없는 상태
+    Failed(String),       // 접속이나 handshake에 실패한 상태
+    HostFailed(String),   // 고른 주소에서 host를 열지 못한 상태
+    BrowsingLobby,        // lobby에서 들어갈 방을 고르는 상태
+    Disconnected(String), // 상대가 돌아오지 않아 게임이 끝난 상태
 }
 
 struct ButtonRect {
-    x : f32, 
-    y : f32, 
-    s_x : f32,
-    s_y : f32, 
+    x: f32,
+    y: f32,
+    s_x: f32,
+    s_y: f32,
 }
 
-impl ButtonRect {
-    fn isInIt(&mut self, x : f32, y: f32) -> bool {
-        if self.x - self.s_x / 2.0 <= x && x <= self.x + self.s_x / 2.0 {
-            if self.y - self.s_y / 2.0 <= y && y <= self.y + self.s_y / 2.0 {
-                return true;
-            }
-        }
+// How often the lobby's room list is fetched again while browsing it
+const LOBBY_REFRESH_INTERVAL: Duration = Duration::from_secs(2);
 
-        return false;
+// Character a key types into an address field.
+fn address_char(keycode: KeyCode) -> Option<char> {
+    match keycode {
+        KeyCode::Key0 | KeyCode::Numpad0 => Some('0'),
+        KeyCode::Key1 | KeyCode::Numpad1 => Some('1'),
+        KeyCode::Key2 | KeyCode::Numpad2 => Some('2'),
+        KeyCode::Key3 | KeyCode::Numpad3 => Some('3'),
+        KeyCode::Key4 | KeyCode::Numpad4 => Some('4'),
+        KeyCode::Key5 | KeyCode::Numpad5 => Some('5'),
+        KeyCode::Key6 | KeyCode::Numpad6 => Some('6'),
+        KeyCode::Key7 | KeyCode::Numpad7 => Some('7'),
+        KeyCode::Key8 | KeyCode::Numpad8 => Some('8'),
+        KeyCode::Key9 | KeyCode::Numpad9 => Some('9'),
+        KeyCode::Colon | KeyCode::Semicolon => Some(':'),
+        KeyCode::Period => Some('.'),
+        _ => None,
     }
 }
 
-pub struct MenuState {
-    state : EInnerState,
-    ip_str : String, 
-    host_button_image : Rc<Image>, 
-    guest_button_image : Rc<Image>, 
-    // cancel_button_image : Rc<Image>, 
+// Character a key types into a relay room code.
+fn room_code_char(keycode: KeyCode) -> Option<char> {
+    let c = match keycode {
+        KeyCode::A => 'A',
+        KeyCode::B => 'B',
+        KeyCode::C => 'C',
+        KeyCode::D => 'D',
+        KeyCode::E => 'E',
+        KeyCode::F => 'F',
+        KeyCode::G => 'G',
+        KeyCode::H => 'H',
+        KeyCode::J => 'J',
+        KeyCode::K => 'K',
+        KeyCode::L => 'L',
+        KeyCode::M => 'M',
+        KeyCode::N => 'N',
+        KeyCode::P => 'P',
+        KeyCode::Q => 'Q',
+        KeyCode::R => 'R',
+        KeyCode::S => 'S',
+        KeyCode::T => 'T',
+        KeyCode::U => 'U',
+        KeyCode::V => 'V',
+        KeyCode::W => 'W',
+        KeyCode::X => 'X',
+        KeyCode::Y => 'Y',
+        KeyCode::Z => 'Z',
+        _ => return None,
+    };
+    Some(c)
+}
 
-    host_button_rect : ButtonRect, 
-    guest_button_rect : ButtonRect, 
+// Shows a relay failure like any other failed handshake.
+fn relay_failure(err: RelayError) -> HandshakeError {
+    match err {
+        RelayError::Protocol(err) => HandshakeError::Protocol(err),
+        err => HandshakeError::Protocol(ProtocolError::Io(io::Error::other(err.to_string()))),
+    }
+}
 
-    should_end_state : bool,
+impl ButtonRect {
+    fn is_in_it(&self, x: f32, y: f32) -> bool {
+        self.x - self.s_x / 2.0 <= x
+            && x <= self.x + self.s_x / 2.0
+            && self.y - self.s_y / 2.0 <= y
+            && y <= self.y + self.s_y / 2.0
+    }
+}
+
+pub struct MenuState {
+    state: EInnerState,
+    ip_str: String,
+    host_button_image: Rc<Image>,
+    guest_button_image: Rc<Image>,
+    // cancel_button_image : Rc<Image>,
+    host_button_rect: ButtonRect,
+    guest_button_rect: ButtonRect,
 
-    sender : SyncSender<TcpStream>, 
-    receiver : Receiver<TcpStream>,
-    pub tcp_stream : Option<Rc<RefCell<TcpStream>>>,
+    should_end_state: bool,
+
+    // Player name announced in the handshake
+    name: String,
+    // Offered to the guest when hosting
+    netcode: Netcode,
+    transport: TransportKind,
+    // Address and port to host on, as typed on the host setup screen
+    bind_ip_str: String,
+    port_str: String,
+    editing_port: bool,
+    // Interfaces offered on the host setup screen, with what they are
+    bind_presets: Vec<(&'static str, IpAddr)>,
+    // What guests should type to reach this host
+    guest_addresses: Vec<SocketAddr>,
+    // Tells the local network about the game while waiting for a guest
+    announcer: Option<Announcer>,
+    // Games found on the local network while typing the host address
+    browser: Option<Browser>,
+    discovered: Vec<(String, SocketAddr)>,
+    lobby_button_rect: ButtonRect,
+    open_room_button_rect: ButtonRect,
+    lobby_addr: SocketAddr,
+    // Connected while browsing the lobby, and while a room opened there waits
+    lobby: Option<LobbyClient>,
+    lobby_rooms: Vec<RoomInfo>,
+    lobby_refreshed: Option<Instant>,
+    lobby_room: Option<u32>,
+    relay_addr: SocketAddr,
+    // Code of the room opened at the relay while waiting there
+    relay_code: Option<String>,
+    sender: SyncSender<Result<(TcpStream, Handshake), HandshakeError>>,
+    receiver: Receiver<Result<(TcpStream, Handshake), HandshakeError>>,
+    // Set to stop the thread waiting for a guest
+    stop_waiting: Arc<AtomicBool>,
+    pub connection: Option<Box<dyn Transport>>,
+    // Agreed with the opponent before the match starts
+    pub handshake: Option<Handshake>,
+    // Where to find the opponent again if the match loses it
+    pub rejoin: Option<Rejoin>,
 }
 
 impl MenuState {
     pub fn new(
-        ctx: &mut Context, 
+        ctx: &mut Context,
         image_pool: &mut HashMap<String, Rc<Image>>,
+        settings: &Settings,
     ) -> MenuState {
         let host_button_image = load_image(ctx, String::from("/host_button.png"), image_pool);
         let guest_button_image = load_image(ctx, String::from("/guest_button.png"), image_pool);
@@ -67,209 +181,854 @@ impl MenuState {
 
         let (sender, receiver) = mpsc::sync_channel(1);
 
-        MenuState { 
-            state: EInnerState::unkown, 
-            ip_str: String::new(), 
-            host_button_image: host_button_image, 
-            guest_button_image: guest_button_image, 
+        MenuState {
+            state: EInnerState::Unknown,
+            ip_str: String::new(),
+            host_button_image,
+            guest_button_image,
             // cancel_button_image: cancel_button_image,
-            host_button_rect : ButtonRect { x: 640.0, y: 320.0, s_x: 195.0, s_y: 49.0 }, 
-            guest_button_rect : ButtonRect { x: 640.0, y: 395.0, s_x: 207.0, s_y: 49.0 }, 
-            should_end_state: false, 
-            tcp_stream : None,
-            sender: sender, 
-            receiver: receiver, 
+            host_button_rect: ButtonRect {
+                x: 640.0,
+                y: 320.0,
+                s_x: 195.0,
+                s_y: 49.0,
+            },
+            guest_button_rect: ButtonRect {
+                x: 640.0,
+                y: 395.0,
+                s_x: 207.0,
+                s_y: 49.0,
+            },
+            should_end_state: false,
+            name: settings.name.clone(),
+            netcode: settings.netcode,
+            transport: settings.transport,
+            bind_ip_str: settings.bind_ip.to_string(),
+            port_str: settings.port.to_string(),
+            editing_port: false,
+            bind_presets: Vec::new(),
+            guest_addresses: Vec::new(),
+            announcer: None,
+            browser: None,
+            discovered: Vec::new(),
+            lobby_button_rect: ButtonRect {
+                x: 640.0,
+                y: 470.0,
+                s_x: 207.0,
+                s_y: 49.0,
+            },
+            open_room_button_rect: ButtonRect {
+                x: 640.0,
+                y: 290.0,
+                s_x: 260.0,
+                s_y: 40.0,
+            },
+            lobby_addr: settings.lobby,
+            lobby: None,
+            lobby_rooms: Vec::new(),
+            lobby_refreshed: None,
+            lobby_room: None,
+            relay_addr: settings.relay,
+            relay_code: None,
+            connection: None,
+            handshake: None,
+            rejoin: None,
+            sender,
+            receiver,
+            stop_waiting: Arc::new(AtomicBool::new(false)),
         }
     }
 
-    pub fn IsServer(&mut self) -> bool {
-        match self.state {
-            EInnerState::waiting_guest  => {
-                return true;
-            },
+    pub fn is_server(&self) -> bool {
+        matches!(self.state, EInnerState::WaitingGuest)
+    }
+
+    /// Comes back from a match that ended because the opponent left.
+    pub fn leave_game(&mut self, reason: String) {
+        self.should_end_state = false;
+        self.handshake = None;
+        self.state = EInnerState::Disconnected(reason);
+    }
+
+    fn go_back(&mut self) {
+        self.state = EInnerState::Unknown;
+        // Frees the host port for the next attempt, once the waiting thread lets go of it
+        self.stop_waiting.store(true, Ordering::Relaxed);
+        self.rejoin = None;
+        // A guest that got through meanwhile is dropped with the receiver
+        let (sender, receiver) = mpsc::sync_channel(1);
+        self.sender = sender;
+        self.receiver = receiver;
+        self.announcer = None;
+        self.browser = None;
+        self.lobby = None;
+        self.lobby_room = None;
+        self.relay_code = None;
+    }
+
+    // Where the discovered game at `idx` is drawn and clicked.
+    fn discovered_rect(idx: usize) -> ButtonRect {
+        ButtonRect {
+            x: 640.0,
+            y: 460.0 + 40.0 * idx as f32,
+            s_x: 640.0,
+            s_y: 36.0,
+        }
+    }
+
+    // Where the lobby room at `idx` is drawn and clicked.
+    fn lobby_room_rect(idx: usize) -> ButtonRect {
+        ButtonRect {
+            x: 640.0,
+            y: 400.0 + 40.0 * idx as f32,
+            s_x: 640.0,
+            s_y: 36.0,
+        }
+    }
+
+    fn open_lobby(&mut self) {
+        println!("lobby {} connect...", self.lobby_addr);
+        match LobbyClient::connect(self.lobby_addr, &self.name) {
+            Ok(lobby) => {
+                self.lobby = Some(lobby);
+                self.lobby_rooms.clear();
+                self.state = EInnerState::BrowsingLobby;
+                self.refresh_lobby();
+            }
+            Err(err) => {
+                println!("Lobby connection failed: {}", err);
+                self.state = EInnerState::Failed(format!("lobby at {}: {}", self.lobby_addr, err));
+            }
+        }
+    }
+
+    fn refresh_lobby(&mut self) {
+        let Some(lobby) = &mut self.lobby else {
+            return;
+        };
+        self.lobby_refreshed = Some(Instant::now());
+        match lobby.rooms() {
+            Ok(rooms) => self.lobby_rooms = rooms,
+            Err(err) => self.leave_lobby(err.to_string()),
+        }
+    }
+
+    fn leave_lobby(&mut self, reason: String) {
+        println!("Lobby failed: {}", reason);
+        self.lobby = None;
+        self.lobby_room = None;
+        self.rejoin = None;
+        self.announcer = None;
+        self.state = EInnerState::Failed(format!("lobby at {}: {}", self.lobby_addr, reason));
+    }
+
+    // Hosts a game where the lobby can see this side, and lists it there.
+    fn open_lobby_room(&mut self) {
+        let ip = match self.lobby.as_ref().map(LobbyClient::local_addr) {
+            Some(Ok(addr)) => addr.ip(),
+            _ => return,
+        };
+        let Some(bound) = self.host_on(SocketAddr::new(ip, 0)) else {
+            return;
+        };
+        if let Some(lobby) = &mut self.lobby {
+            match lobby.create_room(bound.port()) {
+                Ok(id) => self.lobby_room = Some(id),
+                Err(err) => self.leave_lobby(err.to_string()),
+            }
+        }
+    }
+
+    fn join_lobby_room(&mut self, id: u32) {
+        let Some(lobby) = &mut self.lobby else {
+            return;
+        };
+        match lobby.join_room(id) {
+            Ok(addr) => {
+                self.lobby = None;
+                self.ip_str = addr.to_string();
+                self.join();
+            }
+            Err(err) => self.leave_lobby(err.to_string()),
+        }
+    }
+
+    fn join_through_relay(&mut self, code: String) {
+        println!("joining room {} at relay {}...", code, self.relay_addr);
+        let mut stream = match join_room(self.relay_addr, &code) {
+            Ok(stream) => stream,
+            Err(err) => {
+                println!("Joining room {} failed: {}", code, err);
+                self.state = EInnerState::Failed(format!("room {}: {}", code, err));
+                return;
+            }
+        };
+        let result = guest_handshake(&mut stream, &self.name);
+        self.rejoin = Some(Rejoin::RelayGuest {
+            relay: self.relay_addr,
+            code,
+        });
+        self.finish_handshake(result.map(|handshake| (stream, handshake)));
+    }
+
+    // Opens a room at the relay and waits there for a guest to join with its code.
+    fn host_through_relay(&mut self) {
+        let room = match RelayRoom::open(self.relay_addr, None) {
+            Ok(room) => room,
+            Err(err) => {
+                println!("Opening a room at {} failed: {}", self.relay_addr, err);
+                self.state =
+                    EInnerState::HostFailed(format!("relay at {}: {}", self.relay_addr, err));
+                return;
+            }
+        };
+        let code = String::from(room.code());
+        println!("Room {} open at relay {}", code, self.relay_addr);
+        self.guest_addresses.clear();
+        self.relay_code = Some(code.clone());
+        self.state = EInnerState::WaitingGuest;
+        self.rejoin = Some(Rejoin::RelayHost {
+            relay: self.relay_addr,
+            code,
+        });
+
+        self.stop_waiting = Arc::new(AtomicBool::new(false));
+        let stop = self.stop_waiting.clone();
+        let sender2 = self.sender.clone();
+        let name = self.name.clone();
+        let netcode = self.netcode;
+        thread::spawn(move || {
+            let result = match wait_in_room(room, &stop) {
+                Ok(Some(mut stream)) => {
+                    let settings = MatchSettings {
+                        seed: World::random_seed(),
+                        rules: MatchRules::default(),
+                        netcode,
+                        // Relays forward a TCP stream only
+                        transport: TransportKind::Tcp,
+                        token: rand::random(),
+                    };
+                    host_handshake(&mut stream, &name, settings)
+                        .map(|handshake| (stream, handshake))
+                }
+                Ok(None) => return,
+                Err(err) => Err(relay_failure(err)),
+            };
+            // Fails once the menu went back, which dropped the guest
+            let _ = sender2.send(result);
+        });
+    }
+
+    fn open_guest_setup(&mut self) {
+        self.state = EInnerState::TypingHostIp;
+        self.discovered.clear();
+        self.browser = match Browser::new() {
+            Ok(browser) => Some(browser),
+            Err(err) => {
+                println!("Cannot look for games on the local network: {}", err);
+                None
+            }
+        };
+    }
+
+    // Connects to the host typed or picked on the guest screen, or joins the relay room
+    // typed there.
+    fn join(&mut self) {
+        self.browser = None;
+        let is_address = self.ip_str.contains(['.', ':']);
+        if let Some(code) = parse_room_code(&self.ip_str).filter(|_| !is_address) {
+            self.join_through_relay(code);
+            return;
+        }
+        println!("connecting as guest... ");
+        println!("TCP {} connect...", self.ip_str);
+        let mut stream = match TcpStream::connect(self.ip_str.clone()) {
+            Ok(stream) => stream,
+            Err(err) => {
+                println!("Connection failed: {}", err);
+                self.state = EInnerState::Failed(format!("{}: {}", self.ip_str, err));
+                return;
+            }
+        };
+        if let Ok(opponent_ip_address) = stream.peer_addr() {
+            println!("Connected to opponent: {}", opponent_ip_address);
+            self.rejoin = Some(Rejoin::Dial(opponent_ip_address));
+        }
+        let result = guest_handshake(&mut stream, &self.name);
+        self.finish_handshake(result.map(|handshake| (stream, handshake)));
+    }
+
+    fn open_host_setup(&mut self) {
+        self.state = EInnerState::HostSetup;
+        self.editing_port = false;
+        self.bind_presets = vec![
+            ("this machine only", Ipv4Addr::LOCALHOST.into()),
+            ("all interfaces", Ipv4Addr::UNSPECIFIED.into()),
+        ];
+        if let Some(ip) = lan_address() {
+            self.bind_presets.push(("local network", ip));
+        }
+    }
+
+    // Moves the address field `step` presets along.
+    fn cycle_bind_preset(&mut self, step: isize) {
+        let len = self.bind_presets.len() as isize;
+        let current = self
+            .bind_presets
+            .iter()
+            .position(|(_, ip)| ip.to_string() == self.bind_ip_str);
+        // A typed address is left for the first preset going down, the last going up
+        let next = match current {
+            Some(idx) => (idx as isize + step).rem_euclid(len),
+            None if step > 0 => 0,
+            None => len - 1,
+        } as usize;
+        self.bind_ip_str = self.bind_presets[next].1.to_string();
+    }
+
+    // Listens on the address from the host setup screen.
+    fn start_hosting(&mut self) {
+        let addr = match (
+            self.bind_ip_str.parse::<IpAddr>(),
+            self.port_str.parse::<u16>(),
+        ) {
+            (Ok(ip), Ok(port)) => SocketAddr::new(ip, port),
             _ => {
-                return false;
+                self.state = EInnerState::HostFailed(format!(
+                    "{}:{} is not an address to host on",
+                    self.bind_ip_str, self.port_str
+                ));
+                return;
+            }
+        };
+        self.host_on(addr);
+    }
+
+    // Waits for a guest on `addr`, and returns where it listens. Shows why not otherwise.
+    fn host_on(&mut self, addr: SocketAddr) -> Option<SocketAddr> {
+        let tcp_listener = match TcpListener::bind(addr) {
+            Ok(listener) => Arc::new(listener),
+            Err(err) => {
+                println!("Bind to {} failed: {}", addr, err);
+                self.state = EInnerState::HostFailed(format!("{}: {}", addr, err));
+                return None;
+            }
+        };
+        let bound = tcp_listener.local_addr().unwrap_or(addr);
+        self.guest_addresses = guest_addresses(bound);
+        self.relay_code = None;
+        self.state = EInnerState::WaitingGuest;
+        self.rejoin = Some(Rejoin::Listen(tcp_listener.clone()));
+        self.announcer = match Announcer::new(&self.name, bound) {
+            Ok(announcer) => Some(announcer),
+            Err(err) => {
+                println!("Cannot announce the game: {}", err);
+                None
             }
+        };
+
+        self.stop_waiting = Arc::new(AtomicBool::new(false));
+        let stop = self.stop_waiting.clone();
+        let sender2 = self.sender.clone();
+        let name = self.name.clone();
+        let netcode = self.netcode;
+        let transport = self.transport;
+        thread::spawn(move || {
+            println!("host waiting guest... ");
+            println!("TCP {} listen... ", bound);
+            let result = match wait_for_guest(&tcp_listener, &stop) {
+                Ok(Some(mut stream)) => {
+                    if let Ok(opponent_ip_address) = stream.peer_addr() {
+                        println!("Opponent connected: {}", opponent_ip_address);
+                    }
+                    let settings = MatchSettings {
+                        seed: World::random_seed(),
+                        rules: MatchRules::default(),
+                        netcode,
+                        transport,
+                        token: rand::random(),
+                    };
+                    host_handshake(&mut stream, &name, settings)
+                        .map(|handshake| (stream, handshake))
+                }
+                Ok(None) => return,
+                Err(err) => Err(err.into()),
+            };
+            // Fails once the menu went back, which dropped the guest
+            let _ = sender2.send(result);
+        });
+        Some(bound)
+    }
+
+    // Starts the match once the handshake went through, or shows why it did not.
+    fn finish_handshake(&mut self, result: Result<(TcpStream, Handshake), HandshakeError>) {
+        self.announcer = None;
+        self.lobby = None;
+        self.lobby_room = None;
+        let (stream, handshake) = match result {
+            Ok(res) => res,
+            Err(err) => {
+                println!("Handshake failed: {}", err);
+                self.state = match err {
+                    HandshakeError::Incompatible { .. } => {
+                        EInnerState::Incompatible(err.to_string())
+                    }
+                    _ => EInnerState::Failed(err.to_string()),
+                };
+                return;
+            }
+        };
+        match open_transport(stream, handshake.settings.transport) {
+            Ok(connection) => {
+                println!("Playing against {}", handshake.peer.name);
+                self.connection = Some(connection);
+                self.handshake = Some(handshake);
+                self.should_end_state = true;
+            }
+            Err(err) => self.state = EInnerState::Failed(err.to_string()),
         }
     }
+
+    fn draw_message(ctx: &mut Context, title: &str, detail: &str) {
+        let title_param = DrawParam::new()
+            .dest(Point2 { x: 640.0, y: 260.0 })
+            .offset(Point2 { x: 0.5, y: 0.5 })
+            .scale([3.0, 3.0])
+            .color(Color::WHITE);
+        draw(ctx, &Text::new(title), title_param).expect("draw failed");
+
+        let detail_param = DrawParam::new()
+            .dest(Point2 { x: 640.0, y: 340.0 })
+            .offset(Point2 { x: 0.5, y: 0.5 })
+            .scale([1.5, 1.5])
+            .color(Color::WHITE);
+        draw(ctx, &Text::new(detail), detail_param).expect("draw failed");
+
+        let hint_param = DrawParam::new()
+            .dest(Point2 { x: 640.0, y: 420.0 })
+            .offset(Point2 { x: 0.5, y: 0.5 })
+            .scale([1.5, 1.5])
+            .color(Color::WHITE);
+        draw(ctx, &Text::new("Click or press Enter to go back"), hint_param)
+            .expect("draw failed");
+    }
 }
 
 impl IState for MenuState {
     fn update(&mut self, _ctx: &mut ggez::Context) -> EState {
         if self.should_end_state {
-            return EState::Game
+            return EState::Game;
         }
 
         match self.state {
-            EInnerState::unkown => {},
-            EInnerState::waiting_guest => {
-                let res = self.receiver.try_recv();
-                if res.is_ok() {
-                    self.tcp_stream = Some(Rc::new(RefCell::new(res.unwrap())));
-                    self.should_end_state = true;
+            EInnerState::Unknown => {}
+            EInnerState::HostSetup => {}
+            EInnerState::WaitingGuest => {
+                if let Some(announcer) = &mut self.announcer {
+                    if let Err(err) = announcer.poll() {
+                        println!("Announce failed: {}", err);
+                        self.announcer = None;
+                    }
                 }
-            },
-            EInnerState::typing_host_ip => {},
+                if let Ok(result) = self.receiver.try_recv() {
+                    self.finish_handshake(result);
+                }
+            }
+            EInnerState::TypingHostIp => {
+                if let Some(browser) = &mut self.browser {
+                    match browser.poll() {
+                        Ok(games) => {
+                            self.discovered = games
+                                .iter()
+                                .map(|game| {
+                                    let label = if game.is_compatible() {
+                                        format!("{}  {}", game.hello.name, game.addr)
+                                    } else {
+                                        format!(
+                                            "{}  {}  (version {})",
+                                            game.hello.name, game.addr, game.hello.game_version
+                                        )
+                                    };
+                                    (label, game.addr)
+                                })
+                                .collect();
+                        }
+                        Err(err) => {
+                            println!("Looking for games failed: {}", err);
+                            self.browser = None;
+                        }
+                    }
+                }
+            }
+            EInnerState::Incompatible(_) => {}
+            EInnerState::Failed(_) => {}
+            EInnerState::HostFailed(_) => {}
+            EInnerState::BrowsingLobby => {
+                let due = self
+                    .lobby_refreshed
+                    .is_none_or(|refreshed| refreshed.elapsed() >= LOBBY_REFRESH_INTERVAL);
+                if due {
+                    self.refresh_lobby();
+                }
+            }
+            EInnerState::Disconnected(_) => {}
         }
 
         EState::None
     }
 
-    fn draw(&mut self, ctx: &mut ggez::Context){
+    fn draw(&mut self, ctx: &mut ggez::Context) {
         match self.state {
-            EInnerState::unkown => {
+            EInnerState::Unknown => {
                 let host_button_param = DrawParam::new()
-                    .dest(Point2{ x: self.host_button_rect.x, y: self.host_button_rect.y })
-                    .offset(Point2{ x: 0.5, y: 0.5});
-                draw(ctx, self.host_button_image.as_ref(), host_button_param).expect("draw failed");
+                    .dest(Point2 {
+                        x: self.host_button_rect.x,
+                        y: self.host_button_rect.y,
+                    })
+                    .offset(Point2 { x: 0.5, y: 0.5 });
+                draw(ctx, self.host_button_image.as_ref(), host_button_param)
+                    .expect("draw failed");
 
                 let guest_button_param = DrawParam::new()
-                    .dest(Point2{ x: self.guest_button_rect.x, y: self.guest_button_rect.y })
-                    .offset(Point2{ x: 0.5, y: 0.5});
-                draw(ctx, self.guest_button_image.as_ref(), guest_button_param).expect("draw failed");
+                    .dest(Point2 {
+                        x: self.guest_button_rect.x,
+                        y: self.guest_button_rect.y,
+                    })
+                    .offset(Point2 { x: 0.5, y: 0.5 });
+                draw(ctx, self.guest_button_image.as_ref(), guest_button_param)
+                    .expect("draw failed");
+
+                let lobby_button_param = DrawParam::new()
+                    .dest(Point2 {
+                        x: self.lobby_button_rect.x,
+                        y: self.lobby_button_rect.y,
+                    })
+                    .offset(Point2 { x: 0.5, y: 0.5 })
+                    .scale([2.0, 2.0])
+                    .color(Color::WHITE);
+                draw(ctx, &Text::new("Browse lobby"), lobby_button_param).expect("draw failed");
 
                 let param1 = DrawParam::new()
-                .dest(Point2 { x: 600.0, y: 200.0 })
-                .offset(Point2 { x: 0.5, y: 0.5 })
-                .scale([3.0, 3.0])
-                .color(Color::WHITE);
-                draw(
-                    ctx,
-                    &Text::new(String::from("GGEZ")),
-                    param1,
-                ).expect("draw failed");
-            },
-            EInnerState::waiting_guest => {
+                    .dest(Point2 { x: 600.0, y: 200.0 })
+                    .offset(Point2 { x: 0.5, y: 0.5 })
+                    .scale([3.0, 3.0])
+                    .color(Color::WHITE);
+                draw(ctx, &Text::new(String::from("GGEZ")), param1).expect("draw failed");
+            }
+            EInnerState::HostSetup => {
+                let title_param = DrawParam::new()
+                    .dest(Point2 { x: 640.0, y: 200.0 })
+                    .offset(Point2 { x: 0.5, y: 0.5 })
+                    .scale([3.0, 3.0])
+                    .color(Color::WHITE);
+                draw(ctx, &Text::new("Host a game"), title_param).expect("draw failed");
+
+                let marker = |active: bool| if active { "> " } else { "  " };
+                let preset = self
+                    .bind_presets
+                    .iter()
+                    .find(|(_, ip)| ip.to_string() == self.bind_ip_str)
+                    .map_or(String::new(), |(label, _)| format!(" ({})", label));
+                let lines = [
+                    format!(
+                        "{}Address: {}{}",
+                        marker(!self.editing_port),
+                        self.bind_ip_str,
+                        preset
+                    ),
+                    format!("{}Port: {}", marker(self.editing_port), self.port_str),
+                ];
+                for (idx, line) in lines.into_iter().enumerate() {
+                    let param = DrawParam::new()
+                        .dest(Point2 {
+                            x: 640.0,
+                            y: 300.0 + 60.0 * idx as f32,
+                        })
+                        .offset(Point2 { x: 0.5, y: 0.5 })
+                        .scale([2.0, 2.0])
+                        .color(Color::WHITE);
+                    draw(ctx, &Text::new(line), param).expect("draw failed");
+                }
+
+                let hint_param = DrawParam::new()
+                    .dest(Point2 { x: 640.0, y: 460.0 })
+                    .offset(Point2 { x: 0.5, y: 0.5 })
+                    .scale([1.2, 1.2])
+                    .color(Color::WHITE);
+                let hint = "Up/Down picks an interface, Tab switches field, Enter hosts, \
+                            R hosts through the relay, Esc goes back";
+                draw(ctx, &Text::new(hint), hint_param).expect("draw failed");
+            }
+            EInnerState::WaitingGuest => {
                 let param = DrawParam::new()
                     .dest(Point2 { x: 560.0, y: 220.0 })
                     .offset(Point2 { x: 0.5, y: 0.5 })
                     .scale([2.0, 2.0])
                     .color(Color::WHITE);
-                draw(
-                    ctx,
-                    &Text::new(format!("waiting geuest...")),
-                    param,
-                ).expect("draw failed");
-            },
-            EInnerState::typing_host_ip => {
+                draw(ctx, &Text::new("waiting geuest..."), param).expect("draw failed");
+
+                let mut lines = match self.relay_code {
+                    Some(ref code) => vec![
+                        format!("Room code: {}", code),
+                        format!(
+                            "Guests type it to join through the relay at {}",
+                            self.relay_addr
+                        ),
+                    ],
+                    None => vec![String::from("Guests can connect to")],
+                };
+                lines.extend(self.guest_addresses.iter().map(|addr| addr.to_string()));
+                if let Some(id) = self.lobby_room {
+                    lines.push(format!("or join room {} at the lobby", id));
+                }
+                lines.push(String::from("Esc goes back"));
+                for (idx, line) in lines.into_iter().enumerate() {
+                    let param = DrawParam::new()
+                        .dest(Point2 {
+                            x: 640.0,
+                            y: 320.0 + 40.0 * idx as f32,
+                        })
+                        .offset(Point2 { x: 0.5, y: 0.5 })
+                        .scale([1.5, 1.5])
+                        .color(Color::WHITE);
+                    draw(ctx, &Text::new(line), param).expect("draw failed");
+                }
+            }
+            EInnerState::TypingHostIp => {
                 let param1 = DrawParam::new()
-                .dest(Point2 { x: 140.0, y: 270.0 })
-                .offset(Point2 { x: 0.5, y: 0.5 })
-                .scale([2.0, 2.0])
-                .color(Color::WHITE);
+                    .dest(Point2 { x: 140.0, y: 270.0 })
+                    .offset(Point2 { x: 0.5, y: 0.5 })
+                    .scale([2.0, 2.0])
+                    .color(Color::WHITE);
                 draw(
                     ctx,
-                    &Text::new(String::from("Type Host Socket Addr and Press Enter! (??.??.??.??:9999)")),
+                    &Text::new(String::from(
+                        "Type Host Socket Addr and Press Enter! (??.??.??.??:9999)",
+                    )),
                     param1,
-                ).expect("draw failed");
-            
+                )
+                .expect("draw failed");
+
                 let param2 = DrawParam::new()
-                .dest(Point2 { x: 540.0, y: 320.0 })
-                .offset(Point2 { x: 0.5, y: 0.5 })
-                .scale([2.0, 2.0])
-                .color(Color::WHITE);
-            draw(
-                ctx,
-                &Text::new(self.ip_str.clone()),
-                param2,
-            ).expect("draw failed");
-            },
+                    .dest(Point2 { x: 540.0, y: 320.0 })
+                    .offset(Point2 { x: 0.5, y: 0.5 })
+                    .scale([2.0, 2.0])
+                    .color(Color::WHITE);
+                draw(ctx, &Text::new(self.ip_str.clone()), param2).expect("draw failed");
+
+                let relay_param = DrawParam::new()
+                    .dest(Point2 { x: 640.0, y: 365.0 })
+                    .offset(Point2 { x: 0.5, y: 0.5 })
+                    .scale([1.2, 1.2])
+                    .color(Color::WHITE);
+                let relay_hint = format!(
+                    "or a room code to join through the relay at {}",
+                    self.relay_addr
+                );
+                draw(ctx, &Text::new(relay_hint), relay_param).expect("draw failed");
+
+                if self.browser.is_some() {
+                    let title = if self.discovered.is_empty() {
+                        "Looking for games on the local network..."
+                    } else {
+                        "Games on the local network (click to join)"
+                    };
+                    let title_param = DrawParam::new()
+                        .dest(Point2 { x: 640.0, y: 410.0 })
+                        .offset(Point2 { x: 0.5, y: 0.5 })
+                        .scale([1.5, 1.5])
+                        .color(Color::WHITE);
+                    draw(ctx, &Text::new(title), title_param).expect("draw failed");
+                }
+                for (idx, (label, _)) in self.discovered.iter().enumerate() {
+                    let rect = MenuState::discovered_rect(idx);
+                    let param = DrawParam::new()
+                        .dest(Point2 {
+                            x: rect.x,
+                            y: rect.y,
+                        })
+                        .offset(Point2 { x: 0.5, y: 0.5 })
+                        .scale([1.5, 1.5])
+                        .color(Color::WHITE);
+                    draw(ctx, &Text::new(label.as_str()), param).expect("draw failed");
+                }
+            }
+            EInnerState::Incompatible(ref reason) => {
+                MenuState::draw_message(ctx, "Incompatible version", reason);
+            }
+            EInnerState::Failed(ref reason) => {
+                MenuState::draw_message(ctx, "Connection failed", reason);
+            }
+            EInnerState::HostFailed(ref reason) => {
+                MenuState::draw_message(ctx, "Cannot host", reason);
+            }
+            EInnerState::BrowsingLobby => {
+                let title_param = DrawParam::new()
+                    .dest(Point2 { x: 640.0, y: 200.0 })
+                    .offset(Point2 { x: 0.5, y: 0.5 })
+                    .scale([3.0, 3.0])
+                    .color(Color::WHITE);
+                let title = format!("Lobby at {}", self.lobby_addr);
+                draw(ctx, &Text::new(title), title_param).expect("draw failed");
+
+                let open_room_param = DrawParam::new()
+                    .dest(Point2 {
+                        x: self.open_room_button_rect.x,
+                        y: self.open_room_button_rect.y,
+                    })
+                    .offset(Point2 { x: 0.5, y: 0.5 })
+                    .scale([2.0, 2.0])
+                    .color(Color::WHITE);
+                draw(ctx, &Text::new("Open a room"), open_room_param).expect("draw failed");
+
+                let list_title = if self.lobby_rooms.is_empty() {
+                    "No open rooms yet"
+                } else {
+                    "Open rooms (click to join)"
+                };
+                let list_title_param = DrawParam::new()
+                    .dest(Point2 { x: 640.0, y: 350.0 })
+                    .offset(Point2 { x: 0.5, y: 0.5 })
+                    .scale([1.5, 1.5])
+                    .color(Color::WHITE);
+                draw(ctx, &Text::new(list_title), list_title_param).expect("draw failed");
+                for (idx, room) in self.lobby_rooms.iter().enumerate() {
+                    let rect = MenuState::lobby_room_rect(idx);
+                    let param = DrawParam::new()
+                        .dest(Point2 {
+                            x: rect.x,
+                            y: rect.y,
+                        })
+                        .offset(Point2 { x: 0.5, y: 0.5 })
+                        .scale([1.5, 1.5])
+                        .color(Color::WHITE);
+                    let label = format!("Room {}  {}", room.id, room.host);
+                    draw(ctx, &Text::new(label), param).expect("draw failed");
+                }
+
+                let hint_param = DrawParam::new()
+                    .dest(Point2 { x: 640.0, y: 680.0 })
+                    .offset(Point2 { x: 0.5, y: 0.5 })
+                    .scale([1.2, 1.2])
+                    .color(Color::WHITE);
+                draw(ctx, &Text::new("R refreshes, Esc goes back"), hint_param)
+                    .expect("draw failed");
+            }
+            EInnerState::Disconnected(ref reason) => {
+                MenuState::draw_message(ctx, "Opponent disconnected", reason);
+            }
         }
     }
 
-    fn key_down_event(&mut self, _: &mut Context, keycode: KeyCode, keymods: KeyMods, repeat: bool) {
-        match self.state {
-            EInnerState::typing_host_ip => {
-                match keycode {
-                    KeyCode::Key0 | KeyCode::Numpad0 => {
-                        self.ip_str.push('0');
-                    },
-                    KeyCode::Key1 | KeyCode::Numpad1 => {
-                        self.ip_str.push('1');
-                    },
-                    KeyCode::Key2 | KeyCode::Numpad2 => {
-                        self.ip_str.push('2');
-                    },
-                    KeyCode::Key3 | KeyCode::Numpad3 => {
-                        self.ip_str.push('3');
-                    },
-                    KeyCode::Key4 | KeyCode::Numpad4 => {
-                        self.ip_str.push('4');
-                    },
-                    KeyCode::Key5 | KeyCode::Numpad5 => {
-                        self.ip_str.push('5');
-                    },
-                    KeyCode::Key6 | KeyCode::Numpad6 => {
-                        self.ip_str.push('6');
-                    },
-                    KeyCode::Key7 | KeyCode::Numpad7 => {
-                        self.ip_str.push('7');
-                    },
-                    KeyCode::Key8 | KeyCode::Numpad8 => {
-                        self.ip_str.push('8');
-                    },
-                    KeyCode::Key9 | KeyCode::Numpad9 => {
-                        self.ip_str.push('9');
-                    },
-                    KeyCode::Back => {
-                        self.ip_str.pop();
-                    },
-                    KeyCode::Colon | KeyCode::Semicolon => {
-                        self.ip_str.push(':');
-                    },
-                    KeyCode::Period => {
-                        self.ip_str.push('.');
-                    },
-                    KeyCode::Return | KeyCode::NumpadEnter => {
-                        println!("connecting as guest... ");
-                        println!("TCP {} connect...", self.ip_str);
-                        let stream = TcpStream::connect(self.ip_str.clone()).unwrap();
-                        let opponent_ip_address = stream.peer_addr().unwrap();
-                        println!("Connected to opponent: {}", opponent_ip_address);
-                        stream.set_nonblocking(true).unwrap();
-                        self.tcp_stream = Some(Rc::new(RefCell::new(stream)));
-                        self.should_end_state = true;
+    fn key_down_event(&mut self, _: &mut Context, keycode: KeyCode, _keymods: KeyMods, _repeat: bool) {
+        if let EInnerState::Incompatible(_)
+        | EInnerState::Failed(_)
+        | EInnerState::HostFailed(_)
+        | EInnerState::Disconnected(_) = self.state
+        {
+            if let KeyCode::Return | KeyCode::NumpadEnter | KeyCode::Escape = keycode {
+                self.go_back();
+            }
+            return;
+        }
+        if let EInnerState::HostSetup = self.state {
+            let field = if self.editing_port {
+                &mut self.port_str
+            } else {
+                &mut self.bind_ip_str
+            };
+            match keycode {
+                KeyCode::Back => {
+                    field.pop();
+                }
+                KeyCode::Tab => self.editing_port = !self.editing_port,
+                KeyCode::Up if !self.editing_port => self.cycle_bind_preset(-1),
+                KeyCode::Down if !self.editing_port => self.cycle_bind_preset(1),
+                KeyCode::Return | KeyCode::NumpadEnter => self.start_hosting(),
+                KeyCode::R => self.host_through_relay(),
+                KeyCode::Escape => self.go_back(),
+                keycode => {
+                    if let Some(c) = address_char(keycode).filter(|c| *c != ':') {
+                        // Ports are digits only
+                        if !self.editing_port || c.is_ascii_digit() {
+                            field.push(c);
+                        }
                     }
-                    _ => {}
                 }
-            },
-            _ => {}
+            }
+            return;
+        }
+        if let EInnerState::WaitingGuest = self.state {
+            if let KeyCode::Escape | KeyCode::Back = keycode {
+                self.go_back();
+            }
+            return;
+        }
+        if let EInnerState::BrowsingLobby = self.state {
+            match keycode {
+                KeyCode::R => self.refresh_lobby(),
+                KeyCode::Escape => self.go_back(),
+                _ => {}
+            }
+            return;
+        }
+        if let EInnerState::TypingHostIp = self.state {
+            match keycode {
+                KeyCode::Back => {
+                    self.ip_str.pop();
+                }
+                KeyCode::Return | KeyCode::NumpadEnter => self.join(),
+                keycode => {
+                    if let Some(c) = address_char(keycode).or_else(|| room_code_char(keycode)) {
+                        self.ip_str.push(c);
+                    }
+                }
+            }
         }
     }
 
-    fn key_up_event(&mut self, ctx: &mut Context, keycode: KeyCode, keymods: KeyMods) {
-    }
-
-    fn mouse_button_down_event(&mut self, _ctx: &mut Context, _button: MouseButton, x: f32, y: f32
-    ) {
-        print!("{} {} \n", x, y);
+    fn mouse_button_down_event(&mut self, _ctx: &mut Context, _button: MouseButton, x: f32, y: f32) {
+        println!("{} {} ", x, y);
 
         match self.state {
-            EInnerState::unkown => {
-                if self.host_button_rect.isInIt(x, y) {
-                    print!("host! \n");
-                    self.state = EInnerState::waiting_guest;
-                    
-                    let sender2 = self.sender.clone();
-
-                    let tcp_listener = TcpListener::bind("127.0.0.1:9999").expect("tcp bind failed");
-                    thread::spawn(move || {
-                        println!("host waiting guest... ");
-                        println!("TCP port 9999 listen... ");
-                        let stream  = tcp_listener.incoming().next().unwrap().unwrap();
-                        let opponent_ip_address = stream.peer_addr().unwrap();
-                        println!("Opponent connected: {}", opponent_ip_address);
-                        stream.set_nonblocking(true).unwrap();
-                        sender2.send(stream).unwrap();
-                    });
-                    self.state = EInnerState::waiting_guest;
-                } else if self.guest_button_rect.isInIt(x, y) {
-                    print!("guest! \n");
-                    self.state = EInnerState::typing_host_ip;
+            EInnerState::Unknown => {
+                if self.host_button_rect.is_in_it(x, y) {
+                    println!("host! ");
+                    self.open_host_setup();
+                } else if self.guest_button_rect.is_in_it(x, y) {
+                    println!("guest! ");
+                    self.open_guest_setup();
+                } else if self.lobby_button_rect.is_in_it(x, y) {
+                    println!("lobby! ");
+                    self.open_lobby();
                 }
             }
-            EInnerState::waiting_guest => {},
-            EInnerState::typing_host_ip => {},
+            EInnerState::HostSetup => {}
+            EInnerState::WaitingGuest => {}
+            EInnerState::TypingHostIp => {
+                let picked = (0..self.discovered.len())
+                    .find(|idx| MenuState::discovered_rect(*idx).is_in_it(x, y));
+                if let Some(idx) = picked {
+                    self.ip_str = self.discovered[idx].1.to_string();
+                    self.join();
+                }
+            }
+            EInnerState::BrowsingLobby => {
+                if self.open_room_button_rect.is_in_it(x, y) {
+                    self.open_lobby_room();
+                } else if let Some(room) = (0..self.lobby_rooms.len())
+                    .find(|idx| MenuState::lobby_room_rect(*idx).is_in_it(x, y))
+                    .map(|idx| self.lobby_rooms[idx].id)
+                {
+                    self.join_lobby_room(room);
+                }
+            }
+            EInnerState::Incompatible(_)
+            | EInnerState::Failed(_)
+            | EInnerState::HostFailed(_)
+            | EInnerState::Disconnected(_) => {
+                self.go_back();
+            }
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,236 @@
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use ggez_project::relay::{join_room, RelayError, RelayRoom};
+use ggez_project::{guest_rejoin_handshake, host_rejoin_handshake, Handshake, MatchSettings};
+
+// Pause between attempts to reach the opponent
+const RETRY_INTERVAL: Duration = Duration::from_millis(500);
+// How often the host checks for a new connection
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How a side finds its opponent again after losing it: the host keeps listening where
+/// the guest first connected, and the guest dials the host again. Through a relay, the
+/// host opens its room again under the same code and the guest joins it again.
+pub enum Rejoin {
+    Listen(Arc<TcpListener>),
+    Dial(SocketAddr),
+    RelayHost { relay: SocketAddr, code: String },
+    RelayGuest { relay: SocketAddr, code: String },
+}
+
+/// Looks for the opponent in the background until it is back or this is dropped.
+pub struct Reconnect {
+    receiver: Receiver<(TcpStream, Handshake)>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Reconnect {
+    /// Starts looking for an opponent that agrees to `settings`, the match being resumed.
+    pub fn start(rejoin: &Rejoin, name: &str, settings: MatchSettings) -> Reconnect {
+        let (sender, receiver) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let name = String::from(name);
+        let stop2 = stop.clone();
+        match rejoin {
+            Rejoin::Listen(listener) => {
+                let listener = listener.clone();
+                thread::spawn(move || listen(&listener, &name, settings, &sender, &stop2));
+            }
+            Rejoin::Dial(addr) => {
+                let addr = *addr;
+                thread::spawn(move || dial(addr, &name, settings, &sender, &stop2));
+            }
+            Rejoin::RelayHost { relay, code } => {
+                let (relay, code) = (*relay, code.clone());
+                thread::spawn(move || reopen_room(relay, &code, &name, settings, &sender, &stop2));
+            }
+            Rejoin::RelayGuest { relay, code } => {
+                let (relay, code) = (*relay, code.clone());
+                thread::spawn(move || rejoin_room(relay, &code, &name, settings, &sender, &stop2));
+            }
+        }
+        Reconnect { receiver, stop }
+    }
+
+    /// The opponent's new connection, once it has shaken hands again.
+    pub fn poll(&self) -> Option<(TcpStream, Handshake)> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+impl Drop for Reconnect {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+fn listen(
+    listener: &TcpListener,
+    name: &str,
+    settings: MatchSettings,
+    sender: &Sender<(TcpStream, Handshake)>,
+    stop: &AtomicBool,
+) {
+    println!("waiting for the opponent to reconnect... ");
+    // Polled, so the thread notices when it is no longer wanted
+    if let Err(err) = listener.set_nonblocking(true) {
+        println!("Cannot wait for the opponent: {}", err);
+        return;
+    }
+    while !stop.load(Ordering::Relaxed) {
+        let (mut stream, addr) = match listener.accept() {
+            Ok(res) => res,
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(ACCEPT_INTERVAL);
+                continue;
+            }
+            Err(err) => {
+                println!("Accept failed: {}", err);
+                thread::sleep(RETRY_INTERVAL);
+                continue;
+            }
+        };
+        let result = stream
+            .set_nonblocking(false)
+            .map_err(Into::into)
+            .and_then(|_| host_rejoin_handshake(&mut stream, name, settings));
+        match result {
+            Ok(handshake) => {
+                println!("Opponent reconnected: {}", addr);
+                let _ = sender.send((stream, handshake));
+                return;
+            }
+            Err(err) => println!("Rejoin from {} failed: {}", addr, err),
+        }
+    }
+}
+
+fn dial(
+    addr: SocketAddr,
+    name: &str,
+    settings: MatchSettings,
+    sender: &Sender<(TcpStream, Handshake)>,
+    stop: &AtomicBool,
+) {
+    println!("reconnecting to {}... ", addr);
+    while !stop.load(Ordering::Relaxed) {
+        let result = TcpStream::connect_timeout(&addr, RETRY_INTERVAL)
+            .map_err(Into::into)
+            .and_then(|mut stream| {
+                guest_rejoin_handshake(&mut stream, name, settings.token)
+                    .map(|handshake| (stream, handshake))
+            });
+        match result {
+            Ok((stream, handshake)) if handshake.settings == settings => {
+                println!("Reconnected to {}", addr);
+                let _ = sender.send((stream, handshake));
+                return;
+            }
+            // The host went on to another match, which this one cannot join
+            Ok(_) => println!("{} is hosting a different match now", addr),
+            Err(err) => println!("Reconnect to {} failed: {}", addr, err),
+        }
+        thread::sleep(RETRY_INTERVAL);
+    }
+}
+
+/// Waits on `listener` until a guest connects, and returns the connection to it. `None`
+/// once `stop` is set.
+pub fn wait_for_guest(listener: &TcpListener, stop: &AtomicBool) -> io::Result<Option<TcpStream>> {
+    // Polled, so the thread notices when it is no longer wanted
+    listener.set_nonblocking(true)?;
+    while !stop.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                stream.set_nonblocking(false)?;
+                return Ok(Some(stream));
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => thread::sleep(ACCEPT_INTERVAL),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(None)
+}
+
+/// Waits in `room` until its guest joins, and returns the connection to it. `None` once
+/// `stop` is set.
+pub fn wait_in_room(
+    mut room: RelayRoom,
+    stop: &AtomicBool,
+) -> Result<Option<TcpStream>, RelayError> {
+    while !stop.load(Ordering::Relaxed) {
+        if room.wait_for_guest(ACCEPT_INTERVAL)? {
+            return Ok(Some(room.into_stream()?));
+        }
+    }
+    Ok(None)
+}
+
+fn reopen_room(
+    relay: SocketAddr,
+    code: &str,
+    name: &str,
+    settings: MatchSettings,
+    sender: &Sender<(TcpStream, Handshake)>,
+    stop: &AtomicBool,
+) {
+    println!("waiting for the opponent to rejoin room {}... ", code);
+    while !stop.load(Ordering::Relaxed) {
+        let result = RelayRoom::open(relay, Some(code)).and_then(|room| wait_in_room(room, stop));
+        let mut stream = match result {
+            Ok(Some(stream)) => stream,
+            Ok(None) => return,
+            Err(err) => {
+                println!("Reopening room {} failed: {}", code, err);
+                thread::sleep(RETRY_INTERVAL);
+                continue;
+            }
+        };
+        match host_rejoin_handshake(&mut stream, name, settings) {
+            Ok(handshake) => {
+                println!("Opponent rejoined room {}", code);
+                let _ = sender.send((stream, handshake));
+                return;
+            }
+            Err(err) => println!("Rejoin through room {} failed: {}", code, err),
+        }
+    }
+}
+
+fn rejoin_room(
+    relay: SocketAddr,
+    code: &str,
+    name: &str,
+    settings: MatchSettings,
+    sender: &Sender<(TcpStream, Handshake)>,
+    stop: &AtomicBool,
+) {
+    println!("rejoining room {}... ", code);
+    while !stop.load(Ordering::Relaxed) {
+        let result = join_room(relay, code)
+            .map_err(|err| err.to_string())
+            .and_then(|mut stream| {
+                guest_rejoin_handshake(&mut stream, name, settings.token)
+                    .map(|handshake| (stream, handshake))
+                    .map_err(|err| err.to_string())
+            });
+        match result {
+            Ok((stream, handshake)) if handshake.settings == settings => {
+                println!("Rejoined room {}", code);
+                let _ = sender.send((stream, handshake));
+                return;
+            }
+            Ok(_) => println!("Room {} is hosting a different match now", code),
+            // Refused until the host has opened the room again
+            Err(err) => println!("Rejoining room {} failed: {}", code, err),
+        }
+        thread::sleep(RETRY_INTERVAL);
+    }
+}
@@ -0,0 +1,177 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use ggez_project::address::DEFAULT_PORT;
+use ggez_project::interpolation::DEFAULT_INTERPOLATION_DELAY;
+use ggez_project::lobby::DEFAULT_LOBBY_PORT;
+use ggez_project::lockstep::{MAX_INPUT_DELAY, MIN_INPUT_DELAY};
+use ggez_project::relay::DEFAULT_RELAY_PORT;
+use ggez_project::{Netcode, NetworkConditions, TransportKind};
+
+const USAGE: &str = "usage: ggez7 [--name <name>] [--netcode state|rollback|lockstep] \
+                     [--input-delay <ticks>|auto] [--transport tcp|udp] \
+                     [--bind <ip>] [--port <port>] [--lobby <address>] \
+                     [--relay <address>] [--timeout <seconds>] \
+                     [--heartbeat-timeout <seconds>] [--interp-delay <ms>] \
+                     [--netsim latency=<ms>,jitter=<ms>,loss=<%>,duplicate=<%>,reorder=<%>]";
+
+// Anything longer makes the opponent visibly lag behind
+const MAX_INTERPOLATION_DELAY_MS: u64 = 500;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(15);
+const DEFAULT_HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Command line options.
+pub struct Settings {
+    /// Shown to the opponent during the handshake.
+    pub name: String,
+    /// Used when hosting; a guest follows the host's choice.
+    pub netcode: Netcode,
+    /// Fixed lockstep input delay in ticks, or `None` to follow the round trip time.
+    pub input_delay: Option<u64>,
+    /// Used when hosting, like `netcode`.
+    pub transport: TransportKind,
+    /// Address the host setup screen starts out with.
+    pub bind_ip: IpAddr,
+    /// Port the host setup screen starts out with.
+    pub port: u16,
+    /// Lobby server to browse rooms at.
+    pub lobby: SocketAddr,
+    /// Relay server for players who cannot connect to each other directly.
+    pub relay: SocketAddr,
+    /// How long a match waits for a disconnected opponent to come back.
+    pub timeout: Duration,
+    /// How long the opponent may stay silent before it counts as disconnected.
+    pub heartbeat_timeout: Duration,
+    /// How far in the past the opponent is drawn under the state sync netcode.
+    pub interpolation_delay: Duration,
+    /// Network conditions to simulate on everything this side sends, for testing.
+    pub network: Option<NetworkConditions>,
+}
+
+impl Settings {
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Result<Settings, String> {
+        let mut settings = Settings {
+            name: String::from("Player"),
+            netcode: Netcode::default(),
+            input_delay: None,
+            transport: TransportKind::default(),
+            bind_ip: Ipv4Addr::LOCALHOST.into(),
+            port: DEFAULT_PORT,
+            lobby: SocketAddr::new(Ipv4Addr::LOCALHOST.into(), DEFAULT_LOBBY_PORT),
+            relay: SocketAddr::new(Ipv4Addr::LOCALHOST.into(), DEFAULT_RELAY_PORT),
+            timeout: DEFAULT_TIMEOUT,
+            heartbeat_timeout: DEFAULT_HEARTBEAT_TIMEOUT,
+            interpolation_delay: DEFAULT_INTERPOLATION_DELAY,
+            network: None,
+        };
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--name" => {
+                    settings.name = args.next().ok_or(USAGE)?;
+                }
+                "--netcode" => {
+                    let name = args.next().ok_or(USAGE)?;
+                    settings.netcode = match Netcode::from_name(&name) {
+                        // Only a dedicated server hosts with it
+                        Some(Netcode::Server) | None => {
+                            return Err(format!("unknown netcode `{}`\n{}", name, USAGE))
+                        }
+                        Some(netcode) => netcode,
+                    };
+                }
+                "--transport" => {
+                    let name = args.next().ok_or(USAGE)?;
+                    settings.transport = TransportKind::from_name(&name)
+                        .ok_or_else(|| format!("unknown transport `{}`\n{}", name, USAGE))?;
+                }
+                "--input-delay" => {
+                    let delay = args.next().ok_or(USAGE)?;
+                    settings.input_delay = match delay.as_str() {
+                        "auto" => None,
+                        ticks => match ticks.parse() {
+                            Ok(ticks) if (MIN_INPUT_DELAY..=MAX_INPUT_DELAY).contains(&ticks) => {
+                                Some(ticks)
+                            }
+                            _ => {
+                                return Err(format!(
+                                    "input delay must be `auto` or {} to {} ticks\n{}",
+                                    MIN_INPUT_DELAY, MAX_INPUT_DELAY, USAGE
+                                ))
+                            }
+                        },
+                    };
+                }
+                "--bind" => {
+                    let ip = args.next().ok_or(USAGE)?;
+                    settings.bind_ip = ip
+                        .parse()
+                        .map_err(|_| format!("`{}` is not an IP address\n{}", ip, USAGE))?;
+                }
+                "--port" => {
+                    let port = args.next().ok_or(USAGE)?;
+                    settings.port = port
+                        .parse()
+                        .map_err(|_| format!("port must be 0 to 65535\n{}", USAGE))?;
+                }
+                "--lobby" => {
+                    let addr = args.next().ok_or(USAGE)?;
+                    settings.lobby = addr
+                        .parse()
+                        .map_err(|_| format!("`{}` is not an address\n{}", addr, USAGE))?;
+                }
+                "--relay" => {
+                    let addr = args.next().ok_or(USAGE)?;
+                    settings.relay = addr
+                        .parse()
+                        .map_err(|_| format!("`{}` is not an address\n{}", addr, USAGE))?;
+                }
+                "--timeout" => {
+                    let seconds = args.next().ok_or(USAGE)?;
+                    settings.timeout = match seconds.parse() {
+                        Ok(seconds) if seconds > 0 => Duration::from_secs(seconds),
+                        _ => {
+                            return Err(format!(
+                                "timeout must be a whole number of seconds\n{}",
+                                USAGE
+                            ))
+                        }
+                    };
+                }
+                "--heartbeat-timeout" => {
+                    let seconds = args.next().ok_or(USAGE)?;
+                    settings.heartbeat_timeout = match seconds.parse() {
+                        Ok(seconds) if seconds > 0 => Duration::from_secs(seconds),
+                        _ => {
+                            return Err(format!(
+                                "heartbeat timeout must be a whole number of seconds\n{}",
+                                USAGE
+                            ))
+                        }
+                    };
+                }
+                "--interp-delay" => {
+                    let millis = args.next().ok_or(USAGE)?;
+                    settings.interpolation_delay = match millis.parse() {
+                        Ok(millis) if millis <= MAX_INTERPOLATION_DELAY_MS => {
+                            Duration::from_millis(millis)
+                        }
+                        _ => {
+                            return Err(format!(
+                                "interpolation delay must be 0 to {} ms\n{}",
+                                MAX_INTERPOLATION_DELAY_MS, USAGE
+                            ))
+                        }
+                    };
+                }
+                "--netsim" => {
+                    let spec = args.next().ok_or(USAGE)?;
+                    let conditions = NetworkConditions::from_spec(&spec)
+                        .map_err(|err| format!("{}\n{}", err, USAGE))?;
+                    settings.network = Some(conditions);
+                }
+                _ => return Err(format!("unknown option `{}`\n{}", arg, USAGE)),
+            }
+        }
+        Ok(settings)
+    }
+}
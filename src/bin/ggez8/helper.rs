@@ -1,12 +1,8 @@
-use std::collections::HashMap;
-use std::rc::Rc;
-
-use ggez::{Context};
-use ggez::event::{EventHandler, KeyCode, KeyMods, Button, MouseButton};
-use ggez::graphics::Image;
+use ggez::event::{KeyCode, KeyMods, MouseButton};
+use ggez::Context;
 
 pub enum EState {
-    Menu, Game, 
+    Menu, Game, Replay,
     None    // None means no transision for IState::update() return value
 }
 
@@ -15,29 +11,11 @@ pub trait IState {
         EState::None
     }
 
-    fn draw(&mut self, ctx: &mut ggez::Context){}
+    fn draw(&mut self, _ctx: &mut ggez::Context){}
 
-    fn key_down_event(&mut self, _: &mut Context, keycode: KeyCode, keymods: KeyMods, repeat: bool) {}
+    fn key_down_event(&mut self, _: &mut Context, _keycode: KeyCode, _keymods: KeyMods, _repeat: bool) {}
 
-    fn key_up_event(&mut self, ctx: &mut Context, keycode: KeyCode, keymods: KeyMods) {}
+    fn key_up_event(&mut self, _ctx: &mut Context, _keycode: KeyCode, _keymods: KeyMods) {}
 
-    fn mouse_button_down_event(&mut self, _ctx: &mut Context, _button: MouseButton, x: f32, y: f32) {}
+    fn mouse_button_down_event(&mut self, _ctx: &mut Context, _button: MouseButton, _x: f32, _y: f32) {}
 }
-
-pub fn load_image(
-    ctx: &mut Context,
-    path: String,
-    image_pool: &mut HashMap<String, Rc<Image>>,
-) -> Rc<Image> {
-    match image_pool.get(&path) {
-        Some(image) => Rc::clone(image),
-        None => match Image::new(ctx, path.clone()) {
-            Ok(res) => {
-                let image = Rc::new(res);
-                image_pool.insert(path.clone(), Rc::clone(&image));
-                image
-            }
-            Err(err) => panic!("Failed to load image: {}", err),
-        },
-    }
-}
\ No newline at end of file
@@ -1,34 +1,26 @@
-use ggez::conf::{WindowMode, WindowSetup};
-use ggez::event::quit;
 use ggez::event::{run, EventHandler, KeyCode, KeyMods, MouseButton};
-use ggez::graphics::{clear, draw, present, Color, DrawParam, Image, Text};
-use ggez::input::gamepad::Event;
-use ggez::input::keyboard::is_key_pressed;
-use ggez::mint::Point2;
-use ggez::timer::delta;
-use ggez::{Context, ContextBuilder, GameResult, GameError};
-
-use std::cell::RefCell;
-use std::f32::consts::PI;
-use std::rc::Rc;
-
-use rand::rngs::ThreadRng;
-use rand::{thread_rng, Rng};
+use ggez::graphics::{clear, present, Color, Image};
+use ggez::{Context, GameResult};
 
 use std::collections::HashMap;
-use std::io::Read;
-use std::io::Write;
+use std::rc::Rc;
 
 mod helper;
-use helper::{EState, load_image, IState};
+use helper::{EState, IState};
 mod game_state;
 mod menu_state;
+mod replay_state;
+mod settings;
 use game_state::GameState;
 use menu_state::MenuState;
+use replay_state::ReplayState;
+use settings::Settings;
 
 struct Game {
+    settings: Settings, 
     game_state: GameState, 
     menu_state: MenuState, 
+    replay_state: ReplayState, 
     current_state : EState, 
 }
 
@@ -36,43 +28,101 @@ impl Game {
     fn new(
         ctx: &mut Context, 
         image_pool : &mut HashMap<String, Rc<Image>>,
+        settings: Settings,
     ) -> Game {
-        let mut gs = GameState::new(ctx, image_pool);
-        gs.initialize();
+        let mut menu_state = MenuState::new(settings.record);
+        menu_state.refresh(&settings.archive_dir);
 
         Game {
-            menu_state : MenuState::new(ctx, image_pool), 
-            game_state : gs,
-            current_state : EState::None,
+            settings,
+            menu_state, 
+            game_state : GameState::new(ctx, image_pool),
+            replay_state : ReplayState::new(ctx, image_pool),
+            current_state : EState::Menu,
         }
     }
 }
 
 impl EventHandler for Game {
     fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
-        self.game_state.update(ctx);
+        let ret = match self.current_state {
+            EState::Menu => self.menu_state.update(ctx),
+            EState::Game => self.game_state.update(ctx),
+            EState::Replay => self.replay_state.update(ctx),
+            EState::None => EState::None, 
+        };
+
+        match ret {
+            EState::Menu => {
+                self.menu_state.refresh(&self.settings.archive_dir);
+                self.current_state = EState::Menu;
+            }
+            EState::Game => {
+                self.game_state.initialize(&self.settings, self.menu_state.record);
+                self.current_state = EState::Game;
+            }
+            EState::Replay => {
+                if let Some(path) = self.menu_state.selected_match() {
+                    self.replay_state.initialize(path);
+                    self.current_state = EState::Replay;
+                }
+            }
+            EState::None => {}
+        }
         Ok(())
     }
 
     fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
         clear(ctx, Color::BLACK);
 
-        self.game_state.draw(ctx);
+        match self.current_state {
+            EState::Menu => self.menu_state.draw(ctx),
+            EState::Game => self.game_state.draw(ctx),
+            EState::Replay => self.replay_state.draw(ctx),
+            EState::None => {}
+        };
 
         present(ctx)?;
         Ok(())
     }
 
     fn key_down_event(&mut self, ctx: &mut Context, keycode: KeyCode, keymods: KeyMods, repeat: bool) {
-        self.game_state.key_down_event(ctx, keycode, keymods, repeat);
+        match self.current_state {
+            EState::Menu => self.menu_state.key_down_event(ctx, keycode, keymods, repeat),
+            EState::Game => self.game_state.key_down_event(ctx, keycode, keymods, repeat),
+            EState::Replay => self.replay_state.key_down_event(ctx, keycode, keymods, repeat),
+            EState::None => {}
+        }
     }
 
     fn key_up_event(&mut self, ctx: &mut Context, keycode: KeyCode, keymods: KeyMods) {
-        self.game_state.key_up_event(ctx, keycode, keymods);
+        match self.current_state {
+            EState::Menu => self.menu_state.key_up_event(ctx, keycode, keymods), 
+            EState::Game => self.game_state.key_up_event(ctx, keycode, keymods), 
+            EState::Replay => self.replay_state.key_up_event(ctx, keycode, keymods), 
+            EState::None => {}
+        }
+    }
+
+    fn mouse_button_down_event(&mut self, ctx: &mut Context, button: MouseButton, x: f32, y: f32) {
+        match self.current_state {
+            EState::Menu => self.menu_state.mouse_button_down_event(ctx, button, x, y), 
+            EState::Game => self.game_state.mouse_button_down_event(ctx, button, x, y), 
+            EState::Replay => self.replay_state.mouse_button_down_event(ctx, button, x, y), 
+            EState::None => {}
+        }
     }
 }
 
 fn main() {
+    let settings = match Settings::from_args(std::env::args().skip(1)) {
+        Ok(settings) => settings,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
+
     let (mut ctx, event_loop) = match ggez::ContextBuilder::new("GGEZ", "GGEZ")
         .window_setup(ggez::conf::WindowSetup::default().title("GGEZ"))
         .window_mode(ggez::conf::WindowMode::default().dimensions(1280.0, 720.0))
@@ -84,6 +134,6 @@ fn main() {
 
     let mut image_pool: HashMap<String, Rc<Image>> = HashMap::new();
 
-    let ggez = Game::new(&mut ctx, &mut image_pool);
+    let ggez = Game::new(&mut ctx, &mut image_pool, settings);
     run(ctx, event_loop, ggez);
 }
\ No newline at end of file
@@ -1,234 +1,188 @@
-use std::rc::Rc;
-use std::cell::RefCell;
-use std::collections::HashMap;
-use std::net::{TcpListener, TcpStream};
-use std::sync::mpsc;
-use std::sync::mpsc::{Receiver, SyncSender};
-use std::thread;
-
-use ggez::event::{EventHandler, KeyCode, KeyMods, Button, MouseButton};
-use ggez::{Context, GameError};
-use ggez::graphics::{draw, Color, DrawParam, Image, Text};
+use ggez::event::{KeyCode, KeyMods, MouseButton};
+use ggez::graphics::{draw, Color, DrawParam, Text};
 use ggez::mint::Point2;
+use ggez::Context;
 
-use crate::helper::{load_image, IState, EState};
-use crate::game_state::GameState;
+use std::path::{Path, PathBuf};
 
-enum EInnerState {
-    unkown,         // guest인지 host인지 선택하지 않은 상태
-    waiting_guest, // host로서 guest를 기다리는 상태
-    typing_host_ip, // guest로서 접속할 host의 ip를 입력하는 상태
-}
+use ggez_project::replay::recent_matches;
+
+use crate::helper::{EState, IState};
+
+const RECENT_MATCH_COUNT: usize = 5;
+const RECENT_LIST_Y: f32 = 480.0;
+const RECENT_ROW_HEIGHT: f32 = 30.0;
 
 struct ButtonRect {
-    x : f32, 
-    y : f32, 
-    s_x : f32,
-    s_y : f32, 
+    x: f32,
+    y: f32,
+    s_x: f32,
+    s_y: f32,
 }
 
 impl ButtonRect {
-    fn isInIt(&mut self, x : f32, y: f32) -> bool {
-        if self.x - self.s_x / 2.0 <= x && x <= self.x + self.s_x / 2.0 {
-            if self.y - self.s_y / 2.0 <= y && y <= self.y + self.s_y / 2.0 {
-                return true;
-            }
-        }
+    fn is_in_it(&self, x: f32, y: f32) -> bool {
+        self.x - self.s_x / 2.0 <= x
+            && x <= self.x + self.s_x / 2.0
+            && self.y - self.s_y / 2.0 <= y
+            && y <= self.y + self.s_y / 2.0
+    }
 
-        return false;
+    fn draw_label(&self, ctx: &mut Context, label: &str) {
+        let param = DrawParam::new()
+            .dest(Point2 { x: self.x, y: self.y })
+            .offset(Point2 { x: 0.5, y: 0.5 })
+            .scale([2.0, 2.0])
+            .color(Color::WHITE);
+        draw(ctx, &Text::new(label), param).expect("draw failed");
     }
 }
 
 pub struct MenuState {
-    state : EInnerState,
-    ip_str : String, 
-    host_button_image : Rc<Image>, 
-    guest_button_image : Rc<Image>, 
-    // cancel_button_image : Rc<Image>, 
+    play_button_rect: ButtonRect,
+    replay_button_rect: ButtonRect,
+    record_button_rect: ButtonRect,
 
-    host_button_rect : ButtonRect, 
-    guest_button_rect : ButtonRect, 
+    // Whether the next match is recorded
+    pub record: bool,
+    recent_matches: Vec<PathBuf>,
+    selected_match: usize,
+    list_error: Option<String>,
 
-    should_end_state : bool,
+    next_state: Option<EState>,
 }
 
 impl MenuState {
-    pub fn new(
-        ctx: &mut Context, 
-        image_pool: &mut HashMap<String, Rc<Image>>,
-    ) -> MenuState {
-        let host_button_image = load_image(ctx, String::from("/host_button.png"), image_pool);
-        let guest_button_image = load_image(ctx, String::from("/guest_button.png"), image_pool);
-        // let cancel_button_image = load_image(ctx, String::from("/cancel_button.png"), image_pool);
-
-        MenuState { 
-            state: EInnerState::unkown, 
-            ip_str: String::new(), 
-            host_button_image: host_button_image, 
-            guest_button_image: guest_button_image, 
-            // cancel_button_image: cancel_button_image,
-            host_button_rect : ButtonRect { x: 640.0, y: 320.0, s_x: 195.0, s_y: 49.0 }, 
-            guest_button_rect : ButtonRect { x: 640.0, y: 395.0, s_x: 207.0, s_y: 49.0 }, 
-            should_end_state: false,
+    pub fn new(record: bool) -> MenuState {
+        MenuState {
+            play_button_rect: ButtonRect {
+                x: 640.0,
+                y: 320.0,
+                s_x: 300.0,
+                s_y: 49.0,
+            },
+            replay_button_rect: ButtonRect {
+                x: 640.0,
+                y: 370.0,
+                s_x: 300.0,
+                s_y: 49.0,
+            },
+            record_button_rect: ButtonRect {
+                x: 640.0,
+                y: 420.0,
+                s_x: 300.0,
+                s_y: 49.0,
+            },
+            record,
+            recent_matches: Vec::new(),
+            selected_match: 0,
+            list_error: None,
+            next_state: None,
         }
     }
 
-    pub fn IsServer(&mut self) -> bool {
-        match self.state {
-            EInnerState::waiting_guest  => {
-                return true;
-            },
-            _ => {
-                return false;
+    /// Lists the newest matches in `archive_dir` again.
+    pub fn refresh(&mut self, archive_dir: &Path) {
+        self.selected_match = 0;
+        match recent_matches(archive_dir, RECENT_MATCH_COUNT) {
+            Ok(paths) => {
+                self.recent_matches = paths;
+                self.list_error = None;
             }
+            Err(err) => {
+                self.recent_matches.clear();
+                self.list_error = Some(format!("{}: {}", archive_dir.display(), err));
+            }
+        }
+    }
+
+    /// The match the replay viewer should open.
+    pub fn selected_match(&self) -> Option<&Path> {
+        self.recent_matches
+            .get(self.selected_match)
+            .map(PathBuf::as_path)
+    }
+
+    fn watch_selected(&mut self) {
+        if self.selected_match().is_some() {
+            self.next_state = Some(EState::Replay);
         }
     }
 }
 
 impl IState for MenuState {
     fn update(&mut self, _ctx: &mut ggez::Context) -> EState {
-        if self.should_end_state {
-            return EState::Game
-        }
-        EState::None
+        self.next_state.take().unwrap_or(EState::None)
     }
 
-    fn draw(&mut self, ctx: &mut ggez::Context){
-        match self.state {
-            EInnerState::unkown => {
-                let host_button_param = DrawParam::new()
-                    .dest(Point2{ x: self.host_button_rect.x, y: self.host_button_rect.y })
-                    .offset(Point2{ x: 0.5, y: 0.5});
-                draw(ctx, self.host_button_image.as_ref(), host_button_param).expect("draw failed");
-
-                let guest_button_param = DrawParam::new()
-                    .dest(Point2{ x: self.guest_button_rect.x, y: self.guest_button_rect.y })
-                    .offset(Point2{ x: 0.5, y: 0.5});
-                draw(ctx, self.guest_button_image.as_ref(), guest_button_param).expect("draw failed");
-
-                let param1 = DrawParam::new()
-                .dest(Point2 { x: 600.0, y: 200.0 })
-                .offset(Point2 { x: 0.5, y: 0.5 })
-                .scale([3.0, 3.0])
-                .color(Color::WHITE);
-                draw(
-                    ctx,
-                    &Text::new(String::from("GGEZ")),
-                    param1,
-                ).expect("draw failed");
-            },
-            EInnerState::waiting_guest => {
-                let param = DrawParam::new()
-                    .dest(Point2 { x: 560.0, y: 220.0 })
-                    .offset(Point2 { x: 0.5, y: 0.5 })
-                    .scale([2.0, 2.0])
-                    .color(Color::WHITE);
-                draw(
-                    ctx,
-                    &Text::new(format!("waiting geuest...")),
-                    param,
-                ).expect("draw failed");
-            },
-            EInnerState::typing_host_ip => {
-                let param1 = DrawParam::new()
-                .dest(Point2 { x: 140.0, y: 270.0 })
-                .offset(Point2 { x: 0.5, y: 0.5 })
-                .scale([2.0, 2.0])
-                .color(Color::WHITE);
-                draw(
-                    ctx,
-                    &Text::new(String::from("Type Host Socket Addr and Press Enter! (??.??.??.??:9999)")),
-                    param1,
-                ).expect("draw failed");
-            
-                let param2 = DrawParam::new()
-                .dest(Point2 { x: 540.0, y: 320.0 })
+    fn draw(&mut self, ctx: &mut ggez::Context) {
+        let param = DrawParam::new()
+            .dest(Point2 { x: 640.0, y: 200.0 })
+            .offset(Point2 { x: 0.5, y: 0.5 })
+            .scale([3.0, 3.0])
+            .color(Color::WHITE);
+        draw(ctx, &Text::new("GGEZ"), param).expect("draw failed");
+
+        self.play_button_rect.draw_label(ctx, "1. Play");
+        self.replay_button_rect.draw_label(ctx, "2. Watch Replay");
+        let record_label = if self.record {
+            "R. Recording: On"
+        } else {
+            "R. Recording: Off"
+        };
+        self.record_button_rect.draw_label(ctx, record_label);
+
+        let mut lines = Vec::new();
+        if let Some(err) = &self.list_error {
+            lines.push(err.clone());
+        } else if self.recent_matches.is_empty() {
+            lines.push(String::from("No recorded matches yet"));
+        }
+        for (idx, path) in self.recent_matches.iter().enumerate() {
+            let name = path.file_stem().unwrap_or_default().to_string_lossy();
+            let marker = if idx == self.selected_match { ">" } else { " " };
+            lines.push(format!("{} {}", marker, name));
+        }
+        for (idx, line) in lines.iter().enumerate() {
+            let param = DrawParam::new()
+                .dest(Point2 {
+                    x: 640.0,
+                    y: RECENT_LIST_Y + RECENT_ROW_HEIGHT * idx as f32,
+                })
                 .offset(Point2 { x: 0.5, y: 0.5 })
-                .scale([2.0, 2.0])
+                .scale([1.5, 1.5])
                 .color(Color::WHITE);
-            draw(
-                ctx,
-                &Text::new(self.ip_str.clone()),
-                param2,
-            ).expect("draw failed");
-            },
+            draw(ctx, &Text::new(line.as_str()), param).expect("draw failed");
         }
     }
 
-    fn key_down_event(&mut self, _: &mut Context, keycode: KeyCode, keymods: KeyMods, repeat: bool) {
-        match self.state {
-            EInnerState::typing_host_ip => {
-                match keycode {
-                    KeyCode::Key0 | KeyCode::Numpad0 => {
-                        self.ip_str.push('0');
-                    },
-                    KeyCode::Key1 | KeyCode::Numpad1 => {
-                        self.ip_str.push('1');
-                    },
-                    KeyCode::Key2 | KeyCode::Numpad2 => {
-                        self.ip_str.push('2');
-                    },
-                    KeyCode::Key3 | KeyCode::Numpad3 => {
-                        self.ip_str.push('3');
-                    },
-                    KeyCode::Key4 | KeyCode::Numpad4 => {
-                        self.ip_str.push('4');
-                    },
-                    KeyCode::Key5 | KeyCode::Numpad5 => {
-                        self.ip_str.push('5');
-                    },
-                    KeyCode::Key6 | KeyCode::Numpad6 => {
-                        self.ip_str.push('6');
-                    },
-                    KeyCode::Key7 | KeyCode::Numpad7 => {
-                        self.ip_str.push('7');
-                    },
-                    KeyCode::Key8 | KeyCode::Numpad8 => {
-                        self.ip_str.push('8');
-                    },
-                    KeyCode::Key9 | KeyCode::Numpad9 => {
-                        self.ip_str.push('9');
-                    },
-                    KeyCode::Back => {
-                        self.ip_str.pop();
-                    },
-                    KeyCode::Colon | KeyCode::Semicolon => {
-                        self.ip_str.push(':');
-                    },
-                    KeyCode::Period => {
-                        self.ip_str.push('.');
-                    },
-                    KeyCode::Return | KeyCode::NumpadEnter => {
-                        self.should_end_state = true;
-                    }
-                    _ => {}
-                }
-            },
+    fn key_down_event(&mut self, _: &mut Context, keycode: KeyCode, _keymods: KeyMods, _repeat: bool) {
+        match keycode {
+            KeyCode::Key1 | KeyCode::Numpad1 => self.next_state = Some(EState::Game),
+            KeyCode::Key2 | KeyCode::Numpad2 | KeyCode::Return => self.watch_selected(),
+            KeyCode::R => self.record = !self.record,
+            KeyCode::Up => self.selected_match = self.selected_match.saturating_sub(1),
+            KeyCode::Down => {
+                let last = self.recent_matches.len().saturating_sub(1);
+                self.selected_match = (self.selected_match + 1).min(last);
+            }
             _ => {}
         }
     }
 
-    fn key_up_event(&mut self, ctx: &mut Context, keycode: KeyCode, keymods: KeyMods) {
-    }
-
-    fn mouse_button_down_event(&mut self, _ctx: &mut Context, _button: MouseButton, x: f32, y: f32
-    ) {
-        print!("{} {} \n", x, y);
-
-        match self.state {
-            EInnerState::unkown => {
-                if self.host_button_rect.isInIt(x, y) {
-                    print!("host! \n");
-                    self.state = EInnerState::waiting_guest;
-
-                    self.state = EInnerState::waiting_guest;
-                } else if self.guest_button_rect.isInIt(x, y) {
-                    print!("guest! \n");
-                    self.state = EInnerState::typing_host_ip;
-                }
+    fn mouse_button_down_event(&mut self, _ctx: &mut Context, _button: MouseButton, x: f32, y: f32) {
+        if self.play_button_rect.is_in_it(x, y) {
+            self.next_state = Some(EState::Game);
+        } else if self.replay_button_rect.is_in_it(x, y) {
+            self.watch_selected();
+        } else if self.record_button_rect.is_in_it(x, y) {
+            self.record = !self.record;
+        } else if self.list_error.is_none() && y >= RECENT_LIST_Y - RECENT_ROW_HEIGHT / 2.0 {
+            let row = ((y - RECENT_LIST_Y) / RECENT_ROW_HEIGHT + 0.5) as usize;
+            if row < self.recent_matches.len() {
+                self.selected_match = row;
+                self.watch_selected();
             }
-            EInnerState::waiting_guest => {},
-            EInnerState::typing_host_ip => {},
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,278 @@
+use ggez::event::{KeyCode, KeyMods};
+use ggez::graphics::{clear, draw, Color, DrawParam, Image, Text};
+use ggez::mint::Point2;
+use ggez::Context;
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
+
+use ggez_project::{
+    FixedTimestep, Renderer, Replay, ReplayKeyframe, ReplayRunner, ScoreEvent, Side, World,
+    TICK_RATE,
+};
+
+use crate::helper::{EState, IState};
+
+const SPEEDS: [f32; 5] = [0.25, 0.5, 1.0, 2.0, 4.0];
+const NORMAL_SPEED: usize = 2;
+// Seeking resimulates from the closest earlier keyframe
+const KEYFRAME_INTERVAL: u64 = TICK_RATE as u64;
+// Seeking to a score starts this long before it, to show the play that led to it
+const SCORE_LEAD_TICKS: u64 = 2 * TICK_RATE as u64;
+
+pub struct ReplayState {
+    renderer: Renderer,
+    runner: Option<ReplayRunner>,
+    // State before the latest step, drawn blended towards the runner's world
+    previous_world: Option<World>,
+    timestep: FixedTimestep,
+    keyframes: Vec<ReplayKeyframe>,
+    score_events: Vec<ScoreEvent>,
+    end_tick: u64,
+    names: [String; 2],
+    paused: bool,
+    speed_idx: usize,
+    error: Option<String>,
+    back_to_menu: bool,
+}
+
+impl ReplayState {
+    pub fn new(ctx: &mut Context, image_pool: &mut HashMap<String, Rc<Image>>) -> ReplayState {
+        ReplayState {
+            renderer: Renderer::new(ctx, image_pool),
+            runner: None,
+            previous_world: None,
+            timestep: FixedTimestep::new(),
+            keyframes: Vec::new(),
+            score_events: Vec::new(),
+            end_tick: 0,
+            names: [String::new(), String::new()],
+            paused: false,
+            speed_idx: NORMAL_SPEED,
+            error: None,
+            back_to_menu: false,
+        }
+    }
+
+    pub fn initialize(&mut self, path: &Path) {
+        self.runner = None;
+        self.previous_world = None;
+        self.keyframes.clear();
+        self.score_events.clear();
+        self.paused = false;
+        self.speed_idx = NORMAL_SPEED;
+        self.error = None;
+
+        let replay = match Replay::load(path) {
+            Ok(replay) => replay,
+            Err(err) => {
+                self.error = Some(format!("{}: {}", path.display(), err));
+                return;
+            }
+        };
+        self.names = replay.header.names.clone();
+
+        // Run the whole match once up front to find its length and scores
+        let mut scan = ReplayRunner::new(&replay);
+        loop {
+            if scan.world().tick.is_multiple_of(KEYFRAME_INTERVAL) {
+                self.keyframes.push(scan.keyframe());
+            }
+            if scan.is_finished() {
+                break;
+            }
+            self.score_events.extend(scan.step());
+        }
+        self.end_tick = scan.world().tick;
+        // The scan plays the match from here on
+        self.runner = Some(scan);
+        self.seek(0);
+    }
+
+    fn tick(&self) -> u64 {
+        self.runner.as_ref().map_or(0, |runner| runner.world().tick)
+    }
+
+    fn seek(&mut self, tick: u64) {
+        let tick = tick.min(self.end_tick);
+        let idx = ((tick / KEYFRAME_INTERVAL) as usize).min(self.keyframes.len() - 1);
+        let Some(runner) = self.runner.as_mut() else {
+            return;
+        };
+        runner.seek(&self.keyframes[idx]);
+        while runner.world().tick < tick {
+            runner.step();
+        }
+        self.previous_world = Some(runner.world().clone());
+        self.timestep.reset();
+    }
+
+    fn seek_score(&mut self, score_event: ScoreEvent) {
+        self.seek(score_event.tick.saturating_sub(SCORE_LEAD_TICKS));
+    }
+
+    fn step_once(&mut self) {
+        if let Some(runner) = self.runner.as_mut() {
+            if !runner.is_finished() {
+                self.previous_world = Some(runner.world().clone());
+                runner.step();
+            }
+        }
+    }
+
+    fn draw_text(ctx: &mut Context, text: &str, x: f32, y: f32) {
+        let param = DrawParam::new()
+            .dest(Point2 { x, y })
+            .scale([1.5, 1.5])
+            .color(Color::WHITE);
+        draw(ctx, &Text::new(text), param).expect("draw failed");
+    }
+}
+
+impl IState for ReplayState {
+    fn update(&mut self, ctx: &mut ggez::Context) -> EState {
+        if self.back_to_menu {
+            self.back_to_menu = false;
+            return EState::Menu;
+        }
+        if self.paused || self.runner.is_none() {
+            return EState::None;
+        }
+
+        let frame_dt = ggez::timer::delta(ctx).as_secs_f32() * SPEEDS[self.speed_idx];
+        let steps = self.timestep.advance(frame_dt);
+        for _ in 0..steps {
+            self.step_once();
+        }
+
+        EState::None
+    }
+
+    fn draw(&mut self, ctx: &mut ggez::Context) {
+        if let Some(error) = &self.error {
+            ReplayState::draw_text(ctx, error, 40.0, 320.0);
+            ReplayState::draw_text(ctx, "Press ESC to go back", 40.0, 360.0);
+            return;
+        }
+        let (runner, previous_world) = match (&self.runner, &self.previous_world) {
+            (Some(runner), Some(previous_world)) => (runner, previous_world),
+            _ => return,
+        };
+
+        clear(ctx, Color::WHITE);
+        let world = if self.paused {
+            runner.world().clone()
+        } else {
+            previous_world.lerp(runner.world(), self.timestep.alpha())
+        };
+        self.renderer
+            .draw(ctx, &world, Side::Bottom)
+            .expect("draw failed");
+
+        let tick_rate = TICK_RATE as f32;
+        let status = format!(
+            "REPLAY  {:.2}s / {:.2}s  x{}{}",
+            world.tick as f32 / tick_rate,
+            self.end_tick as f32 / tick_rate,
+            SPEEDS[self.speed_idx],
+            if self.paused { "  PAUSED" } else { "" },
+        );
+        ReplayState::draw_text(ctx, &status, 20.0, 20.0);
+        let names = format!(
+            "Top: {}  Bottom: {}",
+            self.names[Side::Top.index()],
+            self.names[Side::Bottom.index()]
+        );
+        ReplayState::draw_text(ctx, &names, 20.0, 50.0);
+
+        for (idx, score_event) in self.score_events.iter().enumerate() {
+            let side = match score_event.side {
+                Side::Top => "Top",
+                Side::Bottom => "Bottom",
+            };
+            let line = format!(
+                "{}. {:.2}s {} {}",
+                idx + 1,
+                score_event.tick as f32 / tick_rate,
+                side,
+                score_event.score
+            );
+            ReplayState::draw_text(ctx, &line, 1040.0, 20.0 + 30.0 * idx as f32);
+        }
+
+        ReplayState::draw_text(
+            ctx,
+            "Space pause  . step  Up/Down speed  Left/Right score  1-9 jump  Home restart  Esc menu",
+            20.0,
+            690.0,
+        );
+    }
+
+    fn key_down_event(&mut self, _ctx: &mut Context, keycode: KeyCode, _keymods: KeyMods, _repeat: bool) {
+        if keycode == KeyCode::Escape {
+            self.back_to_menu = true;
+            return;
+        }
+        if self.runner.is_none() {
+            return;
+        }
+
+        let tick = self.tick();
+        match keycode {
+            KeyCode::Space => {
+                self.paused = !self.paused;
+                self.timestep.reset();
+            }
+            KeyCode::Period => {
+                self.paused = true;
+                self.step_once();
+            }
+            KeyCode::Up => self.speed_idx = (self.speed_idx + 1).min(SPEEDS.len() - 1),
+            KeyCode::Down => self.speed_idx = self.speed_idx.saturating_sub(1),
+            KeyCode::Home => self.seek(0),
+            KeyCode::Left => {
+                // Skip back past the score being watched
+                let previous = self
+                    .score_events
+                    .iter()
+                    .rev()
+                    .find(|score_event| {
+                        score_event.tick.saturating_sub(SCORE_LEAD_TICKS) + 1 < tick
+                    })
+                    .copied();
+                match previous {
+                    Some(score_event) => self.seek_score(score_event),
+                    None => self.seek(0),
+                }
+            }
+            KeyCode::Right => {
+                let next = self
+                    .score_events
+                    .iter()
+                    .find(|score_event| score_event.tick.saturating_sub(SCORE_LEAD_TICKS) > tick)
+                    .copied();
+                if let Some(score_event) = next {
+                    self.seek_score(score_event);
+                }
+            }
+            _ => {
+                let number = match keycode {
+                    KeyCode::Key1 | KeyCode::Numpad1 => 1,
+                    KeyCode::Key2 | KeyCode::Numpad2 => 2,
+                    KeyCode::Key3 | KeyCode::Numpad3 => 3,
+                    KeyCode::Key4 | KeyCode::Numpad4 => 4,
+                    KeyCode::Key5 | KeyCode::Numpad5 => 5,
+                    KeyCode::Key6 | KeyCode::Numpad6 => 6,
+                    KeyCode::Key7 | KeyCode::Numpad7 => 7,
+                    KeyCode::Key8 | KeyCode::Numpad8 => 8,
+                    KeyCode::Key9 | KeyCode::Numpad9 => 9,
+                    _ => return,
+                };
+                if let Some(score_event) = self.score_events.get(number - 1).copied() {
+                    self.seek_score(score_event);
+                }
+            }
+        }
+    }
+}
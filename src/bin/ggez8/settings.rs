@@ -0,0 +1,38 @@
+use std::path::PathBuf;
+
+use ggez_project::replay::DEFAULT_ARCHIVE_DIR;
+
+const USAGE: &str = "usage: ggez8 [--archive-dir <dir>] [--opponent <file>] [--no-record]";
+
+/// Command line options.
+pub struct Settings {
+    /// Where matches are recorded and listed from.
+    pub archive_dir: PathBuf,
+    /// Recording replayed as the opponent.
+    pub opponent_path: PathBuf,
+    /// Whether matches are recorded unless turned off in the menu.
+    pub record: bool,
+}
+
+impl Settings {
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Result<Settings, String> {
+        let mut settings = Settings {
+            archive_dir: PathBuf::from(DEFAULT_ARCHIVE_DIR),
+            opponent_path: PathBuf::from("opponent.txt"),
+            record: true,
+        };
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--archive-dir" => {
+                    settings.archive_dir = args.next().ok_or(USAGE)?.into();
+                }
+                "--opponent" => {
+                    settings.opponent_path = args.next().ok_or(USAGE)?.into();
+                }
+                "--no-record" => settings.record = false,
+                _ => return Err(format!("unknown option `{}`\n{}", arg, USAGE)),
+            }
+        }
+        Ok(settings)
+    }
+}
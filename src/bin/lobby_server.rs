@@ -0,0 +1,59 @@
+//! A lobby where players list, open and join rooms, without a window.
+//!
+//! `lobby_server [<address>]` listens on `0.0.0.0:9997` unless given another address,
+//! such as `127.0.0.1:0` to run one for local testing. It prints the address it listens
+//! on, then a line for each player that joins and each room opened or joined.
+
+use std::env;
+use std::net::{SocketAddr, TcpListener};
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use ggez_project::lobby::{self, Lobby, DEFAULT_LOBBY_PORT};
+use ggez_project::protocol::ProtocolError;
+
+fn main() -> ExitCode {
+    let addr = match env::args().nth(1) {
+        Some(addr) => match addr.parse() {
+            Ok(addr) => addr,
+            Err(_) => {
+                eprintln!("usage: lobby_server [<address>]");
+                return ExitCode::from(2);
+            }
+        },
+        None => SocketAddr::from(([0, 0, 0, 0], DEFAULT_LOBBY_PORT)),
+    };
+    let listener = match TcpListener::bind(addr) {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!("Cannot listen on {}: {}", addr, err);
+            return ExitCode::from(1);
+        }
+    };
+    println!(
+        "Lobby listening on {}",
+        listener.local_addr().unwrap_or(addr)
+    );
+
+    let lobby = Arc::new(Mutex::new(Lobby::new()));
+    for (player, stream) in (0..).zip(listener.incoming()) {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                println!("Accept failed: {}", err);
+                continue;
+            }
+        };
+        let lobby = lobby.clone();
+        thread::spawn(move || {
+            match lobby::serve(&mut stream, player, &lobby, |line| println!("{}", line)) {
+                Ok(()) | Err(ProtocolError::Closed) => {}
+                Err(err) => println!("Player {} dropped: {}", player, err),
+            }
+            // Its rooms cannot be joined any more
+            lobby.lock().unwrap().leave(player);
+        });
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,450 @@
+//! A dedicated server that plays matches between two players, without a window.
+//!
+//! `match_server [<address>]` listens on `0.0.0.0:9999` unless given another address,
+//! such as `127.0.0.1:0` to run one for local testing. Players join it like any host.
+//! The first two are seated, one on each side; the server steps the match from their
+//! inputs, decides grabs, scores and respawns, and streams it to both, so neither plays
+//! with a host's advantage. A player that drops may connect again, showing the match's
+//! token, to take its side back; nobody else is let in once the match has started. Once
+//! the match is over, or given up on, the next one is played.
+
+use std::env;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::process::ExitCode;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ggez_project::address::DEFAULT_PORT;
+use ggez_project::{
+    host_handshake, host_rejoin_handshake, open_transport, AuthoritySession, Communication,
+    FixedTimestep, Handshake, MatchRules, MatchSettings, Message, Netcode, Side, Transport,
+    TransportKind, World,
+};
+
+const SERVER_NAME: &str = "Match server";
+// Time without a word from a player before it counts as gone
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(2);
+// How long a side is kept for a player that dropped mid-match
+const REJOIN_TIMEOUT: Duration = Duration::from_secs(15);
+// Time for the last events to reach both players once the match is won
+const END_LINGER: Duration = Duration::from_secs(3);
+// Pause between two passes over the players
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+struct Player {
+    name: String,
+    connection: Box<dyn Transport>,
+    last_heard: Instant,
+}
+
+// What players that connect are let in under
+#[derive(Clone, Copy)]
+struct Admission {
+    settings: MatchSettings,
+    // Once the match has started, only its players get back in, with its token
+    started: bool,
+}
+
+// A player that shook hands
+struct Arrival {
+    stream: TcpStream,
+    handshake: Handshake,
+    // Whether it showed the match token to take back a side
+    rejoined: bool,
+}
+
+struct Match {
+    settings: MatchSettings,
+    session: AuthoritySession,
+    timestep: FixedTimestep,
+    players: [Option<Player>; 2],
+    // Set once both players have been seated
+    started: bool,
+    // When a side was left empty mid-match, and when the match was won
+    vacated: Option<Instant>,
+    ended: Option<Instant>,
+    // Stamps states so players can drop stale ones
+    clock: Instant,
+    last_update: Instant,
+}
+
+impl Match {
+    fn new() -> Match {
+        let settings = MatchSettings {
+            seed: World::random_seed(),
+            rules: MatchRules::default(),
+            netcode: Netcode::Server,
+            transport: TransportKind::Tcp,
+            token: rand::random(),
+        };
+        Match {
+            settings,
+            session: AuthoritySession::new(World::with_rules(settings.seed, settings.rules)),
+            timestep: FixedTimestep::new(),
+            players: [None, None],
+            started: false,
+            vacated: None,
+            ended: None,
+            clock: Instant::now(),
+            last_update: Instant::now(),
+        }
+    }
+
+    fn admission(&self) -> Admission {
+        Admission {
+            settings: self.settings,
+            started: self.started,
+        }
+    }
+
+    fn name(&self, side: Side) -> &str {
+        match &self.players[side.index()] {
+            Some(player) => &player.name,
+            None => "",
+        }
+    }
+
+    // Seats a player that shook hands on the first free side.
+    fn seat(&mut self, arrival: Arrival) {
+        let name = arrival.handshake.peer.name;
+        if arrival.handshake.settings != self.settings {
+            println!("{} turned away: the match it joined is over", name);
+            return;
+        }
+        if self.started && !arrival.rejoined {
+            println!("{} turned away: the match has started", name);
+            return;
+        }
+        let side = match [Side::Top, Side::Bottom]
+            .into_iter()
+            .find(|side| self.players[side.index()].is_none())
+        {
+            Some(side) => side,
+            None => {
+                println!("{} turned away: the match is full", name);
+                return;
+            }
+        };
+        let connection = match open_transport(arrival.stream, self.settings.transport) {
+            Ok(connection) => connection,
+            Err(err) => {
+                println!("{} dropped: {}", name, err);
+                return;
+            }
+        };
+        println!("{} takes the {:?} side", name, side);
+        self.players[side.index()] = Some(Player {
+            name,
+            connection,
+            last_heard: Instant::now(),
+        });
+        if self.players.iter().any(Option::is_none) {
+            return;
+        }
+
+        // The rejoining player learns its side before the match it resumes
+        self.send(side, &self.seat_message(side));
+        if self.started {
+            // The match stood still while the side was empty, but the player that stayed
+            // may have predicted ahead; both go on from where it stopped
+            let world = self.session.world().clone();
+            self.broadcast(&Message::Resume(world.state_bytes()));
+            self.session = AuthoritySession::new(world);
+            self.vacated = None;
+        } else {
+            println!("Match started");
+            self.started = true;
+            self.send(side.opponent(), &self.seat_message(side.opponent()));
+        }
+        self.timestep.reset();
+        self.last_update = Instant::now();
+    }
+
+    fn seat_message(&self, side: Side) -> Message {
+        Message::Seat {
+            side,
+            opponent: String::from(self.name(side.opponent())),
+        }
+    }
+
+    fn drop_player(&mut self, side: Side, reason: &str) {
+        if let Some(player) = self.players[side.index()].take() {
+            println!("{} left: {}", player.name, reason);
+            if self.started && self.ended.is_none() {
+                self.vacated.get_or_insert(Instant::now());
+            }
+        }
+    }
+
+    fn send(&mut self, side: Side, message: &Message) {
+        if let Some(player) = &mut self.players[side.index()] {
+            if let Err(err) = player.connection.send(message) {
+                self.drop_player(side, &err.to_string());
+            }
+        }
+    }
+
+    fn broadcast(&mut self, message: &Message) {
+        self.send(Side::Top, message);
+        self.send(Side::Bottom, message);
+    }
+
+    fn flush(&mut self) {
+        for side in [Side::Top, Side::Bottom] {
+            if let Some(player) = &mut self.players[side.index()] {
+                if let Err(err) = player.connection.flush() {
+                    self.drop_player(side, &err.to_string());
+                }
+            }
+        }
+    }
+
+    // Takes in everything the players sent, and drops those gone quiet.
+    fn receive(&mut self) {
+        for side in [Side::Top, Side::Bottom] {
+            let Some(player) = &mut self.players[side.index()] else {
+                continue;
+            };
+            let messages = match player.connection.receive() {
+                Ok(messages) => messages,
+                Err(err) => {
+                    self.drop_player(side, &err.to_string());
+                    continue;
+                }
+            };
+            if !messages.is_empty() {
+                player.last_heard = Instant::now();
+            }
+            if player.last_heard.elapsed() > HEARTBEAT_TIMEOUT {
+                self.drop_player(side, "nothing heard for too long");
+                continue;
+            }
+            for message in messages {
+                match message {
+                    Message::Input { tick, input } if self.started => {
+                        self.session.add_input(side, tick, input);
+                    }
+                    Message::Ping(token) => self.send(side, &Message::Pong(token)),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    // Steps the match while both players are in, and streams where it got to.
+    fn update(&mut self) {
+        let now = Instant::now();
+        let dt = now.duration_since(self.last_update).as_secs_f32();
+        self.last_update = now;
+        if !self.started || self.vacated.is_some() || self.ended.is_some() {
+            return;
+        }
+
+        let steps = self.timestep.advance(dt);
+        for _ in 0..steps {
+            for event in self.session.advance([None; 2]) {
+                self.broadcast(&Message::Event(event));
+            }
+        }
+        if steps > 0 {
+            let time = self.clock.elapsed().as_secs_f32();
+            for side in [Side::Top, Side::Bottom] {
+                let character = self.session.world().character(side.opponent());
+                let state = Message::State {
+                    time,
+                    character: character.get_send_data(),
+                };
+                self.send(side, &state);
+                if let Some(correction) = self.session.correction(side) {
+                    self.send(side, &correction);
+                }
+            }
+        }
+
+        if let Some(winner) = self.session.world().winner() {
+            println!("{} won", self.name(winner));
+            self.ended = Some(now);
+        }
+    }
+
+    // Why the match is over, once it is.
+    fn end_reason(&self) -> Option<&'static str> {
+        if self.ended.is_some_and(|ended| ended.elapsed() > END_LINGER) {
+            Some("Match over")
+        } else if self.started && self.players.iter().all(Option::is_none) {
+            Some("Match given up: both players left")
+        } else if self
+            .vacated
+            .is_some_and(|vacated| vacated.elapsed() > REJOIN_TIMEOUT)
+        {
+            Some("Match given up: a player did not come back")
+        } else {
+            None
+        }
+    }
+}
+
+// Shakes hands with every player that connects, under the settings of the match being
+// played at the time, and passes the ones that agree on. Once it has started, only
+// players showing its token get that far.
+fn accept(listener: TcpListener, admission: Arc<Mutex<Admission>>, sender: Sender<Arrival>) {
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                println!("Accept failed: {}", err);
+                continue;
+            }
+        };
+        let Admission { settings, started } = *admission.lock().unwrap();
+        let sender = sender.clone();
+        // Off the listening thread, so a slow player holds no one else up
+        thread::spawn(move || {
+            let handshake = if started {
+                host_rejoin_handshake(&mut stream, SERVER_NAME, settings)
+            } else {
+                host_handshake(&mut stream, SERVER_NAME, settings)
+            };
+            match handshake {
+                Ok(handshake) => {
+                    let _ = sender.send(Arrival {
+                        stream,
+                        handshake,
+                        rejoined: started,
+                    });
+                }
+                Err(err) => println!("Handshake failed: {}", err),
+            }
+        });
+    }
+}
+
+fn play(game: &mut Match, receiver: &Receiver<Arrival>, admission: &Mutex<Admission>) {
+    loop {
+        while let Ok(arrival) = receiver.try_recv() {
+            game.seat(arrival);
+            *admission.lock().unwrap() = game.admission();
+        }
+        game.receive();
+        game.update();
+        game.flush();
+        if let Some(reason) = game.end_reason() {
+            println!("{}", reason);
+            return;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn main() -> ExitCode {
+    let addr = match env::args().nth(1) {
+        Some(addr) => match addr.parse() {
+            Ok(addr) => addr,
+            Err(_) => {
+                eprintln!("usage: match_server [<address>]");
+                return ExitCode::from(2);
+            }
+        },
+        None => SocketAddr::from(([0, 0, 0, 0], DEFAULT_PORT)),
+    };
+    let listener = match TcpListener::bind(addr) {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!("Cannot listen on {}: {}", addr, err);
+            return ExitCode::from(1);
+        }
+    };
+    println!(
+        "Match server listening on {}",
+        listener.local_addr().unwrap_or(addr)
+    );
+
+    let mut game = Match::new();
+    let admission = Arc::new(Mutex::new(game.admission()));
+    let (sender, receiver) = mpsc::channel();
+    {
+        let admission = admission.clone();
+        thread::spawn(move || accept(listener, admission, sender));
+    }
+    loop {
+        play(&mut game, &receiver, &admission);
+        // Everyone still connected is let go with the match
+        game = Match::new();
+        *admission.lock().unwrap() = game.admission();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use ggez_project::{guest_handshake, guest_rejoin_handshake, HandshakeError};
+
+    use super::*;
+
+    // A match taking players on 127.0.0.1:0, and where they connect.
+    fn start_match() -> (Match, Arc<Mutex<Admission>>, Receiver<Arrival>, SocketAddr) {
+        let game = Match::new();
+        let admission = Arc::new(Mutex::new(game.admission()));
+        let (sender, receiver) = mpsc::channel();
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        {
+            let admission = admission.clone();
+            thread::spawn(move || accept(listener, admission, sender));
+        }
+        (game, admission, receiver, addr)
+    }
+
+    // Seats the next player to shake hands, as play does.
+    fn seat_next(game: &mut Match, admission: &Mutex<Admission>, receiver: &Receiver<Arrival>) {
+        let arrival = receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+        game.seat(arrival);
+        *admission.lock().unwrap() = game.admission();
+    }
+
+    fn join(addr: SocketAddr, name: &str) -> Result<(TcpStream, Handshake), HandshakeError> {
+        let mut stream = TcpStream::connect(addr)?;
+        let handshake = guest_handshake(&mut stream, name)?;
+        Ok((stream, handshake))
+    }
+
+    fn rejoin(addr: SocketAddr, name: &str, token: u64) -> Result<TcpStream, HandshakeError> {
+        let mut stream = TcpStream::connect(addr)?;
+        guest_rejoin_handshake(&mut stream, name, token)?;
+        Ok(stream)
+    }
+
+    #[test]
+    fn keeps_a_vacated_side_for_the_player_that_left_it() {
+        let (mut game, admission, receiver, addr) = start_match();
+        let _top = join(addr, "Ann").unwrap();
+        seat_next(&mut game, &admission, &receiver);
+        let (bottom, handshake) = join(addr, "Bo").unwrap();
+        seat_next(&mut game, &admission, &receiver);
+        assert!(game.started);
+        let token = handshake.settings.token;
+
+        drop(bottom);
+        game.drop_player(Side::Bottom, "connection lost");
+        assert!(game.vacated.is_some());
+
+        // First in line, but without the token it never gets the settings
+        let stranger = thread::spawn(move || join(addr, "Stranger"));
+        thread::sleep(Duration::from_millis(100));
+        assert!(matches!(
+            rejoin(addr, "Impostor", token.wrapping_add(1)),
+            Err(HandshakeError::Protocol(_))
+        ));
+
+        let _bottom = rejoin(addr, "Bo", token).unwrap();
+        seat_next(&mut game, &admission, &receiver);
+        assert_eq!(game.name(Side::Bottom), "Bo");
+        assert!(game.vacated.is_none());
+
+        assert!(stranger.join().unwrap().is_err());
+        assert!(receiver.try_recv().is_err());
+    }
+}
@@ -0,0 +1,160 @@
+//! A relay that pairs players by room code and forwards their game between them, for
+//! peers that cannot connect to each other directly. Runs without a window.
+//!
+//! `relay_server [<address>]` listens on `0.0.0.0:9996` unless given another address,
+//! such as `127.0.0.1:0` to run one for local testing. It prints the address it listens
+//! on, then a line for each room opened, joined and closed.
+
+use std::collections::HashMap;
+use std::env;
+use std::io;
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use ggez_project::handshake::HANDSHAKE_TIMEOUT;
+use ggez_project::protocol::ProtocolError;
+use ggez_project::relay::{
+    parse_room_code, random_room_code, read_request, write_reply, RelayReply, RelayRequest,
+    DEFAULT_RELAY_PORT,
+};
+
+// A host waiting for its guest
+struct Room {
+    // Tells a room apart from a later one opened under the same code
+    id: u64,
+    host: TcpStream,
+}
+
+// Rooms waiting for their guest, by code
+type Rooms = Mutex<HashMap<String, Room>>;
+
+static NEXT_ROOM_ID: AtomicU64 = AtomicU64::new(0);
+
+// Opens a room for the host on `stream`, then waits with it until it is joined or the
+// host goes away, and closes it in that case.
+fn open(mut stream: TcpStream, code: Option<String>, rooms: &Rooms) -> Result<(), ProtocolError> {
+    // Hosts wait for as long as it takes to be joined
+    stream.set_read_timeout(None)?;
+    let watched = stream.try_clone()?;
+    let id = NEXT_ROOM_ID.fetch_add(1, Ordering::Relaxed);
+
+    let mut waiting = rooms.lock().unwrap();
+    let code = match code {
+        Some(code) => match parse_room_code(&code) {
+            Some(code) if !waiting.contains_key(&code) => code,
+            Some(code) => {
+                let reason = format!("room {} is taken", code);
+                return write_reply(&mut stream, &RelayReply::Refused(reason));
+            }
+            None => {
+                let reason = format!("`{}` is not a room code", code);
+                return write_reply(&mut stream, &RelayReply::Refused(reason));
+            }
+        },
+        None => loop {
+            let code = random_room_code(&mut rand::thread_rng());
+            if !waiting.contains_key(&code) {
+                break code;
+            }
+        },
+    };
+    // Still locked, so no guest is paired before the host hears its code
+    write_reply(&mut stream, &RelayReply::Opened(code.clone()))?;
+    println!("Room {} opened by {}", code, stream.peer_addr()?);
+    waiting.insert(code.clone(), Room { id, host: stream });
+    drop(waiting);
+
+    // A waiting host sends nothing; it only speaks once paired, for its handshake. Only
+    // this peek checks on the host, as its handles share one blocking mode
+    let host_left = !matches!(watched.peek(&mut [0]), Ok(read) if read > 0);
+    let mut waiting = rooms.lock().unwrap();
+    if host_left && waiting.get(&code).is_some_and(|room| room.id == id) {
+        waiting.remove(&code);
+        println!("Room {} closed: its host left", code);
+    }
+    Ok(())
+}
+
+// Copies everything `from` sends to `to` until either side goes away, then closes both.
+fn forward(mut from: TcpStream, mut to: TcpStream) {
+    let _ = io::copy(&mut from, &mut to);
+    let _ = from.shutdown(Shutdown::Both);
+    let _ = to.shutdown(Shutdown::Both);
+}
+
+fn join(mut guest: TcpStream, code: &str, rooms: &Rooms) -> Result<(), ProtocolError> {
+    let code = parse_room_code(code).unwrap_or_else(|| String::from(code));
+    let room = rooms.lock().unwrap().remove(&code);
+    let Some(Room { mut host, .. }) = room else {
+        let reason = format!("no room {} is waiting", code);
+        return write_reply(&mut guest, &RelayReply::Refused(reason));
+    };
+    // Players may go quiet for a while mid-match
+    host.set_read_timeout(None)?;
+    guest.set_read_timeout(None)?;
+    host.set_nodelay(true)?;
+    guest.set_nodelay(true)?;
+    write_reply(&mut host, &RelayReply::Paired)?;
+    write_reply(&mut guest, &RelayReply::Paired)?;
+    println!("Room {} joined from {}", code, guest.peer_addr()?);
+
+    let host2 = host.try_clone()?;
+    let guest2 = guest.try_clone()?;
+    thread::spawn(move || forward(host2, guest2));
+    forward(guest, host);
+    println!("Room {} closed", code);
+    Ok(())
+}
+
+fn serve(mut stream: TcpStream, rooms: &Rooms) -> Result<(), ProtocolError> {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    match read_request(&mut stream)? {
+        RelayRequest::Open(code) => open(stream, code, rooms),
+        RelayRequest::Join(code) => join(stream, &code, rooms),
+    }
+}
+
+fn main() -> ExitCode {
+    let addr = match env::args().nth(1) {
+        Some(addr) => match addr.parse() {
+            Ok(addr) => addr,
+            Err(_) => {
+                eprintln!("usage: relay_server [<address>]");
+                return ExitCode::from(2);
+            }
+        },
+        None => SocketAddr::from(([0, 0, 0, 0], DEFAULT_RELAY_PORT)),
+    };
+    let listener = match TcpListener::bind(addr) {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!("Cannot listen on {}: {}", addr, err);
+            return ExitCode::from(1);
+        }
+    };
+    println!(
+        "Relay listening on {}",
+        listener.local_addr().unwrap_or(addr)
+    );
+
+    let rooms: Arc<Rooms> = Arc::default();
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                println!("Accept failed: {}", err);
+                continue;
+            }
+        };
+        let rooms = rooms.clone();
+        thread::spawn(move || {
+            if let Err(err) = serve(stream, &rooms) {
+                println!("Player dropped: {}", err);
+            }
+        });
+    }
+    ExitCode::SUCCESS
+}
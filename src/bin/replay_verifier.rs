@@ -0,0 +1,117 @@
+//! Replays recorded matches without a window and checks them against the outcome stored
+//! in each recording.
+//!
+//! `replay_verifier [--allow-missing] <recording>...` exits with 1 if any replay ends
+//! differently from its recording, with 2 if a recording cannot be read, and with 3 if a
+//! recording has no outcome stored to check against, unless `--allow-missing` is given.
+
+use std::env;
+use std::process::ExitCode;
+
+use ggez_project::replay::{side_name, MatchOutcome};
+use ggez_project::{Replay, ReplayRunner, ScoreEvent};
+
+enum Verdict {
+    Agrees,
+    Differs,
+    // Nothing disagreed, but there was no outcome to check the end against
+    NoOutcome,
+}
+
+impl Verdict {
+    fn exit_code(&self, allow_missing: bool) -> u8 {
+        match self {
+            Verdict::Agrees => 0,
+            Verdict::Differs => 1,
+            Verdict::NoOutcome if allow_missing => 0,
+            Verdict::NoOutcome => 3,
+        }
+    }
+}
+
+fn print_score(score_event: &ScoreEvent) {
+    println!(
+        "  score tick {} {} {}",
+        score_event.tick,
+        side_name(score_event.side),
+        score_event.score
+    );
+}
+
+// Checks the replay against everything the recording stored.
+fn verify(path: &str, replay: &Replay) -> Verdict {
+    let mut runner = ReplayRunner::new(replay);
+    let mut score_events = Vec::new();
+    while !runner.is_finished() {
+        score_events.extend(runner.step());
+    }
+    let outcome = MatchOutcome::of(runner.world());
+
+    println!(
+        "{}: format {}, game {}",
+        path, replay.version, replay.header.game_version
+    );
+    for score_event in &score_events {
+        print_score(score_event);
+    }
+    println!(
+        "  final {} - {} at tick {}",
+        outcome.scores[0], outcome.scores[1], outcome.tick
+    );
+    println!("  state hash {:016x}", outcome.state_hash);
+
+    let mut verdict = Verdict::Agrees;
+    if !replay.scores.is_empty() && replay.scores != score_events {
+        println!("  MISMATCH: recorded score events were");
+        replay.scores.iter().for_each(print_score);
+        verdict = Verdict::Differs;
+    }
+    match &replay.outcome {
+        Some(recorded) if *recorded != outcome => {
+            println!(
+                "  MISMATCH: recorded final {} - {} at tick {}, state hash {:016x}",
+                recorded.scores[0], recorded.scores[1], recorded.tick, recorded.state_hash
+            );
+            verdict = Verdict::Differs;
+        }
+        Some(_) => {}
+        None => {
+            println!("  NO OUTCOME: the recording stored no outcome to compare against");
+            if let Verdict::Agrees = verdict {
+                verdict = Verdict::NoOutcome;
+            }
+        }
+    }
+    if let Verdict::Agrees = verdict {
+        println!("  OK");
+    }
+    verdict
+}
+
+fn main() -> ExitCode {
+    let mut paths: Vec<String> = env::args().skip(1).collect();
+    let allow_missing = paths.iter().any(|arg| arg == "--allow-missing");
+    paths.retain(|arg| arg != "--allow-missing");
+    if paths.is_empty() {
+        eprintln!("usage: replay_verifier [--allow-missing] <recording>...");
+        return ExitCode::from(2);
+    }
+
+    // A mismatch outweighs a missing outcome; an unreadable recording outweighs both
+    let mut code = 0;
+    for path in &paths {
+        match Replay::load(path) {
+            Ok(replay) => {
+                let verdict = verify(path, &replay).exit_code(allow_missing);
+                if code == 0 || (code == 3 && verdict == 1) {
+                    code = verdict;
+                }
+            }
+            Err(err) => {
+                eprintln!("{}: {}", path, err);
+                code = 2;
+            }
+        }
+    }
+    ExitCode::from(code)
+}
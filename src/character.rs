@@ -0,0 +1,237 @@
+use ggez::mint::Point2;
+
+use rand::Rng;
+
+use std::f32::consts::PI;
+
+use crate::communication::Communication;
+use crate::flash::Flash;
+use crate::game_object::GameObject;
+use crate::grab::Grab;
+use crate::input::Input;
+use crate::target::Target;
+use crate::transform::Transform;
+use crate::world::MatchRng;
+
+/// Which end of the arena a character spawns on, in world space.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum Side {
+    Top,
+    Bottom,
+}
+
+impl Side {
+    pub fn opponent(self) -> Side {
+        match self {
+            Side::Top => Side::Bottom,
+            Side::Bottom => Side::Top,
+        }
+    }
+
+    pub fn index(self) -> usize {
+        match self {
+            Side::Top => 0,
+            Side::Bottom => 1,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Character {
+    pub gameobject: GameObject,
+    pub side: Side,
+    pub input: Input,
+    pub move_state: f32,
+    pub move_speed: f32,
+    pub score: i32,
+
+    pub target: Target,
+    pub grab: Grab,
+    pub is_grabbed_by: bool,
+    pub flash: Flash,
+}
+
+impl Character {
+    pub fn new(side: Side) -> Character {
+        let mut target = Target::new();
+        if side == Side::Bottom {
+            target.direction = -1.0;
+        }
+
+        let mut character = Character {
+            gameobject: GameObject::new(),
+            side,
+            input: Input::NONE,
+            move_state: 0.0,
+            move_speed: 300.0,
+            score: 0,
+
+            target,
+            grab: Grab::new(),
+            is_grabbed_by: false,
+            flash: Flash::new(),
+        };
+        character.rebirth(None);
+        character
+    }
+
+    /// Puts the character back on its spawn line, at a random x drawn from `rng` or at
+    /// the centre when there is none.
+    pub fn rebirth(&mut self, rng: Option<&mut MatchRng>) {
+        self.rebirth_at(match rng {
+            Some(rng) => rng.gen_range(-340.0..340.0),
+            None => 0.0,
+        });
+    }
+
+    /// Puts the character back on its spawn line at `x`.
+    pub fn rebirth_at(&mut self, x: f32) {
+        self.gameobject.transform.position = Point2 {
+            x,
+            y: match self.side {
+                Side::Top => -290.0,
+                Side::Bottom => 290.0,
+            },
+        };
+        self.gameobject.transform.rotation = match self.side {
+            Side::Top => PI / 2.0,
+            Side::Bottom => -PI / 2.0,
+        };
+        self.gameobject.update_global_transform(None);
+        self.is_grabbed_by = false;
+    }
+
+    pub fn set_global_rotation(&mut self, rotation: f32) {
+        self.gameobject.global_transform.rotation = rotation;
+        self.gameobject.update_local_transform(None);
+    }
+
+    pub fn set_global_position(&mut self, position: Point2<f32>) {
+        self.gameobject.global_transform.position = position;
+        self.gameobject.update_local_transform(None);
+    }
+
+    pub fn get_global_position(&self) -> Point2<f32> {
+        self.gameobject.global_transform.position
+    }
+
+    /// Recomputes the world transforms of the target and grab hand.
+    pub fn update_children_global_transform(&mut self) {
+        self.gameobject.update_global_transform(None);
+        let parent = self.gameobject.global_transform;
+        self.target.gameobject.update_global_transform(Some(&parent));
+        self.grab.gameobject.update_global_transform(Some(&parent));
+    }
+
+    /// Blends the visible transforms towards `next` for drawing between two steps.
+    pub fn lerp(&self, next: &Character, alpha: f32) -> Character {
+        let mut character = next.clone();
+        character.gameobject = self.gameobject.lerp(&next.gameobject, alpha);
+        character.target.gameobject = self.target.gameobject.lerp(&next.target.gameobject, alpha);
+        // A new throw restarts at the character, so only blend a hand already in flight
+        if self.grab.state != 0.0 && next.grab.state != 0.0 {
+            character.grab.gameobject = self.grab.gameobject.lerp(&next.grab.gameobject, alpha);
+        }
+        character
+    }
+
+    /// Applies the buttons held for this step. Grab and flash fire on the step they
+    /// are first pressed.
+    pub fn apply_input(&mut self, input: Input) {
+        let previous = self.input;
+        self.input = input;
+
+        if input.grab && !previous.grab && self.grab.state == 0.0 {
+            self.grab.state = 1.0;
+
+            self.grab.set_position(Transform::rotate_point(
+                Point2 { x: 35.0, y: 60.0 },
+                self.target.get_rotation(),
+            ));
+            self.grab.set_rotation(self.target.get_rotation());
+        }
+
+        self.move_state = input.move_state();
+
+        if input.flash && !previous.flash {
+            self.flash.use_skill(&mut self.gameobject, self.move_state);
+        }
+    }
+
+    /// Advances this character by `dt` seconds. The grab hand may catch, drag and
+    /// respawn `opponent` using the match `rng`; see [`Grab::update`] for `resolve_grabs`.
+    pub fn update(
+        &mut self,
+        dt: f32,
+        opponent: &mut Character,
+        rng: &mut MatchRng,
+        resolve_grabs: bool,
+    ) {
+        if !self.is_grabbed_by {
+            let speed = dt * self.move_state * self.move_speed * (1.0 - self.grab.state.abs());
+            self.target.speed = 800.0 * (1.0 - self.grab.state.abs());
+
+            let delta_vec = self.gameobject.transform.right();
+            self.gameobject.transform.position.x += speed * delta_vec.x;
+            self.gameobject.transform.position.x =
+                self.gameobject.transform.position.x.clamp(-340.0, 340.0);
+            self.gameobject.update_global_transform(None);
+
+            if self.grab.check_grab_once {
+                self.score += 1;
+                self.grab.check_grab_once = false;
+            }
+
+            let parent = self.gameobject.global_transform;
+            self.target.update(dt, &parent);
+            self.grab.update(dt, &parent, opponent, rng, resolve_grabs);
+        } else {
+            self.grab.state = 0.0;
+            self.move_state = 0.0;
+        }
+        self.flash.update(dt);
+    }
+}
+
+/// Length of [`Character::get_send_data`](Communication::get_send_data).
+pub const CHARACTER_DATA_LEN: usize = 72;
+
+impl Communication for Character {
+    fn get_send_data(&self) -> Vec<u8> {
+        let mut data = vec![];
+        let ip = (self.input.to_bits() as u32).to_le_bytes();
+        let sc = self.score.to_le_bytes();
+        let go = self.gameobject.get_send_data();
+
+        data.extend_from_slice(&ip);
+        data.extend_from_slice(&sc);
+        data.extend(go);
+
+        data.extend(self.target.get_send_data());
+        data.extend(self.grab.get_send_data());
+        data.extend(self.flash.get_send_data());
+
+        data
+    }
+
+    fn set_recv_data(&mut self, buf: &[u8]) {
+        let (data, buf) = buf.split_at(8);
+        self.input = Input::from_bits(u32::from_le_bytes(data[0..4].try_into().unwrap()) as u8);
+        self.move_state = self.input.move_state();
+        self.score = i32::from_le_bytes(data[4..8].try_into().unwrap());
+
+        let (data, buf) = buf.split_at(20);
+        self.gameobject.set_recv_data(data);
+
+        let (data, buf) = buf.split_at(4);
+        self.target.set_recv_data(data);
+
+        let (data, buf) = buf.split_at(28);
+        self.grab.set_recv_data(data);
+
+        let (data, _) = buf.split_at(12);
+        self.flash.set_recv_data(data);
+
+        self.update_children_global_transform();
+    }
+}
@@ -0,0 +1,6 @@
+/// Little-endian byte serialization used to mirror a game object on the remote peer.
+pub trait Communication {
+    fn get_send_data(&self) -> Vec<u8>;
+    /// `buf` holds exactly what `get_send_data` produced.
+    fn set_recv_data(&mut self, buf: &[u8]);
+}
@@ -0,0 +1,227 @@
+//! Finding games hosted on the local network without typing an address.
+//!
+//! A waiting host has an [`Announcer`] broadcast a small datagram every
+//! [`ANNOUNCE_INTERVAL`]: a magic tag, the port its game listens on and its [`Hello`].
+//! A guest's [`Browser`] listens for them on [`DISCOVERY_PORT`] and keeps a list of the
+//! games heard from recently. The game's address is the datagram's source address with
+//! the announced port, so hosts never have to know their own address.
+
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::protocol::{Hello, Message, LEN_PREFIX, MAX_FRAME_LEN};
+
+/// Port browsers listen on for announcements.
+pub const DISCOVERY_PORT: u16 = 9998;
+
+/// Time between two announcements of the same game.
+pub const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(1);
+
+// A game not announced for this long has stopped waiting or gone away
+const GAME_TIMEOUT: Duration = Duration::from_secs(3);
+
+// Tells announcements apart from anything else sent to the port
+const MAGIC: &[u8; 4] = b"GGEZ";
+
+/// A game heard from on the local network.
+#[derive(Clone, Debug)]
+pub struct DiscoveredGame {
+    /// Where a guest connects to play it.
+    pub addr: SocketAddr,
+    pub hello: Hello,
+    last_seen: Instant,
+}
+
+impl DiscoveredGame {
+    /// Whether this build can play the game.
+    pub fn is_compatible(&self) -> bool {
+        Hello::local("").is_compatible(&self.hello)
+    }
+}
+
+/// Tells the local network about a game waiting for a guest.
+pub struct Announcer {
+    socket: UdpSocket,
+    target: SocketAddr,
+    datagram: Vec<u8>,
+    last_sent: Option<Instant>,
+}
+
+impl Announcer {
+    /// Announces the game listening on `game_addr`, hosted by `name`. A game only
+    /// reachable through loopback is announced on loopback, anything else by broadcast.
+    pub fn new(name: &str, game_addr: SocketAddr) -> io::Result<Announcer> {
+        let target = if game_addr.ip().is_loopback() {
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), DISCOVERY_PORT)
+        } else {
+            SocketAddr::new(Ipv4Addr::BROADCAST.into(), DISCOVERY_PORT)
+        };
+        Announcer::with_target(name, game_addr, target)
+    }
+
+    /// Like [`new`](Self::new), sending the announcements to `target`.
+    pub fn with_target(
+        name: &str,
+        game_addr: SocketAddr,
+        target: SocketAddr,
+    ) -> io::Result<Announcer> {
+        // Sent from the game's own address, which browsers take as where to connect
+        let socket = UdpSocket::bind(SocketAddr::new(game_addr.ip(), 0))?;
+        socket.set_broadcast(true)?;
+
+        let mut datagram = Vec::from(&MAGIC[..]);
+        datagram.extend_from_slice(&game_addr.port().to_le_bytes());
+        datagram.extend_from_slice(&Message::Hello(Hello::local(name)).encode()[LEN_PREFIX..]);
+        Ok(Announcer {
+            socket,
+            target,
+            datagram,
+            last_sent: None,
+        })
+    }
+
+    /// Sends an announcement if the last one is [`ANNOUNCE_INTERVAL`] old. Called every
+    /// frame while the game waits.
+    pub fn poll(&mut self) -> io::Result<()> {
+        if self
+            .last_sent
+            .is_some_and(|sent| sent.elapsed() < ANNOUNCE_INTERVAL)
+        {
+            return Ok(());
+        }
+        self.socket.send_to(&self.datagram, self.target)?;
+        self.last_sent = Some(Instant::now());
+        Ok(())
+    }
+}
+
+/// Collects the games announced on the local network.
+pub struct Browser {
+    socket: UdpSocket,
+    games: Vec<DiscoveredGame>,
+}
+
+impl Browser {
+    /// Listens for announcements on [`DISCOVERY_PORT`] on every interface.
+    pub fn new() -> io::Result<Browser> {
+        Browser::bind(SocketAddr::new(
+            Ipv4Addr::UNSPECIFIED.into(),
+            DISCOVERY_PORT,
+        ))
+    }
+
+    /// Listens for announcements on `addr`.
+    pub fn bind(addr: SocketAddr) -> io::Result<Browser> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(Browser {
+            socket,
+            games: Vec::new(),
+        })
+    }
+
+    /// Where announcements are received, useful when bound to port 0.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Takes every announcement received since the last call, and returns the games
+    /// heard from lately, in the order they were first heard.
+    pub fn poll(&mut self) -> io::Result<&[DiscoveredGame]> {
+        let mut buf = [0; MAX_FRAME_LEN + MAGIC.len() + 2];
+        loop {
+            let (len, from) = match self.socket.recv_from(&mut buf) {
+                Ok(res) => res,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            };
+            // Anything that is not an announcement is someone else's traffic
+            if let Some((port, hello)) = parse_announcement(&buf[..len]) {
+                self.hear(SocketAddr::new(from.ip(), port), hello);
+            }
+        }
+        self.games
+            .retain(|game| game.last_seen.elapsed() < GAME_TIMEOUT);
+        Ok(&self.games)
+    }
+
+    fn hear(&mut self, addr: SocketAddr, hello: Hello) {
+        let last_seen = Instant::now();
+        match self.games.iter_mut().find(|game| game.addr == addr) {
+            Some(game) => {
+                game.hello = hello;
+                game.last_seen = last_seen;
+            }
+            None => self.games.push(DiscoveredGame {
+                addr,
+                hello,
+                last_seen,
+            }),
+        }
+    }
+}
+
+fn parse_announcement(datagram: &[u8]) -> Option<(u16, Hello)> {
+    let body = datagram.strip_prefix(&MAGIC[..])?;
+    let port = u16::from_le_bytes(body.get(..2)?.try_into().ok()?);
+    match Message::decode(&body[2..]) {
+        Ok(Message::Hello(hello)) => Some((port, hello)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    const GAME_ADDR: ([u8; 4], u16) = ([127, 0, 0, 1], 4242);
+
+    fn browser() -> Browser {
+        Browser::bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap()
+    }
+
+    // Polls `browser` until it lists a game, for up to a second.
+    fn wait_for_games(browser: &mut Browser) -> Vec<DiscoveredGame> {
+        let deadline = Instant::now() + Duration::from_secs(1);
+        loop {
+            let games = browser.poll().unwrap().to_vec();
+            if !games.is_empty() || Instant::now() > deadline {
+                return games;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn finds_an_announced_game_until_it_times_out() {
+        let mut browser = browser();
+        let game_addr = SocketAddr::from(GAME_ADDR);
+        let mut announcer =
+            Announcer::with_target("Host", game_addr, browser.local_addr().unwrap()).unwrap();
+        announcer.poll().unwrap();
+
+        let games = wait_for_games(&mut browser);
+        assert_eq!(games.len(), 1);
+        assert_eq!(games[0].addr, game_addr);
+        assert_eq!(games[0].hello.name, "Host");
+        assert!(games[0].is_compatible());
+
+        // Announced again only once the interval is up
+        announcer.poll().unwrap();
+        thread::sleep(GAME_TIMEOUT + Duration::from_millis(200));
+        assert!(browser.poll().unwrap().is_empty());
+    }
+
+    #[test]
+    fn ignores_other_traffic() {
+        let mut browser = browser();
+        let socket = UdpSocket::bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        let target = browser.local_addr().unwrap();
+        socket.send_to(b"hello", target).unwrap();
+        socket.send_to(b"GGEZ\x01\x00garbage", target).unwrap();
+        assert!(wait_for_games(&mut browser).is_empty());
+    }
+}
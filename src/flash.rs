@@ -0,0 +1,68 @@
+use crate::communication::Communication;
+use crate::game_object::GameObject;
+
+/// Short-range teleport skill. `gameobject` marks where the last flash started.
+#[derive(Clone, Debug)]
+pub struct Flash {
+    pub gameobject: GameObject,
+    pub cooltime: f32,
+    pub cooldown: f32,
+    pub distance: f32,
+}
+
+impl Default for Flash {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Flash {
+    pub fn new() -> Flash {
+        Flash {
+            gameobject: GameObject::new(),
+            cooltime: 5.0,
+            cooldown: 0.0,
+            distance: 200.0,
+        }
+    }
+
+    pub fn use_skill(&mut self, subject: &mut GameObject, direction: f32) {
+        if self.cooldown > 0.0 || direction == 0.0 {
+            return;
+        }
+        self.cooldown = self.cooltime;
+
+        self.gameobject.transform.position = subject.transform.position;
+        let direction = subject.transform.right().x * direction;
+        subject.transform.move_offset_x(direction * self.distance);
+
+        self.gameobject.update_global_transform(None);
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        self.cooldown = (self.cooldown - dt).max(0.0);
+    }
+}
+
+impl Communication for Flash {
+    fn get_send_data(&self) -> Vec<u8> {
+        let mut data = vec![];
+        let position = self.gameobject.transform.position;
+        let cd = self.cooldown.to_le_bytes();
+        let px = position.x.to_le_bytes();
+        let py = position.y.to_le_bytes();
+
+        data.extend_from_slice(&cd);
+        data.extend_from_slice(&px);
+        data.extend_from_slice(&py);
+
+        data
+    }
+
+    fn set_recv_data(&mut self, buf: &[u8]) {
+        self.cooldown = f32::from_le_bytes(buf[0..4].try_into().unwrap());
+        self.gameobject.transform.position.x = f32::from_le_bytes(buf[4..8].try_into().unwrap());
+        self.gameobject.transform.position.y = f32::from_le_bytes(buf[8..12].try_into().unwrap());
+        self.gameobject.update_global_transform(None);
+    }
+}
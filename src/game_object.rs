@@ -0,0 +1,84 @@
+use crate::communication::Communication;
+use crate::transform::Transform;
+
+// Anything moving further in one step teleported (flash, respawn) and is not blended.
+const MAX_LERP_DISTANCE: f32 = 100.0;
+
+/// A transform relative to its owner plus the cached world-space result.
+///
+/// Parents are passed in explicitly instead of being linked, so whole game objects can
+/// be cloned into snapshots.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct GameObject {
+    pub transform: Transform,
+    pub global_transform: Transform,
+}
+
+impl GameObject {
+    pub fn new() -> GameObject {
+        GameObject {
+            transform: Transform::new(),
+            global_transform: Transform::new(),
+        }
+    }
+
+    pub fn update_global_transform(&mut self, parent: Option<&Transform>) {
+        self.global_transform = match parent {
+            Some(parent) => self.transform.to_global(parent),
+            None => self.transform,
+        };
+    }
+
+    pub fn update_local_transform(&mut self, parent: Option<&Transform>) {
+        self.transform = match parent {
+            Some(parent) => self.global_transform.to_local(parent),
+            None => self.global_transform,
+        };
+    }
+
+    /// Blends towards `other`, snapping instead when it moved further than a step allows.
+    pub fn lerp(&self, other: &GameObject, alpha: f32) -> GameObject {
+        let dx = other.global_transform.position.x - self.global_transform.position.x;
+        let dy = other.global_transform.position.y - self.global_transform.position.y;
+        if dx.abs() > MAX_LERP_DISTANCE || dy.abs() > MAX_LERP_DISTANCE {
+            return *other;
+        }
+        GameObject {
+            transform: self.transform.lerp(&other.transform, alpha),
+            global_transform: self.global_transform.lerp(&other.global_transform, alpha),
+        }
+    }
+}
+
+impl Communication for GameObject {
+    fn get_send_data(&self) -> Vec<u8> {
+        let mut data = vec![];
+
+        let px = self.transform.position.x.to_le_bytes();
+        let py = self.transform.position.y.to_le_bytes();
+        let r = self.transform.rotation.to_le_bytes();
+        let sx = self.transform.scale.x.to_le_bytes();
+        let sy = self.transform.scale.y.to_le_bytes();
+
+        data.extend_from_slice(&px);
+        data.extend_from_slice(&py);
+        data.extend_from_slice(&r);
+        data.extend_from_slice(&sx);
+        data.extend_from_slice(&sy);
+        data
+    }
+
+    // Only the local transform is received; the owner refreshes the global one.
+    fn set_recv_data(&mut self, buf: &[u8]) {
+        let px = f32::from_le_bytes(buf[0..4].try_into().unwrap());
+        let py = f32::from_le_bytes(buf[4..8].try_into().unwrap());
+        let r = f32::from_le_bytes(buf[8..12].try_into().unwrap());
+        let sx = f32::from_le_bytes(buf[12..16].try_into().unwrap());
+        let sy = f32::from_le_bytes(buf[16..20].try_into().unwrap());
+        self.transform.position.x = px;
+        self.transform.position.y = py;
+        self.transform.rotation = r;
+        self.transform.scale.x = sx;
+        self.transform.scale.y = sy;
+    }
+}
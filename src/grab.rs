@@ -0,0 +1,124 @@
+use ggez::mint::Point2;
+
+use std::f32::consts::PI;
+
+use crate::character::Character;
+use crate::communication::Communication;
+use crate::game_object::GameObject;
+use crate::transform::Transform;
+use crate::world::MatchRng;
+
+//' state {0: nothing, 1: throw, -1: catched}
+#[derive(Clone, Debug)]
+pub struct Grab {
+    pub gameobject: GameObject,
+    pub threshold: f32,
+    pub speed: f32,
+    pub state: f32,
+    pub check_grab_once: bool,
+}
+
+impl Default for Grab {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Grab {
+    pub fn new() -> Grab {
+        Grab {
+            gameobject: GameObject::new(),
+            threshold: 580.0,
+            speed: 800.0,
+            state: 0.0,
+            check_grab_once: false,
+        }
+    }
+
+    pub fn set_rotation(&mut self, rotation: f32) {
+        self.gameobject.transform.rotation = rotation;
+    }
+
+    pub fn set_position(&mut self, position: Point2<f32>) {
+        self.gameobject.transform.position = position;
+    }
+
+    /// Locks `target` onto the hand so it is dragged back.
+    pub fn catch(&mut self, target: &mut Character) {
+        self.state = -1.0;
+        target.set_global_rotation(self.gameobject.global_transform.rotation - PI);
+        target.is_grabbed_by = true;
+    }
+
+    /// Moves the hand by `dt` seconds and resolves hits against `target`.
+    /// `parent` is the owning character's transform; `rng` picks the respawn position.
+    ///
+    /// Without `resolve` a thrown hand waits at full reach, and a dropped target waits
+    /// where it is, for the peer with authority to decide.
+    pub fn update(
+        &mut self,
+        dt: f32,
+        parent: &Transform,
+        target: &mut Character,
+        rng: &mut MatchRng,
+        resolve: bool,
+    ) {
+        if !resolve && self.state == 1.0 && self.gameobject.transform.position.x > self.threshold {
+            return;
+        }
+
+        let speed = dt * self.speed * self.state;
+        let delta_vec = self.gameobject.transform.forward();
+        self.gameobject.transform.position.x += speed * delta_vec.x;
+        self.gameobject.transform.position.y += speed * delta_vec.y;
+
+        self.gameobject.update_global_transform(Some(parent));
+
+        let gameobject = &self.gameobject;
+        if self.state == 1.0 && gameobject.transform.position.x > self.threshold {
+            if !resolve {
+                return;
+            }
+            // Check target is in grab range (80.0)
+            if (target.get_global_position().x - gameobject.global_transform.position.x).abs()
+                < 80.0
+            {
+                self.catch(target);
+            } else {
+                self.state = 0.0;
+            }
+        } else if self.state == -1.0 {
+            if gameobject.transform.position.x < 0.0 {
+                self.state = 0.0;
+                self.check_grab_once = resolve;
+            } else {
+                // target position is same with grab position
+                target.set_global_position(gameobject.global_transform.position);
+            }
+        } else if self.state == 0.0 && target.is_grabbed_by && resolve {
+            target.rebirth(Some(rng));
+        }
+    }
+}
+
+impl Communication for Grab {
+    fn get_send_data(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        let sp = self.speed.to_le_bytes();
+        let st = self.state.to_le_bytes();
+        let go = self.gameobject.get_send_data();
+
+        data.extend_from_slice(&sp);
+        data.extend_from_slice(&st);
+        data.extend(go);
+        data
+    }
+
+    fn set_recv_data(&mut self, buf: &[u8]) {
+        let (data, buf) = buf.split_at(8);
+        self.speed = f32::from_le_bytes(data[0..4].try_into().unwrap());
+        self.state = f32::from_le_bytes(data[4..8].try_into().unwrap());
+
+        self.gameobject.set_recv_data(buf);
+    }
+}
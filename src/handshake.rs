@@ -0,0 +1,171 @@
+//! Agreement between host and guest before a match starts.
+//!
+//! Both peers send a [`Hello`] as soon as the stream is open and check the other's. When
+//! they are compatible the host follows with the [`MatchSettings`]; otherwise both sides
+//! stop without exchanging anything else. A guest coming back to a match shows its token
+//! first, so that no one else can take its place.
+
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, TcpStream, UdpSocket};
+use std::time::Duration;
+
+use crate::protocol::{
+    read_message, write_message, Connection, Hello, MatchSettings, Message, ProtocolError,
+    Transport, TransportKind,
+};
+use crate::udp::UdpConnection;
+
+/// How long a peer may take to answer before the handshake is given up.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Outcome of a successful handshake.
+#[derive(Clone, Debug)]
+pub struct Handshake {
+    pub peer: Hello,
+    pub settings: MatchSettings,
+}
+
+#[derive(Debug)]
+pub enum HandshakeError {
+    Protocol(ProtocolError),
+    /// The peer runs a different protocol or game version.
+    Incompatible {
+        local: Hello,
+        peer: Hello,
+    },
+    /// The peer sent something out of turn.
+    Unexpected(&'static str),
+    /// The peer tried to rejoin without the token of the match being resumed.
+    NotInMatch,
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HandshakeError::Protocol(err) => write!(f, "{}", err),
+            HandshakeError::Incompatible { local, peer } => write!(
+                f,
+                "incompatible version: {} runs {} (protocol {}), this game is {} (protocol {})",
+                peer.name,
+                peer.game_version,
+                peer.protocol_version,
+                local.game_version,
+                local.protocol_version
+            ),
+            HandshakeError::Unexpected(expected) => {
+                write!(f, "expected {} from the peer", expected)
+            }
+            HandshakeError::NotInMatch => write!(f, "the peer did not play this match"),
+        }
+    }
+}
+
+impl std::error::Error for HandshakeError {}
+
+impl From<ProtocolError> for HandshakeError {
+    fn from(err: ProtocolError) -> Self {
+        HandshakeError::Protocol(err)
+    }
+}
+
+impl From<io::Error> for HandshakeError {
+    fn from(err: io::Error) -> Self {
+        HandshakeError::Protocol(ProtocolError::Io(err))
+    }
+}
+
+// Exchanges hellos and returns the peer's once it is known to be compatible.
+fn exchange_hellos(stream: &mut TcpStream, local: Hello) -> Result<Hello, HandshakeError> {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    write_message(stream, &Message::Hello(local.clone()))?;
+    let peer = match read_message(stream)? {
+        Message::Hello(peer) => peer,
+        _ => return Err(HandshakeError::Unexpected("a hello")),
+    };
+    if !local.is_compatible(&peer) {
+        return Err(HandshakeError::Incompatible { local, peer });
+    }
+    Ok(peer)
+}
+
+/// Host side: agrees on versions, then hands `settings` to the guest.
+pub fn host_handshake(
+    stream: &mut TcpStream,
+    name: &str,
+    settings: MatchSettings,
+) -> Result<Handshake, HandshakeError> {
+    let peer = exchange_hellos(stream, Hello::local(name))?;
+    write_message(stream, &Message::Settings(settings))?;
+    stream.set_read_timeout(None)?;
+    Ok(Handshake { peer, settings })
+}
+
+/// Host side of a guest reconnecting: agrees on versions, then hands `settings` over only
+/// if the guest shows their token.
+pub fn host_rejoin_handshake(
+    stream: &mut TcpStream,
+    name: &str,
+    settings: MatchSettings,
+) -> Result<Handshake, HandshakeError> {
+    let peer = exchange_hellos(stream, Hello::local(name))?;
+    match read_message(stream)? {
+        Message::Rejoin(token) if token == settings.token => {}
+        Message::Rejoin(_) => return Err(HandshakeError::NotInMatch),
+        _ => return Err(HandshakeError::Unexpected("the match token")),
+    }
+    write_message(stream, &Message::Settings(settings))?;
+    stream.set_read_timeout(None)?;
+    Ok(Handshake { peer, settings })
+}
+
+/// Guest side: agrees on versions, then takes the host's settings.
+pub fn guest_handshake(stream: &mut TcpStream, name: &str) -> Result<Handshake, HandshakeError> {
+    let peer = exchange_hellos(stream, Hello::local(name))?;
+    read_settings(stream, peer)
+}
+
+/// Guest side of reconnecting to the match with `token`: agrees on versions, shows the
+/// token, then takes the host's settings.
+pub fn guest_rejoin_handshake(
+    stream: &mut TcpStream,
+    name: &str,
+    token: u64,
+) -> Result<Handshake, HandshakeError> {
+    let peer = exchange_hellos(stream, Hello::local(name))?;
+    write_message(stream, &Message::Rejoin(token))?;
+    read_settings(stream, peer)
+}
+
+fn read_settings(stream: &mut TcpStream, peer: Hello) -> Result<Handshake, HandshakeError> {
+    let settings = match read_message(stream)? {
+        Message::Settings(settings) => settings,
+        _ => return Err(HandshakeError::Unexpected("the match settings")),
+    };
+    stream.set_read_timeout(None)?;
+    Ok(Handshake { peer, settings })
+}
+
+/// Moves the match off the handshake's `stream` onto the agreed `transport`. For UDP
+/// both peers bind a socket next to their TCP end and swap its port over `stream`.
+pub fn open_transport(
+    mut stream: TcpStream,
+    transport: TransportKind,
+) -> Result<Box<dyn Transport>, HandshakeError> {
+    if transport == TransportKind::Tcp {
+        let connection = Connection::new(stream)?;
+        return Ok(Box::new(connection));
+    }
+
+    let socket = UdpSocket::bind(SocketAddr::new(stream.local_addr()?.ip(), 0))?;
+    let port = socket.local_addr()?.port();
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    write_message(&mut stream, &Message::UdpPort(port))?;
+    let peer_port = match read_message(&mut stream)? {
+        Message::UdpPort(port) => port,
+        _ => return Err(HandshakeError::Unexpected("a UDP port")),
+    };
+    let peer = SocketAddr::new(stream.peer_addr()?.ip(), peer_port);
+    let connection = UdpConnection::new(socket, peer)?;
+    Ok(Box::new(connection))
+}
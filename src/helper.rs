@@ -0,0 +1,23 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use ggez::graphics::Image;
+use ggez::Context;
+
+pub fn load_image(
+    ctx: &mut Context,
+    path: String,
+    image_pool: &mut HashMap<String, Rc<Image>>,
+) -> Rc<Image> {
+    match image_pool.get(&path) {
+        Some(image) => Rc::clone(image),
+        None => match Image::new(ctx, path.clone()) {
+            Ok(res) => {
+                let image = Rc::new(res);
+                image_pool.insert(path, Rc::clone(&image));
+                image
+            }
+            Err(err) => panic!("Failed to load image: {}", err),
+        },
+    }
+}
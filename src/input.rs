@@ -0,0 +1,108 @@
+use ggez::event::KeyCode;
+
+/// Buttons one character holds during a simulation step.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Hash)]
+pub struct Input {
+    pub left: bool,
+    pub right: bool,
+    pub grab: bool,
+    pub flash: bool,
+}
+
+impl Input {
+    pub const NONE: Input = Input {
+        left: false,
+        right: false,
+        grab: false,
+        flash: false,
+    };
+
+    /// -1.0, 0.0 or 1.0 along the character's right axis. Right wins when both are held.
+    pub fn move_state(&self) -> f32 {
+        if self.right {
+            1.0
+        } else if self.left {
+            -1.0
+        } else {
+            0.0
+        }
+    }
+
+    pub fn to_bits(self) -> u8 {
+        (self.left as u8) | (self.right as u8) << 1 | (self.grab as u8) << 2 | (self.flash as u8) << 3
+    }
+
+    pub fn from_bits(bits: u8) -> Input {
+        Input {
+            left: bits & 1 != 0,
+            right: bits & 1 << 1 != 0,
+            grab: bits & 1 << 2 != 0,
+            flash: bits & 1 << 3 != 0,
+        }
+    }
+
+    /// Buttons in the order replay actions number them from 1: left, right, grab, flash.
+    pub fn buttons(self) -> [bool; 4] {
+        [self.left, self.right, self.grab, self.flash]
+    }
+
+    /// Applies a replay action: `n` presses button `n` of [`buttons`](Self::buttons) and
+    /// `-n` releases it. Unknown actions are ignored.
+    pub fn apply_action(&mut self, action: i32) {
+        let button = match action.abs() {
+            1 => &mut self.left,
+            2 => &mut self.right,
+            3 => &mut self.grab,
+            4 => &mut self.flash,
+            _ => return,
+        };
+        *button = action > 0;
+    }
+
+    fn button_mut(&mut self, keycode: KeyCode) -> Option<&mut bool> {
+        match keycode {
+            KeyCode::Left => Some(&mut self.left),
+            KeyCode::Right => Some(&mut self.right),
+            KeyCode::Space => Some(&mut self.grab),
+            KeyCode::LShift => Some(&mut self.flash),
+            _ => None,
+        }
+    }
+}
+
+/// Turns keyboard events into one [`Input`] per simulation step.
+///
+/// A key pressed and released between two samples still shows up as held for one step,
+/// so quick taps are never lost.
+#[derive(Default)]
+pub struct KeyboardInput {
+    held: Input,
+    pressed: Input,
+}
+
+impl KeyboardInput {
+    pub fn new() -> KeyboardInput {
+        KeyboardInput::default()
+    }
+
+    pub fn key_down(&mut self, keycode: KeyCode) {
+        if let Some(held) = self.held.button_mut(keycode) {
+            *held = true;
+        }
+        if let Some(pressed) = self.pressed.button_mut(keycode) {
+            *pressed = true;
+        }
+    }
+
+    pub fn key_up(&mut self, keycode: KeyCode) {
+        if let Some(held) = self.held.button_mut(keycode) {
+            *held = false;
+        }
+    }
+
+    pub fn sample(&mut self) -> Input {
+        let input = Input::from_bits(self.held.to_bits() | self.pressed.to_bits());
+        self.pressed = Input::NONE;
+        input
+    }
+}
@@ -0,0 +1,102 @@
+//! Smooth drawing of a character streamed from the other peer.
+//!
+//! States arrive unevenly, and some not at all. Instead of jumping to each one as it
+//! comes in, the remote character is drawn a fixed delay in the past, blended between
+//! the two states received around that moment. The delay only has to cover the jitter,
+//! not the whole trip.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::character::Character;
+
+/// How far in the past the remote character is drawn by default.
+pub const DEFAULT_INTERPOLATION_DELAY: Duration = Duration::from_millis(100);
+
+// About a second of states at the tick rate, far more than any sensible delay needs
+const MAX_SNAPSHOTS: usize = 64;
+
+pub struct SnapshotBuffer {
+    delay: f32,
+    // States by the sender's clock, oldest first
+    snapshots: VecDeque<(f32, Character)>,
+    // Sender's clock minus ours when a state arrives, smoothed
+    clock_offset: Option<f32>,
+}
+
+impl SnapshotBuffer {
+    pub fn new(delay: Duration) -> SnapshotBuffer {
+        SnapshotBuffer {
+            delay: delay.as_secs_f32(),
+            snapshots: VecDeque::new(),
+            clock_offset: None,
+        }
+    }
+
+    /// Forgets every state, as when the sender's clock starts over.
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.clock_offset = None;
+    }
+
+    /// Adds `character` as the sender had it at `time` on its clock, arriving at `now`
+    /// on ours. States older than the newest one are dropped.
+    pub fn push(&mut self, time: f32, now: f32, character: Character) {
+        let sample = time - now;
+        self.clock_offset = Some(match self.clock_offset {
+            Some(offset) => offset + (sample - offset) / 8.0,
+            None => sample,
+        });
+
+        if self
+            .snapshots
+            .back()
+            .is_some_and(|(newest, _)| time <= *newest)
+        {
+            return;
+        }
+        self.snapshots.push_back((time, character));
+        if self.snapshots.len() > MAX_SNAPSHOTS {
+            self.snapshots.pop_front();
+        }
+    }
+
+    /// The character as it was the delay before `now` on our clock. Outside the states
+    /// received it holds the oldest or newest one rather than guessing.
+    pub fn sample(&self, now: f32) -> Option<Character> {
+        let time = now + self.clock_offset? - self.delay;
+        let next = self
+            .snapshots
+            .iter()
+            .position(|(snapshot_time, _)| *snapshot_time >= time);
+        match next {
+            Some(0) => self
+                .snapshots
+                .front()
+                .map(|(_, character)| character.clone()),
+            Some(idx) => {
+                let (before_time, before) = &self.snapshots[idx - 1];
+                let (after_time, after) = &self.snapshots[idx];
+                let alpha = (time - before_time) / (after_time - before_time);
+                Some(before.lerp(after, alpha))
+            }
+            None => self
+                .snapshots
+                .back()
+                .map(|(_, character)| character.clone()),
+        }
+    }
+}
+
+/// Puts `character`, as simulated here, where `seen` shows it: its body and its thrown
+/// hand. Grabs and the score stay as simulated, and so does the body while it is held,
+/// since the other hand drags it.
+pub fn show_as(character: &mut Character, seen: &Character) {
+    if !character.is_grabbed_by {
+        character.gameobject = seen.gameobject;
+    }
+    if character.grab.state != 0.0 && seen.grab.state != 0.0 {
+        character.grab.gameobject = seen.grab.gameobject;
+    }
+    character.update_children_global_transform();
+}
@@ -0,0 +1,64 @@
+//! Game core shared by the networked (`ggez6`, `ggez7`) and record/replay (`ggez8`) binaries.
+//!
+//! [`World`] holds the whole match and is stepped with an explicit `dt` and per-side
+//! [`Input`], so it runs without a window. The binaries step it at a fixed [`TICK_RATE`]
+//! through [`FixedTimestep`], and [`Renderer`] draws it in a separate pass.
+
+pub mod address;
+pub mod authority;
+pub mod character;
+pub mod communication;
+pub mod discovery;
+pub mod flash;
+pub mod game_object;
+pub mod grab;
+pub mod handshake;
+pub mod helper;
+pub mod input;
+pub mod interpolation;
+pub mod lobby;
+pub mod lockstep;
+pub mod netsim;
+pub mod playback;
+pub mod prediction;
+pub mod protocol;
+pub mod relay;
+pub mod render;
+pub mod replay;
+pub mod rollback;
+pub mod target;
+pub mod timestep;
+pub mod transform;
+pub mod udp;
+pub mod world;
+
+pub use authority::AuthoritySession;
+pub use character::{Character, Side};
+pub use communication::Communication;
+pub use flash::Flash;
+pub use game_object::GameObject;
+pub use grab::Grab;
+pub use handshake::{
+    guest_handshake, guest_rejoin_handshake, host_handshake, host_rejoin_handshake, open_transport,
+    Handshake, HandshakeError,
+};
+pub use helper::load_image;
+pub use input::{Input, KeyboardInput};
+pub use interpolation::SnapshotBuffer;
+pub use lockstep::LockstepSession;
+pub use netsim::{NetworkConditions, SimulatedNetwork};
+pub use playback::{ReplayKeyframe, ReplayRunner, ReplayScript};
+pub use prediction::Prediction;
+pub use protocol::{
+    Connection, Hello, MatchSettings, Message, Netcode, ProtocolError, Transport, TransportKind,
+};
+pub use render::Renderer;
+pub use replay::{Replay, ReplayError};
+pub use rollback::RollbackSession;
+pub use target::Target;
+pub use timestep::FixedTimestep;
+pub use transform::Transform;
+pub use udp::UdpConnection;
+pub use world::{
+    MatchEvent, MatchRng, MatchRules, ScoreEvent, World, TICK_DT, TICK_RATE, WORLD_STATE_LEN,
+};
@@ -0,0 +1,526 @@
+//! Matchmaking through a lobby server.
+//!
+//! Players who cannot find each other on the local network meet at a lobby. A host opens
+//! a room there, naming the port its game listens on, and a guest picks the room from
+//! the list. The lobby only tells the guest where the host is; the match is played over
+//! the usual direct game connection, handshake included. A room closes once it is joined
+//! or when its host leaves the lobby.
+//!
+//! Lobby messages are framed like game messages (see [`crate::protocol`]) but form a
+//! separate set: [`LobbyRequest`] from players and one [`LobbyReply`] for each.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::sync::Mutex;
+
+use crate::handshake::HANDSHAKE_TIMEOUT;
+use crate::protocol::{frame, put_hello, put_text, read_frame, FieldReader, Hello, ProtocolError};
+
+/// Port a lobby listens on unless told otherwise.
+pub const DEFAULT_LOBBY_PORT: u16 = 9997;
+
+// Keeps a room list inside one frame
+const MAX_LISTED_ROOMS: usize = 64;
+
+const TYPE_REGISTER: u8 = 1;
+const TYPE_LIST_ROOMS: u8 = 2;
+const TYPE_CREATE_ROOM: u8 = 3;
+const TYPE_JOIN_ROOM: u8 = 4;
+
+const TYPE_WELCOME: u8 = 1;
+const TYPE_ROOMS: u8 = 2;
+const TYPE_ROOM_CREATED: u8 = 3;
+const TYPE_PAIRED: u8 = 4;
+const TYPE_REFUSED: u8 = 5;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum LobbyRequest {
+    /// First request on every connection: who the player is and which game it runs.
+    Register(Hello),
+    ListRooms,
+    /// Opens a room for the game listening on `port` at the player's address.
+    CreateRoom {
+        port: u16,
+    },
+    JoinRoom(u32),
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum LobbyReply {
+    Welcome,
+    /// The open rooms the player can play in.
+    Rooms(Vec<RoomInfo>),
+    RoomCreated(u32),
+    /// Where the host of the joined room waits for its guest.
+    Paired(SocketAddr),
+    /// Why the request cannot be done.
+    Refused(String),
+}
+
+/// An open room as listed to players.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RoomInfo {
+    pub id: u32,
+    /// Name of the player hosting it.
+    pub host: String,
+}
+
+impl LobbyRequest {
+    /// The whole frame, length prefix included.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        match self {
+            LobbyRequest::Register(hello) => {
+                body.push(TYPE_REGISTER);
+                put_hello(&mut body, hello);
+            }
+            LobbyRequest::ListRooms => body.push(TYPE_LIST_ROOMS),
+            LobbyRequest::CreateRoom { port } => {
+                body.push(TYPE_CREATE_ROOM);
+                body.extend_from_slice(&port.to_le_bytes());
+            }
+            LobbyRequest::JoinRoom(id) => {
+                body.push(TYPE_JOIN_ROOM);
+                body.extend_from_slice(&id.to_le_bytes());
+            }
+        }
+        frame(body)
+    }
+
+    /// Decodes one frame body, without its length prefix.
+    pub fn decode(frame: &[u8]) -> Result<LobbyRequest, ProtocolError> {
+        let (kind, body) = frame.split_first().ok_or(ProtocolError::EmptyFrame)?;
+        let mut reader = FieldReader {
+            message: "lobby request",
+            body,
+        };
+        let request = match *kind {
+            TYPE_REGISTER => LobbyRequest::Register(reader.hello()?),
+            TYPE_LIST_ROOMS => LobbyRequest::ListRooms,
+            TYPE_CREATE_ROOM => LobbyRequest::CreateRoom {
+                port: reader.u16()?,
+            },
+            TYPE_JOIN_ROOM => LobbyRequest::JoinRoom(reader.u32()?),
+            kind => return Err(ProtocolError::UnknownMessage(kind)),
+        };
+        reader.finish()?;
+        Ok(request)
+    }
+}
+
+impl LobbyReply {
+    /// The whole frame, length prefix included. Only the first rooms of a long list fit.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        match self {
+            LobbyReply::Welcome => body.push(TYPE_WELCOME),
+            LobbyReply::Rooms(rooms) => {
+                body.push(TYPE_ROOMS);
+                let rooms = &rooms[..rooms.len().min(MAX_LISTED_ROOMS)];
+                body.extend_from_slice(&(rooms.len() as u16).to_le_bytes());
+                for room in rooms {
+                    body.extend_from_slice(&room.id.to_le_bytes());
+                    put_text(&mut body, &room.host);
+                }
+            }
+            LobbyReply::RoomCreated(id) => {
+                body.push(TYPE_ROOM_CREATED);
+                body.extend_from_slice(&id.to_le_bytes());
+            }
+            LobbyReply::Paired(addr) => {
+                body.push(TYPE_PAIRED);
+                put_text(&mut body, &addr.to_string());
+            }
+            LobbyReply::Refused(reason) => {
+                body.push(TYPE_REFUSED);
+                put_text(&mut body, reason);
+            }
+        }
+        frame(body)
+    }
+
+    /// Decodes one frame body, without its length prefix.
+    pub fn decode(frame: &[u8]) -> Result<LobbyReply, ProtocolError> {
+        let (kind, body) = frame.split_first().ok_or(ProtocolError::EmptyFrame)?;
+        let mut reader = FieldReader {
+            message: "lobby reply",
+            body,
+        };
+        let reply = match *kind {
+            TYPE_WELCOME => LobbyReply::Welcome,
+            TYPE_ROOMS => {
+                let count = reader.u16()?;
+                let mut rooms = Vec::new();
+                for _ in 0..count {
+                    rooms.push(RoomInfo {
+                        id: reader.u32()?,
+                        host: reader.text()?,
+                    });
+                }
+                LobbyReply::Rooms(rooms)
+            }
+            TYPE_ROOM_CREATED => LobbyReply::RoomCreated(reader.u32()?),
+            TYPE_PAIRED => {
+                let addr = reader
+                    .text()?
+                    .parse()
+                    .map_err(|_| ProtocolError::Malformed {
+                        message: "lobby reply",
+                        reason: "bad address",
+                    })?;
+                LobbyReply::Paired(addr)
+            }
+            TYPE_REFUSED => LobbyReply::Refused(reader.text()?),
+            kind => return Err(ProtocolError::UnknownMessage(kind)),
+        };
+        reader.finish()?;
+        Ok(reply)
+    }
+}
+
+/// Writes one request to a blocking stream.
+pub fn write_request(stream: &mut impl Write, request: &LobbyRequest) -> Result<(), ProtocolError> {
+    stream.write_all(&request.encode())?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Blocks until one whole request has been read from `stream`.
+pub fn read_request(stream: &mut impl Read) -> Result<LobbyRequest, ProtocolError> {
+    LobbyRequest::decode(&read_frame(stream)?)
+}
+
+/// Writes one reply to a blocking stream.
+pub fn write_reply(stream: &mut impl Write, reply: &LobbyReply) -> Result<(), ProtocolError> {
+    stream.write_all(&reply.encode())?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Blocks until one whole reply has been read from `stream`.
+pub fn read_reply(stream: &mut impl Read) -> Result<LobbyReply, ProtocolError> {
+    LobbyReply::decode(&read_frame(stream)?)
+}
+
+struct Room {
+    id: u32,
+    owner: u64,
+    hello: Hello,
+    addr: SocketAddr,
+}
+
+/// The rooms open at a lobby. The server tells players apart by an id it gives each
+/// connection.
+#[derive(Default)]
+pub struct Lobby {
+    rooms: Vec<Room>,
+    next_id: u32,
+}
+
+impl Lobby {
+    pub fn new() -> Lobby {
+        Lobby::default()
+    }
+
+    /// The open rooms a player announcing `hello` can play in, oldest first.
+    pub fn rooms_for(&self, hello: &Hello) -> Vec<RoomInfo> {
+        self.rooms
+            .iter()
+            .filter(|room| room.hello.is_compatible(hello))
+            .map(|room| RoomInfo {
+                id: room.id,
+                host: room.hello.name.clone(),
+            })
+            .collect()
+    }
+
+    /// Opens a room for the game `owner` hosts at `addr`, closing any it had open.
+    pub fn create(&mut self, owner: u64, hello: Hello, addr: SocketAddr) -> u32 {
+        self.leave(owner);
+        self.next_id = self.next_id.wrapping_add(1);
+        self.rooms.push(Room {
+            id: self.next_id,
+            owner,
+            hello,
+            addr,
+        });
+        self.next_id
+    }
+
+    /// Takes room `id` for `player`, announcing `hello`, and returns where its host waits.
+    /// The room closes, so nobody else joins it.
+    pub fn join(&mut self, id: u32, player: u64, hello: &Hello) -> Result<SocketAddr, String> {
+        let idx = self
+            .rooms
+            .iter()
+            .position(|room| room.id == id)
+            .ok_or_else(|| format!("room {} is no longer open", id))?;
+        let room = &self.rooms[idx];
+        if room.owner == player {
+            return Err(String::from("cannot join your own room"));
+        }
+        if !room.hello.is_compatible(hello) {
+            return Err(format!(
+                "room {} runs game {} (protocol {})",
+                id, room.hello.game_version, room.hello.protocol_version
+            ));
+        }
+        Ok(self.rooms.remove(idx).addr)
+    }
+
+    /// Closes the rooms `owner` has open, as when it leaves the lobby.
+    pub fn leave(&mut self, owner: u64) {
+        self.rooms.retain(|room| room.owner != owner);
+    }
+
+    /// Answers `request` from `player`, who registered with `hello` and connects from `ip`.
+    pub fn answer(
+        &mut self,
+        player: u64,
+        hello: &Hello,
+        ip: IpAddr,
+        request: &LobbyRequest,
+    ) -> LobbyReply {
+        match *request {
+            LobbyRequest::Register(_) => LobbyReply::Refused(String::from("already registered")),
+            LobbyRequest::ListRooms => LobbyReply::Rooms(self.rooms_for(hello)),
+            LobbyRequest::CreateRoom { port } => {
+                let id = self.create(player, hello.clone(), SocketAddr::new(ip, port));
+                LobbyReply::RoomCreated(id)
+            }
+            LobbyRequest::JoinRoom(id) => match self.join(id, player, hello) {
+                Ok(game_addr) => LobbyReply::Paired(game_addr),
+                Err(reason) => LobbyReply::Refused(reason),
+            },
+        }
+    }
+}
+
+/// Answers `player`'s requests on `stream` until it leaves, as a lobby server does for
+/// each connection. `log` gets a line when the player registers and for each room it
+/// opens or joins. The caller closes the player's rooms afterwards with
+/// [`Lobby::leave`].
+pub fn serve(
+    stream: &mut TcpStream,
+    player: u64,
+    lobby: &Mutex<Lobby>,
+    mut log: impl FnMut(String),
+) -> Result<(), ProtocolError> {
+    let addr = stream.peer_addr()?;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let hello = match read_request(stream)? {
+        LobbyRequest::Register(hello) => hello,
+        _ => {
+            let reason = String::from("register before anything else");
+            return write_reply(stream, &LobbyReply::Refused(reason));
+        }
+    };
+    // Hosts wait in the lobby for as long as it takes to be joined
+    stream.set_read_timeout(None)?;
+    write_reply(stream, &LobbyReply::Welcome)?;
+    log(format!("{} joined from {}", hello.name, addr));
+
+    loop {
+        let request = read_request(stream)?;
+        let reply = lobby
+            .lock()
+            .unwrap()
+            .answer(player, &hello, addr.ip(), &request);
+        match (&request, &reply) {
+            (LobbyRequest::CreateRoom { port }, LobbyReply::RoomCreated(id)) => {
+                let game_addr = SocketAddr::new(addr.ip(), *port);
+                log(format!(
+                    "{} opened room {} at {}",
+                    hello.name, id, game_addr
+                ));
+            }
+            (LobbyRequest::JoinRoom(id), LobbyReply::Paired(game_addr)) => {
+                log(format!(
+                    "{} joined room {} at {}",
+                    hello.name, id, game_addr
+                ));
+            }
+            _ => {}
+        }
+        write_reply(stream, &reply)?;
+    }
+}
+
+#[derive(Debug)]
+pub enum LobbyError {
+    Protocol(ProtocolError),
+    /// The lobby turned the request down.
+    Refused(String),
+    /// The lobby answered with something else than the request calls for.
+    Unexpected(&'static str),
+}
+
+impl fmt::Display for LobbyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LobbyError::Protocol(err) => write!(f, "{}", err),
+            LobbyError::Refused(reason) => write!(f, "lobby refused: {}", reason),
+            LobbyError::Unexpected(expected) => write!(f, "expected {} from the lobby", expected),
+        }
+    }
+}
+
+impl std::error::Error for LobbyError {}
+
+impl From<ProtocolError> for LobbyError {
+    fn from(err: ProtocolError) -> Self {
+        LobbyError::Protocol(err)
+    }
+}
+
+impl From<io::Error> for LobbyError {
+    fn from(err: io::Error) -> Self {
+        LobbyError::Protocol(ProtocolError::Io(err))
+    }
+}
+
+/// A player's connection to a lobby. Every request blocks until the lobby answers.
+pub struct LobbyClient {
+    stream: TcpStream,
+}
+
+impl LobbyClient {
+    /// Connects to the lobby at `addr` and registers as `name`.
+    pub fn connect(addr: SocketAddr, name: &str) -> Result<LobbyClient, LobbyError> {
+        let stream = TcpStream::connect_timeout(&addr, HANDSHAKE_TIMEOUT)?;
+        stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        let mut client = LobbyClient { stream };
+        match client.request(&LobbyRequest::Register(Hello::local(name)))? {
+            LobbyReply::Welcome => Ok(client),
+            _ => Err(LobbyError::Unexpected("a welcome")),
+        }
+    }
+
+    /// This side of the connection, the address the lobby sees the player at.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.local_addr()
+    }
+
+    /// The open rooms this player can join.
+    pub fn rooms(&mut self) -> Result<Vec<RoomInfo>, LobbyError> {
+        match self.request(&LobbyRequest::ListRooms)? {
+            LobbyReply::Rooms(rooms) => Ok(rooms),
+            _ => Err(LobbyError::Unexpected("a room list")),
+        }
+    }
+
+    /// Opens a room for the game this player hosts on `port`. It stays open until
+    /// joined, or until this client is dropped.
+    pub fn create_room(&mut self, port: u16) -> Result<u32, LobbyError> {
+        match self.request(&LobbyRequest::CreateRoom { port })? {
+            LobbyReply::RoomCreated(id) => Ok(id),
+            _ => Err(LobbyError::Unexpected("a room")),
+        }
+    }
+
+    /// Joins room `id` and returns the address of the game to connect to.
+    pub fn join_room(&mut self, id: u32) -> Result<SocketAddr, LobbyError> {
+        match self.request(&LobbyRequest::JoinRoom(id))? {
+            LobbyReply::Paired(addr) => Ok(addr),
+            _ => Err(LobbyError::Unexpected("a host address")),
+        }
+    }
+
+    fn request(&mut self, request: &LobbyRequest) -> Result<LobbyReply, LobbyError> {
+        write_request(&mut self.stream, request)?;
+        match read_reply(&mut self.stream)? {
+            LobbyReply::Refused(reason) => Err(LobbyError::Refused(reason)),
+            reply => Ok(reply),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, TcpListener};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    // Runs a lobby on 127.0.0.1:0 for the rest of the test, and returns its address.
+    fn start_lobby() -> SocketAddr {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let lobby = Arc::new(Mutex::new(Lobby::new()));
+        thread::spawn(move || {
+            for (player, stream) in (0..).zip(listener.incoming()) {
+                let mut stream = stream.unwrap();
+                let lobby = lobby.clone();
+                thread::spawn(move || {
+                    let _ = serve(&mut stream, player, &lobby, |_| {});
+                    lobby.lock().unwrap().leave(player);
+                });
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn pairs_a_guest_with_the_room_it_joins() {
+        let lobby = start_lobby();
+        let mut host = LobbyClient::connect(lobby, "Host").unwrap();
+        let id = host.create_room(4242).unwrap();
+
+        let mut guest = LobbyClient::connect(lobby, "Guest").unwrap();
+        let rooms = guest.rooms().unwrap();
+        assert_eq!(
+            rooms,
+            [RoomInfo {
+                id,
+                host: String::from("Host"),
+            }]
+        );
+        let game_addr = guest.join_room(id).unwrap();
+        assert_eq!(game_addr, SocketAddr::from((Ipv4Addr::LOCALHOST, 4242)));
+
+        // Joined rooms close
+        assert!(guest.rooms().unwrap().is_empty());
+        assert!(matches!(guest.join_room(id), Err(LobbyError::Refused(_))));
+    }
+
+    #[test]
+    fn refuses_joining_your_own_room() {
+        let lobby = start_lobby();
+        let mut host = LobbyClient::connect(lobby, "Host").unwrap();
+        let id = host.create_room(4242).unwrap();
+        assert!(matches!(host.join_room(id), Err(LobbyError::Refused(_))));
+    }
+
+    #[test]
+    fn closes_the_rooms_of_a_host_that_leaves() {
+        let lobby = start_lobby();
+        let mut host = LobbyClient::connect(lobby, "Host").unwrap();
+        host.create_room(4242).unwrap();
+        let mut guest = LobbyClient::connect(lobby, "Guest").unwrap();
+        assert_eq!(guest.rooms().unwrap().len(), 1);
+
+        drop(host);
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while !guest.rooms().unwrap().is_empty() {
+            assert!(Instant::now() < deadline, "room still open");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn hides_rooms_of_other_versions() {
+        let mut lobby = Lobby::new();
+        let old = Hello {
+            protocol_version: 0,
+            ..Hello::local("Old")
+        };
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 4242));
+        let id = lobby.create(1, old, addr);
+        let hello = Hello::local("Guest");
+        assert!(lobby.rooms_for(&hello).is_empty());
+        assert!(lobby.join(id, 2, &hello).is_err());
+    }
+}
@@ -0,0 +1,186 @@
+//! Delay-based lockstep: each input is scheduled a few ticks ahead of the tick it is
+//! sampled on, and the world only steps once both sides' inputs for the tick are in.
+//!
+//! The delay hides the trip to the other peer. Each side picks its own delay, and
+//! may change it mid-match, because inputs are labelled with the tick they apply to.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::character::Side;
+use crate::input::Input;
+use crate::world::{ScoreEvent, World, TICK_DT};
+
+/// Input delay in ticks before any round trip has been measured.
+pub const DEFAULT_INPUT_DELAY: u64 = 4;
+/// Bounds for the delay picked from the round trip time.
+pub const MIN_INPUT_DELAY: u64 = 1;
+pub const MAX_INPUT_DELAY: u64 = 15;
+
+// Furthest ahead of the current tick an input can be scheduled for. The other peer may be
+// up to this side's delay ahead, and schedules its inputs its own delay further on.
+const MAX_INPUT_LEAD: u64 = 2 * MAX_INPUT_DELAY;
+
+/// Delay that lets an input reach the other peer before its tick comes up, with one
+/// tick of slack.
+pub fn delay_for_rtt(rtt: Duration) -> u64 {
+    let one_way = rtt.as_secs_f32() / 2.0;
+    let ticks = (one_way / TICK_DT).ceil() as u64 + 1;
+    ticks.clamp(MIN_INPUT_DELAY, MAX_INPUT_DELAY)
+}
+
+pub struct LockstepSession {
+    world: World,
+    previous_world: World,
+    local: Side,
+    delay: u64,
+    // Inputs by side for each tick from the current one on, as far as they have been
+    // scheduled or received
+    inputs: VecDeque<[Option<Input>; 2]>,
+    // First tick the next local input is scheduled for
+    next_local_tick: u64,
+}
+
+impl LockstepSession {
+    /// Starts from `world`, which may be mid-match as when resuming after a reconnect.
+    pub fn new(world: World, local: Side, delay: u64) -> LockstepSession {
+        LockstepSession {
+            previous_world: world.clone(),
+            next_local_tick: world.tick,
+            world,
+            local,
+            delay,
+            inputs: VecDeque::new(),
+        }
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    /// State one tick before [`world`](Self::world), to draw blended towards it.
+    pub fn previous_world(&self) -> &World {
+        &self.previous_world
+    }
+
+    pub fn delay(&self) -> u64 {
+        self.delay
+    }
+
+    /// Takes effect from the next local input on.
+    pub fn set_delay(&mut self, delay: u64) {
+        self.delay = delay;
+    }
+
+    // Ignores ticks already stepped, or further ahead than any input may be scheduled.
+    fn set_input(&mut self, side: Side, tick: u64, input: Input) {
+        if tick < self.world.tick || tick > self.world.tick + MAX_INPUT_LEAD {
+            return;
+        }
+        let idx = (tick - self.world.tick) as usize;
+        if idx >= self.inputs.len() {
+            self.inputs.resize(idx + 1, [None; 2]);
+        }
+        self.inputs[idx][side.index()].get_or_insert(input);
+    }
+
+    /// Whether the next local input would be scheduled. False while the delay shrinks.
+    pub fn wants_local_input(&self) -> bool {
+        self.world.tick + self.delay >= self.next_local_tick
+    }
+
+    /// Schedules the local `input` for the current tick plus the delay and returns the
+    /// ticks it was scheduled for, to send to the other peer.
+    ///
+    /// When the delay grew, the ticks skipped over get the same input. When it shrank,
+    /// nothing is scheduled until the current tick catches up.
+    pub fn add_local_input(&mut self, input: Input) -> Vec<u64> {
+        let target = self.world.tick + self.delay;
+        let ticks: Vec<u64> = (self.next_local_tick..=target).collect();
+        for &tick in &ticks {
+            self.set_input(self.local, tick, input);
+        }
+        self.next_local_tick = self.next_local_tick.max(target + 1);
+        ticks
+    }
+
+    pub fn add_remote_input(&mut self, tick: u64, input: Input) {
+        self.set_input(self.local.opponent(), tick, input);
+    }
+
+    // Both inputs for the current tick, once they are in.
+    fn current_inputs(&self) -> Option<[Input; 2]> {
+        let [top, bottom] = self.inputs.front()?;
+        Some([(*top)?, (*bottom)?])
+    }
+
+    /// True while the current tick still waits for an input.
+    pub fn is_waiting(&self) -> bool {
+        self.current_inputs().is_none()
+    }
+
+    /// Steps the current tick if both inputs for it are in, and returns the points
+    /// scored on it.
+    pub fn advance(&mut self) -> Option<Vec<ScoreEvent>> {
+        let inputs = self.current_inputs()?;
+        self.previous_world = self.world.clone();
+        let scores = self.world.step(TICK_DT, inputs);
+        // A match already over stays on its last tick
+        if self.world.tick > self.previous_world.tick {
+            self.inputs.pop_front();
+        }
+        Some(scores)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEED: u64 = 7;
+
+    fn input(tick: u64) -> Input {
+        Input::from_bits((tick % 4) as u8)
+    }
+
+    #[test]
+    fn waits_for_the_delayed_remote_input() {
+        let mut session = LockstepSession::new(World::new(SEED), Side::Top, 2);
+        assert_eq!(session.add_local_input(input(0)), [0, 1, 2]);
+        assert!(session.is_waiting());
+        assert!(session.advance().is_none());
+        assert_eq!(session.world().tick, 0);
+
+        session.add_remote_input(1, input(1));
+        assert!(session.advance().is_none());
+        session.add_remote_input(0, input(0));
+        assert!(!session.is_waiting());
+        assert!(session.advance().is_some());
+        assert!(session.advance().is_some());
+        assert!(session.is_waiting());
+        assert!(session.advance().is_none());
+
+        let mut expected = World::new(SEED);
+        for tick in 0..2 {
+            expected.step(TICK_DT, [input(0), input(tick)]);
+        }
+        assert_eq!(session.world().tick, 2);
+        assert_eq!(session.world().state_hash(), expected.state_hash());
+    }
+
+    #[test]
+    fn repeats_the_local_input_over_ticks_skipped_by_a_longer_delay() {
+        let mut session = LockstepSession::new(World::new(SEED), Side::Bottom, 1);
+        assert_eq!(session.add_local_input(input(0)), [0, 1]);
+        session.set_delay(3);
+        assert_eq!(session.add_local_input(input(1)), [2, 3]);
+
+        session.set_delay(1);
+        assert!(!session.wants_local_input());
+        for tick in 0..3 {
+            session.add_remote_input(tick, input(tick));
+            assert!(session.advance().is_some());
+        }
+        assert!(session.wants_local_input());
+    }
+}
@@ -0,0 +1,365 @@
+//! Bad network conditions on demand, to try netplay between two instances on one
+//! machine.
+//!
+//! [`SimulatedNetwork`] wraps another [`Transport`] and holds each outgoing message back
+//! before handing it on. Messages that may be dropped (see [`Message::is_reliable`]) are
+//! also lost, duplicated and reordered. Reliable ones keep their order and are never
+//! lost; a loss only delays them by a resend, as the UDP transport would. Only what
+//! this side sends is affected, so either peer can run it, or both for twice the effect.
+
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::protocol::{Message, ProtocolError, Transport};
+use crate::udp::RESEND_INTERVAL;
+
+// Shortest time a reordered message is held back, so it is overtaken even on a fast link
+const MIN_REORDER_HOLD: Duration = Duration::from_millis(50);
+
+/// What the simulated network does to outgoing messages. The default passes them on
+/// untouched.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct NetworkConditions {
+    /// One-way delay added to every message.
+    pub latency: Duration,
+    /// Largest random change to the latency, either way.
+    pub jitter: Duration,
+    /// Chance of each message being lost, from 0 to 1.
+    pub loss: f32,
+    /// Chance of each droppable message arriving twice.
+    pub duplicate: f32,
+    /// Chance of each droppable message being held back so later ones overtake it.
+    pub reorder: f32,
+}
+
+impl NetworkConditions {
+    /// Parses comma-separated `key=value` pairs: `latency` and `jitter` in milliseconds,
+    /// `loss`, `duplicate` and `reorder` in percent. For example
+    /// `latency=80,jitter=20,loss=5`.
+    pub fn from_spec(spec: &str) -> Result<NetworkConditions, String> {
+        let mut conditions = NetworkConditions::default();
+        for pair in spec.split(',').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("expected `key=value`, found `{}`", pair))?;
+            let number: f32 = value
+                .parse()
+                .ok()
+                .filter(|number: &f32| number.is_finite() && *number >= 0.0)
+                .ok_or_else(|| format!("`{}` needs a number that is 0 or more", key))?;
+            let percent = || {
+                if number <= 100.0 {
+                    Ok(number / 100.0)
+                } else {
+                    Err(format!("`{}` is a percentage, 0 to 100", key))
+                }
+            };
+            // Rounded to whole microseconds, so 80 ms is not taken for 79.999998
+            let millis = Duration::from_micros((number * 1000.0).round() as u64);
+            match key {
+                "latency" => conditions.latency = millis,
+                "jitter" => conditions.jitter = millis,
+                "loss" => conditions.loss = percent()?,
+                "duplicate" => conditions.duplicate = percent()?,
+                "reorder" => conditions.reorder = percent()?,
+                _ => return Err(format!("unknown network condition `{}`", key)),
+            }
+        }
+        Ok(conditions)
+    }
+}
+
+impl fmt::Display for NetworkConditions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} ms latency, {} ms jitter, {}% loss, {}% duplicated, {}% reordered",
+            self.latency.as_millis(),
+            self.jitter.as_millis(),
+            self.loss * 100.0,
+            self.duplicate * 100.0,
+            self.reorder * 100.0
+        )
+    }
+}
+
+pub struct SimulatedNetwork {
+    inner: Box<dyn Transport>,
+    conditions: NetworkConditions,
+    rng: ChaCha8Rng,
+    // Messages held back and when each may go on, in the order they were sent
+    held: Vec<(Instant, Message)>,
+    // When the last reliable message goes on; later ones wait for it
+    last_reliable: Option<Instant>,
+}
+
+impl SimulatedNetwork {
+    pub fn new(inner: Box<dyn Transport>, conditions: NetworkConditions) -> SimulatedNetwork {
+        SimulatedNetwork::with_seed(inner, conditions, rand::random())
+    }
+
+    /// Like [`new`](Self::new), with the same losses and delays every run for `seed`.
+    pub fn with_seed(
+        inner: Box<dyn Transport>,
+        conditions: NetworkConditions,
+        seed: u64,
+    ) -> SimulatedNetwork {
+        SimulatedNetwork {
+            inner,
+            conditions,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            held: Vec::new(),
+            last_reliable: None,
+        }
+    }
+
+    // Latency with jitter applied.
+    fn delay(&mut self) -> Duration {
+        let jitter = self.conditions.jitter.as_secs_f32();
+        let offset = if jitter > 0.0 {
+            self.rng.gen_range(-jitter..=jitter)
+        } else {
+            0.0
+        };
+        Duration::from_secs_f32((self.conditions.latency.as_secs_f32() + offset).max(0.0))
+    }
+
+    fn chance(&mut self, probability: f32) -> bool {
+        probability > 0.0 && self.rng.gen::<f32>() < probability
+    }
+
+    // Hands on every message whose time has come, earliest first.
+    fn release(&mut self) -> Result<(), ProtocolError> {
+        let now = Instant::now();
+        let mut due: Vec<(Instant, Message)> = Vec::new();
+        let mut idx = 0;
+        while idx < self.held.len() {
+            if self.held[idx].0 <= now {
+                due.push(self.held.remove(idx));
+            } else {
+                idx += 1;
+            }
+        }
+        // Stable, so messages due at the same time keep their order
+        due.sort_by_key(|(at, _)| *at);
+        for (_, message) in due {
+            self.inner.send(&message)?;
+        }
+        Ok(())
+    }
+}
+
+impl Transport for SimulatedNetwork {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.peer_addr()
+    }
+
+    fn send(&mut self, message: &Message) -> Result<(), ProtocolError> {
+        let now = Instant::now();
+        if message.is_reliable() {
+            let mut at = now + self.delay();
+            if self.chance(self.conditions.loss) {
+                at += RESEND_INTERVAL;
+            }
+            if let Some(last) = self.last_reliable {
+                at = at.max(last);
+            }
+            self.last_reliable = Some(at);
+            self.held.push((at, message.clone()));
+        } else if !self.chance(self.conditions.loss) {
+            let mut at = now + self.delay();
+            if self.chance(self.conditions.reorder) {
+                at += (self.conditions.latency + self.conditions.jitter * 2).max(MIN_REORDER_HOLD);
+            }
+            self.held.push((at, message.clone()));
+            if self.chance(self.conditions.duplicate) {
+                let at = now + self.delay();
+                self.held.push((at, message.clone()));
+            }
+        }
+        self.release()
+    }
+
+    fn flush(&mut self) -> Result<(), ProtocolError> {
+        self.release()?;
+        self.inner.flush()
+    }
+
+    fn receive(&mut self) -> Result<Vec<Message>, ProtocolError> {
+        self.release()?;
+        self.inner.receive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::net::Ipv4Addr;
+    use std::rc::Rc;
+    use std::thread;
+
+    use super::*;
+    use crate::input::Input;
+
+    const MESSAGES: u64 = 4000;
+    const SEED: u64 = 7;
+
+    // Collects whatever the simulated network hands on.
+    struct Recorder(Rc<RefCell<Vec<Message>>>);
+
+    impl Transport for Recorder {
+        fn peer_addr(&self) -> io::Result<SocketAddr> {
+            Ok(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+        }
+
+        fn send(&mut self, message: &Message) -> Result<(), ProtocolError> {
+            self.0.borrow_mut().push(message.clone());
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<(), ProtocolError> {
+            Ok(())
+        }
+
+        fn receive(&mut self) -> Result<Vec<Message>, ProtocolError> {
+            Ok(Vec::new())
+        }
+    }
+
+    fn network(conditions: NetworkConditions) -> (SimulatedNetwork, Rc<RefCell<Vec<Message>>>) {
+        let sent = Rc::new(RefCell::new(Vec::new()));
+        let inner = Box::new(Recorder(sent.clone()));
+        (SimulatedNetwork::with_seed(inner, conditions, SEED), sent)
+    }
+
+    // Sends `MESSAGES` pings numbered from 0 and returns how many were handed on at once.
+    fn send_pings(network: &mut SimulatedNetwork, sent: &RefCell<Vec<Message>>) -> f32 {
+        for token in 0..MESSAGES {
+            network.send(&Message::Ping(token)).unwrap();
+        }
+        sent.borrow().len() as f32 / MESSAGES as f32
+    }
+
+    fn tokens(sent: &RefCell<Vec<Message>>) -> Vec<u64> {
+        sent.borrow()
+            .iter()
+            .map(|message| match message {
+                Message::Ping(token) => *token,
+                _ => panic!("unexpected {:?}", message),
+            })
+            .collect()
+    }
+
+    fn assert_near(rate: f32, expected: f32) {
+        assert!(
+            (rate - expected).abs() < 0.03,
+            "rate {} is not near {}",
+            rate,
+            expected
+        );
+    }
+
+    #[test]
+    fn passes_messages_on_untouched_by_default() {
+        let (mut network, sent) = network(NetworkConditions::default());
+        assert_eq!(send_pings(&mut network, &sent), 1.0);
+        assert_eq!(tokens(&sent), (0..MESSAGES).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn loses_messages_at_the_configured_rate() {
+        let (mut network, sent) = network(NetworkConditions {
+            loss: 0.2,
+            ..NetworkConditions::default()
+        });
+        assert_near(send_pings(&mut network, &sent), 0.8);
+        let tokens = tokens(&sent);
+        assert!(tokens.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn duplicates_messages_at_the_configured_rate() {
+        let (mut network, sent) = network(NetworkConditions {
+            duplicate: 0.3,
+            ..NetworkConditions::default()
+        });
+        assert_near(send_pings(&mut network, &sent), 1.3);
+    }
+
+    #[test]
+    fn reorders_messages_at_the_configured_rate() {
+        let (mut network, sent) = network(NetworkConditions {
+            reorder: 0.25,
+            ..NetworkConditions::default()
+        });
+        send_pings(&mut network, &sent);
+        thread::sleep(MIN_REORDER_HOLD * 2);
+        network.flush().unwrap();
+
+        // A reordered message arrives after one sent later than it
+        let mut tokens = tokens(&sent);
+        let mut latest = 0;
+        let mut overtaken = 0;
+        for &token in &tokens {
+            if token < latest {
+                overtaken += 1;
+            }
+            latest = latest.max(token);
+        }
+        assert_near(overtaken as f32 / MESSAGES as f32, 0.25);
+        tokens.sort_unstable();
+        assert_eq!(tokens, (0..MESSAGES).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn same_seed_gives_same_conditions() {
+        let conditions = NetworkConditions {
+            loss: 0.1,
+            duplicate: 0.1,
+            reorder: 0.1,
+            ..NetworkConditions::default()
+        };
+        let (mut first, first_sent) = network(conditions);
+        let (mut second, second_sent) = network(conditions);
+        send_pings(&mut first, &first_sent);
+        send_pings(&mut second, &second_sent);
+        assert_eq!(tokens(&first_sent), tokens(&second_sent));
+    }
+
+    #[test]
+    fn never_loses_or_reorders_reliable_messages() {
+        let (mut network, sent) = network(NetworkConditions {
+            loss: 0.5,
+            reorder: 0.5,
+            duplicate: 0.5,
+            ..NetworkConditions::default()
+        });
+        let inputs: Vec<Message> = (0..100)
+            .map(|tick| Message::Input {
+                tick,
+                input: Input::NONE,
+            })
+            .collect();
+        for input in &inputs {
+            network.send(input).unwrap();
+        }
+        thread::sleep(RESEND_INTERVAL * 2);
+        network.flush().unwrap();
+        assert_eq!(*sent.borrow(), inputs);
+    }
+
+    #[test]
+    fn parses_a_spec() {
+        let conditions = NetworkConditions::from_spec("latency=80,jitter=20,loss=5").unwrap();
+        assert_eq!(conditions.latency, Duration::from_millis(80));
+        assert_eq!(conditions.jitter, Duration::from_millis(20));
+        assert_eq!(conditions.loss, 0.05);
+        assert!(NetworkConditions::from_spec("loss=101").is_err());
+        assert!(NetworkConditions::from_spec("speed=1").is_err());
+    }
+}
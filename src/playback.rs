@@ -0,0 +1,167 @@
+use crate::character::Side;
+use crate::input::Input;
+use crate::replay::{Replay, ReplayEvent};
+use crate::world::{ScoreEvent, World, TICK_DT};
+
+/// Feeds one side's recording back as input.
+#[derive(Clone, Debug)]
+pub struct ReplayScript {
+    events: Vec<ReplayEvent>,
+    // Per-tick snapshots; replayed as is when the recording has them
+    inputs: Vec<Input>,
+    uses_side_clock: bool,
+    // Space and LShift presses without recorded releases last a single step
+    pulse_buttons: bool,
+    replay_idx: usize,
+    input: Input,
+}
+
+impl ReplayScript {
+    pub fn new(replay: &Replay, side: Side) -> ReplayScript {
+        ReplayScript {
+            events: replay.events(side).copied().collect(),
+            inputs: replay
+                .inputs
+                .iter()
+                .map(|inputs| inputs[side.index()])
+                .collect(),
+            uses_side_clock: replay.uses_side_clock(),
+            pulse_buttons: !replay.has_releases(),
+            replay_idx: 0,
+            input: Input::NONE,
+        }
+    }
+
+    /// Whether [`advance`](Self::advance) takes the side's own clock instead of the world tick.
+    pub fn uses_side_clock(&self) -> bool {
+        self.uses_side_clock
+    }
+
+    /// Input for the step last advanced to.
+    pub fn input(&self) -> Input {
+        self.input
+    }
+
+    /// Moves to `tick` and returns the events that fired on it.
+    pub fn advance(&mut self, tick: u64) -> &[ReplayEvent] {
+        let first = self.replay_idx;
+        while self.replay_idx < self.events.len() && self.events[self.replay_idx].tick <= tick {
+            self.replay_idx += 1;
+        }
+        let fired = &self.events[first..self.replay_idx];
+
+        if !self.inputs.is_empty() {
+            self.input = self
+                .inputs
+                .get(tick as usize)
+                .copied()
+                .unwrap_or(Input::NONE);
+            return fired;
+        }
+        if self.pulse_buttons {
+            self.input.grab = false;
+            self.input.flash = false;
+        }
+        for event in fired {
+            self.input.apply_action(event.action);
+        }
+        fired
+    }
+
+    /// True once nothing recorded is left after `tick`.
+    pub fn is_exhausted(&self, tick: u64) -> bool {
+        if self.inputs.is_empty() {
+            self.replay_idx == self.events.len()
+        } else {
+            tick >= self.inputs.len() as u64
+        }
+    }
+}
+
+/// Where a [`ReplayRunner`] is in its recording: the world's
+/// [`state_bytes`](World::state_bytes) and how far each script has read, without the
+/// recording itself.
+#[derive(Clone, Debug)]
+pub struct ReplayKeyframe {
+    state: Vec<u8>,
+    cursors: [(usize, Input); 2],
+    clocks: [u64; 2],
+}
+
+/// Replays a whole recording for both sides, one step at a time.
+#[derive(Clone, Debug)]
+pub struct ReplayRunner {
+    world: World,
+    scripts: [ReplayScript; 2],
+    // Per-side clocks in ticks, paused while that side is grabbed, for recordings timed that way
+    clocks: [u64; 2],
+}
+
+impl ReplayRunner {
+    pub fn new(replay: &Replay) -> ReplayRunner {
+        ReplayRunner {
+            world: World::with_rules(replay.header.seed.unwrap_or(0), replay.header.rules),
+            scripts: [
+                ReplayScript::new(replay, Side::Top),
+                ReplayScript::new(replay, Side::Bottom),
+            ],
+            clocks: [0; 2],
+        }
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    /// A keyframe to come back to this step with [`seek`](Self::seek).
+    pub fn keyframe(&self) -> ReplayKeyframe {
+        ReplayKeyframe {
+            state: self.world.state_bytes(),
+            cursors: [
+                (self.scripts[0].replay_idx, self.scripts[0].input),
+                (self.scripts[1].replay_idx, self.scripts[1].input),
+            ],
+            clocks: self.clocks,
+        }
+    }
+
+    /// Goes back or ahead to the step `keyframe` was taken on, by the same recording.
+    pub fn seek(&mut self, keyframe: &ReplayKeyframe) {
+        self.world.restore_state(&keyframe.state);
+        for (script, &(replay_idx, input)) in self.scripts.iter_mut().zip(&keyframe.cursors) {
+            script.replay_idx = replay_idx;
+            script.input = input;
+        }
+        self.clocks = keyframe.clocks;
+    }
+
+    /// The match ended or the recording ran out.
+    pub fn is_finished(&self) -> bool {
+        self.world.is_game_end()
+            || self
+                .scripts
+                .iter()
+                .all(|script| script.is_exhausted(self.world.tick))
+    }
+
+    /// Runs one step and returns the points scored on it.
+    pub fn step(&mut self) -> Vec<ScoreEvent> {
+        let tick = self.world.tick;
+        let mut inputs = [Input::NONE; 2];
+        for side in [Side::Top, Side::Bottom] {
+            let idx = side.index();
+            if !self.world.character(side).is_grabbed_by {
+                self.clocks[idx] += 1;
+            }
+            let script = &mut self.scripts[idx];
+            let script_tick = if script.uses_side_clock() {
+                self.clocks[idx]
+            } else {
+                tick
+            };
+            script.advance(script_tick);
+            inputs[idx] = script.input();
+        }
+        self.world.step(TICK_DT, inputs)
+    }
+}
@@ -0,0 +1,110 @@
+//! Client-side prediction for a character whose authority is another peer.
+//!
+//! The local character is stepped right away with each input, which is also sent to the
+//! authority. When the authority reports where the character ended up after one of
+//! those inputs, the character is put there and stepped again with every input sent
+//! since, so only a real disagreement moves it. The jump that leaves is drawn as an
+//! offset that fades out instead of a snap.
+
+use std::collections::VecDeque;
+
+use ggez::mint::Point2;
+use rand::SeedableRng;
+
+use crate::character::{Character, Side};
+use crate::communication::Communication;
+use crate::input::Input;
+use crate::world::{MatchRng, World, TICK_DT};
+
+// Seconds for a correction to fade to about a third
+const SMOOTHING_TIME: f32 = 0.1;
+// Corrections further than this are teleports (flash, respawn) and are not smoothed
+const MAX_SMOOTHED_ERROR: f32 = 100.0;
+
+pub struct Prediction {
+    side: Side,
+    // Local inputs the authority has not reported on yet, by tick
+    pending: VecDeque<(u64, Input)>,
+    acked: Option<u64>,
+    // Offset from the corrected position to where the character was drawn before
+    error: Point2<f32>,
+}
+
+impl Prediction {
+    /// Predicts the character on `side`.
+    pub fn new(side: Side) -> Prediction {
+        Prediction {
+            side,
+            pending: VecDeque::new(),
+            acked: None,
+            error: Point2 { x: 0.0, y: 0.0 },
+        }
+    }
+
+    /// Forgets every input, as when the match restarts from a new state.
+    pub fn clear(&mut self) {
+        self.pending.clear();
+        self.acked = None;
+        self.error = Point2 { x: 0.0, y: 0.0 };
+    }
+
+    /// Records the local input stepped on `tick`.
+    pub fn record(&mut self, tick: u64, input: Input) {
+        self.pending.push_back((tick, input));
+    }
+
+    /// Takes the authority's `data` for the character after the input for `tick`, and
+    /// steps it again with the inputs recorded since. Reports older than one already
+    /// taken are ignored.
+    ///
+    /// The replay only moves the character. Grab outcomes are left to the authority, so
+    /// nothing else in `world` changes.
+    pub fn reconcile(&mut self, world: &mut World, tick: u64, data: &[u8]) {
+        if self.acked.is_some_and(|acked| tick <= acked) {
+            return;
+        }
+        self.acked = Some(tick);
+        while self
+            .pending
+            .front()
+            .is_some_and(|(pending, _)| *pending <= tick)
+        {
+            self.pending.pop_front();
+        }
+
+        let mut opponent = world.character(self.side.opponent()).clone();
+        // Never drawn from, as grabs are not resolved
+        let mut rng = MatchRng::seed_from_u64(world.seed);
+        let character = world.character_mut(self.side);
+        let before = character.get_global_position();
+        character.set_recv_data(data);
+        for (_, input) in &self.pending {
+            character.apply_input(*input);
+            character.update(TICK_DT, &mut opponent, &mut rng, false);
+        }
+
+        let after = character.get_global_position();
+        self.error.x += before.x - after.x;
+        self.error.y += before.y - after.y;
+        if self.error.x.abs() > MAX_SMOOTHED_ERROR || self.error.y.abs() > MAX_SMOOTHED_ERROR {
+            self.error = Point2 { x: 0.0, y: 0.0 };
+        }
+    }
+
+    /// Fades the leftover correction over `dt` seconds.
+    pub fn settle(&mut self, dt: f32) {
+        let keep = (-dt / SMOOTHING_TIME).exp();
+        self.error.x *= keep;
+        self.error.y *= keep;
+    }
+
+    /// Shifts `character`, about to be drawn, by what is left of the last correction.
+    pub fn smooth(&self, character: &mut Character) {
+        if character.is_grabbed_by {
+            return;
+        }
+        character.gameobject.transform.position.x += self.error.x;
+        character.gameobject.transform.position.y += self.error.y;
+        character.update_children_global_transform();
+    }
+}
@@ -0,0 +1,875 @@
+//! Framed messages exchanged between two game peers.
+//!
+//! Every frame is a little-endian `u32` length, then that many bytes: a one byte message
+//! type followed by the message's little-endian fields.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+
+use crate::character::{Side, CHARACTER_DATA_LEN};
+use crate::input::Input;
+use crate::world::{MatchEvent, MatchRules, WORLD_STATE_LEN};
+
+/// Bumped whenever a message changes shape. Peers only play when theirs match.
+pub const PROTOCOL_VERSION: u32 = 9;
+
+/// Largest frame accepted, type byte included. Anything bigger is a broken stream.
+pub const MAX_FRAME_LEN: usize = 4096;
+
+/// Longest player name sent, in bytes.
+pub const MAX_NAME_LEN: usize = 32;
+
+pub(crate) const LEN_PREFIX: usize = 4;
+
+// The hello type and layout must stay the same across protocol versions, so that
+// mismatched peers can still tell each other apart
+const TYPE_HELLO: u8 = 1;
+const TYPE_SETTINGS: u8 = 2;
+const TYPE_STATE: u8 = 3;
+const TYPE_EVENT: u8 = 4;
+const TYPE_INPUT: u8 = 5;
+const TYPE_PING: u8 = 6;
+const TYPE_PONG: u8 = 7;
+const TYPE_UDP_PORT: u8 = 8;
+const TYPE_RESUME: u8 = 9;
+const TYPE_CORRECTION: u8 = 10;
+const TYPE_SEAT: u8 = 11;
+const TYPE_REJOIN: u8 = 12;
+
+const EVENT_HIT: u8 = 1;
+const EVENT_MISS: u8 = 2;
+const EVENT_RESPAWN: u8 = 3;
+const EVENT_SCORE: u8 = 4;
+
+/// What each peer announces about itself when it connects.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Hello {
+    pub protocol_version: u32,
+    pub game_version: String,
+    pub name: String,
+}
+
+impl Hello {
+    /// This build's hello, with `name` cut to [`MAX_NAME_LEN`].
+    pub fn local(name: &str) -> Hello {
+        let mut end = name.len().min(MAX_NAME_LEN);
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        Hello {
+            protocol_version: PROTOCOL_VERSION,
+            game_version: String::from(crate::replay::GAME_VERSION),
+            name: String::from(&name[..end]),
+        }
+    }
+
+    /// Whether a peer announcing `other` runs the same simulation and protocol.
+    pub fn is_compatible(&self, other: &Hello) -> bool {
+        self.protocol_version == other.protocol_version && self.game_version == other.game_version
+    }
+}
+
+/// How the peers keep their worlds in step during a match.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Netcode {
+    /// Each peer streams its own character and the host resolves grabs.
+    #[default]
+    StateSync,
+    /// Peers exchange inputs only and roll back mispredicted ticks; see
+    /// [`RollbackSession`](crate::rollback::RollbackSession).
+    Rollback,
+    /// Peers exchange inputs a few ticks ahead and step only once both are in; see
+    /// [`LockstepSession`](crate::lockstep::LockstepSession).
+    Lockstep,
+    /// A dedicated server steps both characters from the players' inputs, and both
+    /// players predict their own; see [`AuthoritySession`](crate::authority::AuthoritySession).
+    Server,
+}
+
+impl Netcode {
+    pub const ALL: [Netcode; 4] = [
+        Netcode::StateSync,
+        Netcode::Rollback,
+        Netcode::Lockstep,
+        Netcode::Server,
+    ];
+
+    /// Name used on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            Netcode::StateSync => "state",
+            Netcode::Rollback => "rollback",
+            Netcode::Lockstep => "lockstep",
+            Netcode::Server => "server",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Netcode> {
+        Netcode::ALL
+            .into_iter()
+            .find(|netcode| netcode.name() == name)
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            Netcode::StateSync => 0,
+            Netcode::Rollback => 1,
+            Netcode::Lockstep => 2,
+            Netcode::Server => 3,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Netcode> {
+        Netcode::ALL
+            .into_iter()
+            .find(|netcode| netcode.to_byte() == byte)
+    }
+}
+
+/// What carries the match once the handshake is done.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum TransportKind {
+    /// Stay on the handshake's TCP stream.
+    #[default]
+    Tcp,
+    /// Switch to a [`UdpConnection`](crate::udp::UdpConnection).
+    Udp,
+}
+
+impl TransportKind {
+    pub const ALL: [TransportKind; 2] = [TransportKind::Tcp, TransportKind::Udp];
+
+    /// Name used on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            TransportKind::Tcp => "tcp",
+            TransportKind::Udp => "udp",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<TransportKind> {
+        TransportKind::ALL
+            .into_iter()
+            .find(|transport| transport.name() == name)
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            TransportKind::Tcp => 0,
+            TransportKind::Udp => 1,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<TransportKind> {
+        TransportKind::ALL
+            .into_iter()
+            .find(|transport| transport.to_byte() == byte)
+    }
+}
+
+/// Everything the host decides for a match, sent to the guest before it starts.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MatchSettings {
+    pub seed: u64,
+    pub rules: MatchRules,
+    pub netcode: Netcode,
+    pub transport: TransportKind,
+    /// Drawn for each match. A guest that reconnects shows it to be let back in, see
+    /// [`Message::Rejoin`].
+    pub token: u64,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Message {
+    Hello(Hello),
+    /// Sent by the host once both hellos are compatible.
+    Settings(MatchSettings),
+    /// The sender's own character after its latest step, stamped with the sender's clock
+    /// so stale states can be dropped.
+    State {
+        time: f32,
+        /// [`CHARACTER_DATA_LEN`] bytes from
+        /// [`Communication::get_send_data`](crate::Communication::get_send_data).
+        character: Vec<u8>,
+    },
+    /// An outcome decided by the host, which has grab authority.
+    Event(MatchEvent),
+    /// The sender's input for one tick. Under [`Netcode::StateSync`] only the guest sends
+    /// them, for the host to step the guest's character with.
+    Input {
+        tick: u64,
+        input: Input,
+    },
+    /// The receiver's own character as the host has it after stepping the receiver's
+    /// input for `tick`, for the receiver to reconcile its prediction with.
+    Correction {
+        tick: u64,
+        /// [`CHARACTER_DATA_LEN`] bytes, as in [`Message::State`].
+        character: Vec<u8>,
+    },
+    /// Asks for a [`Message::Pong`] with the same token, to measure the round trip.
+    Ping(u64),
+    Pong(u64),
+    /// Port of the sender's UDP socket, swapped over TCP under [`TransportKind::Udp`].
+    UdpPort(u16),
+    /// The host's whole match, sent to a guest that reconnected so both go on from it.
+    /// [`WORLD_STATE_LEN`] bytes from [`World::state_bytes`](crate::World::state_bytes).
+    Resume(Vec<u8>),
+    /// Sent by a dedicated server to each player once both are in: the side the
+    /// receiver plays and its opponent's name.
+    Seat {
+        side: Side,
+        opponent: String,
+    },
+    /// Sent by a guest reconnecting to a match right after the hellos, with the match's
+    /// [`token`](MatchSettings::token). The host only hands over the settings when it
+    /// is the one of the match being resumed.
+    Rejoin(u64),
+}
+
+#[derive(Debug)]
+pub enum ProtocolError {
+    Io(io::Error),
+    /// The peer closed the connection.
+    Closed,
+    FrameTooLarge(usize),
+    EmptyFrame,
+    UnknownMessage(u8),
+    BadLength {
+        message: &'static str,
+        expected: usize,
+        found: usize,
+    },
+    /// A variable length message does not parse.
+    Malformed {
+        message: &'static str,
+        reason: &'static str,
+    },
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProtocolError::Io(err) => write!(f, "{}", err),
+            ProtocolError::Closed => write!(f, "connection closed by peer"),
+            ProtocolError::FrameTooLarge(len) => write!(
+                f,
+                "frame of {} bytes exceeds the limit of {}",
+                len, MAX_FRAME_LEN
+            ),
+            ProtocolError::EmptyFrame => write!(f, "frame without a message type"),
+            ProtocolError::UnknownMessage(kind) => write!(f, "unknown message type {}", kind),
+            ProtocolError::BadLength {
+                message,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} message has {} bytes, expected {}",
+                message, found, expected
+            ),
+            ProtocolError::Malformed { message, reason } => {
+                write!(f, "malformed {} message: {}", message, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+impl From<io::Error> for ProtocolError {
+    fn from(err: io::Error) -> Self {
+        ProtocolError::Io(err)
+    }
+}
+
+fn expect_len(message: &'static str, body: &[u8], expected: usize) -> Result<(), ProtocolError> {
+    if body.len() != expected {
+        return Err(ProtocolError::BadLength {
+            message,
+            expected,
+            found: body.len(),
+        });
+    }
+    Ok(())
+}
+
+// Walks the fields of a variable length message body.
+pub(crate) struct FieldReader<'a> {
+    pub(crate) message: &'static str,
+    pub(crate) body: &'a [u8],
+}
+
+impl<'a> FieldReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ProtocolError> {
+        if self.body.len() < len {
+            return Err(ProtocolError::Malformed {
+                message: self.message,
+                reason: "truncated",
+            });
+        }
+        let (field, rest) = self.body.split_at(len);
+        self.body = rest;
+        Ok(field)
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, ProtocolError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, ProtocolError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn text(&mut self) -> Result<String, ProtocolError> {
+        let len = self.u16()? as usize;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| ProtocolError::Malformed {
+            message: self.message,
+            reason: "text is not UTF-8",
+        })
+    }
+
+    pub(crate) fn hello(&mut self) -> Result<Hello, ProtocolError> {
+        Ok(Hello {
+            protocol_version: self.u32()?,
+            game_version: self.text()?,
+            name: self.text()?,
+        })
+    }
+
+    pub(crate) fn finish(self) -> Result<(), ProtocolError> {
+        if !self.body.is_empty() {
+            return Err(ProtocolError::Malformed {
+                message: self.message,
+                reason: "trailing bytes",
+            });
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn put_text(body: &mut Vec<u8>, text: &str) {
+    body.extend_from_slice(&(text.len() as u16).to_le_bytes());
+    body.extend_from_slice(text.as_bytes());
+}
+
+pub(crate) fn put_hello(body: &mut Vec<u8>, hello: &Hello) {
+    body.extend_from_slice(&hello.protocol_version.to_le_bytes());
+    put_text(body, &hello.game_version);
+    put_text(body, &hello.name);
+}
+
+fn side_byte(side: Side) -> u8 {
+    side.index() as u8
+}
+
+fn byte_side(message: &'static str, byte: u8) -> Result<Side, ProtocolError> {
+    match byte {
+        0 => Ok(Side::Top),
+        1 => Ok(Side::Bottom),
+        _ => Err(ProtocolError::Malformed {
+            message,
+            reason: "unknown side",
+        }),
+    }
+}
+
+impl Message {
+    /// Whether a transport that may lose packets has to deliver this message. States,
+    /// corrections and round trip probes are superseded by the next ones, so they may be
+    /// dropped.
+    pub fn is_reliable(&self) -> bool {
+        !matches!(
+            self,
+            Message::State { .. }
+                | Message::Correction { .. }
+                | Message::Ping(_)
+                | Message::Pong(_)
+        )
+    }
+
+    /// The whole frame, length prefix included.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        match self {
+            Message::Hello(hello) => {
+                body.push(TYPE_HELLO);
+                put_hello(&mut body, hello);
+            }
+            Message::Settings(settings) => {
+                body.push(TYPE_SETTINGS);
+                body.extend_from_slice(&settings.seed.to_le_bytes());
+                body.extend_from_slice(&settings.rules.winning_score.to_le_bytes());
+                body.push(settings.netcode.to_byte());
+                body.push(settings.transport.to_byte());
+                body.extend_from_slice(&settings.token.to_le_bytes());
+            }
+            Message::State { time, character } => {
+                body.push(TYPE_STATE);
+                body.extend_from_slice(&time.to_le_bytes());
+                body.extend_from_slice(character);
+            }
+            Message::Correction { tick, character } => {
+                body.push(TYPE_CORRECTION);
+                body.extend_from_slice(&tick.to_le_bytes());
+                body.extend_from_slice(character);
+            }
+            Message::Resume(state) => {
+                body.push(TYPE_RESUME);
+                body.extend_from_slice(state);
+            }
+            Message::Seat { side, opponent } => {
+                body.push(TYPE_SEAT);
+                body.push(side_byte(*side));
+                put_text(&mut body, opponent);
+            }
+            Message::UdpPort(port) => {
+                body.push(TYPE_UDP_PORT);
+                body.extend_from_slice(&port.to_le_bytes());
+            }
+            Message::Ping(token) => {
+                body.push(TYPE_PING);
+                body.extend_from_slice(&token.to_le_bytes());
+            }
+            Message::Pong(token) => {
+                body.push(TYPE_PONG);
+                body.extend_from_slice(&token.to_le_bytes());
+            }
+            Message::Rejoin(token) => {
+                body.push(TYPE_REJOIN);
+                body.extend_from_slice(&token.to_le_bytes());
+            }
+            Message::Input { tick, input } => {
+                body.push(TYPE_INPUT);
+                body.extend_from_slice(&tick.to_le_bytes());
+                body.push(input.to_bits());
+            }
+            Message::Event(event) => {
+                body.push(TYPE_EVENT);
+                let (kind, side, value) = match *event {
+                    MatchEvent::Hit { grabber } => (EVENT_HIT, grabber, [0; 4]),
+                    MatchEvent::Miss { grabber } => (EVENT_MISS, grabber, [0; 4]),
+                    MatchEvent::Respawn { side, x } => (EVENT_RESPAWN, side, x.to_le_bytes()),
+                    MatchEvent::Score { side, score } => (EVENT_SCORE, side, score.to_le_bytes()),
+                };
+                body.push(kind);
+                body.push(side_byte(side));
+                body.extend_from_slice(&value);
+            }
+        }
+
+        frame(body)
+    }
+
+    /// Decodes one frame body, without its length prefix.
+    pub fn decode(frame: &[u8]) -> Result<Message, ProtocolError> {
+        let (kind, body) = frame.split_first().ok_or(ProtocolError::EmptyFrame)?;
+        match *kind {
+            TYPE_HELLO => {
+                let mut reader = FieldReader {
+                    message: "hello",
+                    body,
+                };
+                let hello = reader.hello()?;
+                reader.finish()?;
+                Ok(Message::Hello(hello))
+            }
+            TYPE_SETTINGS => {
+                expect_len("settings", body, 22)?;
+                let netcode = Netcode::from_byte(body[12]).ok_or(ProtocolError::Malformed {
+                    message: "settings",
+                    reason: "unknown netcode",
+                })?;
+                Ok(Message::Settings(MatchSettings {
+                    seed: u64::from_le_bytes(body[0..8].try_into().unwrap()),
+                    rules: MatchRules {
+                        winning_score: i32::from_le_bytes(body[8..12].try_into().unwrap()),
+                    },
+                    netcode,
+                    transport: TransportKind::from_byte(body[13]).ok_or(
+                        ProtocolError::Malformed {
+                            message: "settings",
+                            reason: "unknown transport",
+                        },
+                    )?,
+                    token: u64::from_le_bytes(body[14..22].try_into().unwrap()),
+                }))
+            }
+            TYPE_INPUT => {
+                expect_len("input", body, 9)?;
+                if body[8] > 0x0f {
+                    return Err(ProtocolError::Malformed {
+                        message: "input",
+                        reason: "unknown buttons",
+                    });
+                }
+                Ok(Message::Input {
+                    tick: u64::from_le_bytes(body[0..8].try_into().unwrap()),
+                    input: Input::from_bits(body[8]),
+                })
+            }
+            TYPE_STATE => {
+                expect_len("state", body, 4 + CHARACTER_DATA_LEN)?;
+                let (time, character) = body.split_at(4);
+                Ok(Message::State {
+                    time: f32::from_le_bytes(time.try_into().unwrap()),
+                    character: character.to_vec(),
+                })
+            }
+            TYPE_EVENT => {
+                expect_len("event", body, 6)?;
+                let side = byte_side("event", body[1])?;
+                let value: [u8; 4] = body[2..6].try_into().unwrap();
+                let event = match body[0] {
+                    EVENT_HIT => MatchEvent::Hit { grabber: side },
+                    EVENT_MISS => MatchEvent::Miss { grabber: side },
+                    EVENT_RESPAWN => MatchEvent::Respawn {
+                        side,
+                        x: f32::from_le_bytes(value),
+                    },
+                    EVENT_SCORE => MatchEvent::Score {
+                        side,
+                        score: i32::from_le_bytes(value),
+                    },
+                    _ => {
+                        return Err(ProtocolError::Malformed {
+                            message: "event",
+                            reason: "unknown event kind",
+                        })
+                    }
+                };
+                Ok(Message::Event(event))
+            }
+            TYPE_CORRECTION => {
+                expect_len("correction", body, 8 + CHARACTER_DATA_LEN)?;
+                let (tick, character) = body.split_at(8);
+                Ok(Message::Correction {
+                    tick: u64::from_le_bytes(tick.try_into().unwrap()),
+                    character: character.to_vec(),
+                })
+            }
+            TYPE_RESUME => {
+                expect_len("resume", body, WORLD_STATE_LEN)?;
+                Ok(Message::Resume(body.to_vec()))
+            }
+            TYPE_SEAT => {
+                let mut reader = FieldReader {
+                    message: "seat",
+                    body,
+                };
+                let side = byte_side("seat", reader.take(1)?[0])?;
+                let opponent = reader.text()?;
+                reader.finish()?;
+                Ok(Message::Seat { side, opponent })
+            }
+            TYPE_UDP_PORT => {
+                expect_len("UDP port", body, 2)?;
+                Ok(Message::UdpPort(u16::from_le_bytes(
+                    body.try_into().unwrap(),
+                )))
+            }
+            TYPE_PING => {
+                expect_len("ping", body, 8)?;
+                Ok(Message::Ping(u64::from_le_bytes(body.try_into().unwrap())))
+            }
+            TYPE_PONG => {
+                expect_len("pong", body, 8)?;
+                Ok(Message::Pong(u64::from_le_bytes(body.try_into().unwrap())))
+            }
+            TYPE_REJOIN => {
+                expect_len("rejoin", body, 8)?;
+                Ok(Message::Rejoin(u64::from_le_bytes(
+                    body.try_into().unwrap(),
+                )))
+            }
+            kind => Err(ProtocolError::UnknownMessage(kind)),
+        }
+    }
+}
+
+// Prefixes `body` with its length.
+pub(crate) fn frame(body: Vec<u8>) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(LEN_PREFIX + body.len());
+    frame.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    frame.extend(body);
+    frame
+}
+
+// Length of the complete frame at the start of `buf`, once all of it has arrived.
+pub(crate) fn complete_frame_len(buf: &[u8]) -> Result<Option<usize>, ProtocolError> {
+    if buf.len() < LEN_PREFIX {
+        return Ok(None);
+    }
+    let len = u32::from_le_bytes(buf[..LEN_PREFIX].try_into().unwrap()) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(ProtocolError::FrameTooLarge(len));
+    }
+    if buf.len() < LEN_PREFIX + len {
+        return Ok(None);
+    }
+    Ok(Some(len))
+}
+
+/// Writes one message to a blocking stream.
+pub fn write_message(stream: &mut impl Write, message: &Message) -> Result<(), ProtocolError> {
+    stream.write_all(&message.encode())?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Blocks until one whole message has been read from `stream`.
+pub fn read_message(stream: &mut impl Read) -> Result<Message, ProtocolError> {
+    Message::decode(&read_frame(stream)?)
+}
+
+// Blocks until one whole frame has been read from `stream`, and returns its body.
+pub(crate) fn read_frame(stream: &mut impl Read) -> Result<Vec<u8>, ProtocolError> {
+    let mut prefix = [0u8; LEN_PREFIX];
+    read_exact_or_closed(stream, &mut prefix)?;
+    let len = u32::from_le_bytes(prefix) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(ProtocolError::FrameTooLarge(len));
+    }
+    let mut frame = vec![0u8; len];
+    read_exact_or_closed(stream, &mut frame)?;
+    Ok(frame)
+}
+
+fn read_exact_or_closed(stream: &mut impl Read, buf: &mut [u8]) -> Result<(), ProtocolError> {
+    stream.read_exact(buf).map_err(|err| match err.kind() {
+        io::ErrorKind::UnexpectedEof => ProtocolError::Closed,
+        _ => ProtocolError::Io(err),
+    })
+}
+
+/// Carries messages between two peers during a match.
+pub trait Transport {
+    fn peer_addr(&self) -> io::Result<SocketAddr>;
+
+    /// Queues `message` and sends as much as the socket takes right away.
+    fn send(&mut self, message: &Message) -> Result<(), ProtocolError>;
+
+    /// Sends whatever is still queued or due again, without blocking.
+    fn flush(&mut self) -> Result<(), ProtocolError>;
+
+    /// Every message that has arrived since the last call, in order. Once the peer has
+    /// gone, the messages it sent before are still handed on before the error is. A frame
+    /// that does not decode is reported the same way, and the frames after it follow.
+    fn receive(&mut self) -> Result<Vec<Message>, ProtocolError>;
+}
+
+/// A nonblocking TCP stream of messages. Partial frames in either direction are buffered
+/// until they complete, so the stream never desyncs.
+pub struct Connection {
+    stream: TcpStream,
+    incoming: Vec<u8>,
+    outgoing: Vec<u8>,
+    // Why the stream ended, once it has; reported after the last frames are handed on
+    ended: Option<ProtocolError>,
+    // A frame that did not decode, reported on the call after the messages before it
+    bad_frame: Option<ProtocolError>,
+}
+
+impl Connection {
+    pub fn new(stream: TcpStream) -> io::Result<Connection> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        Ok(Connection {
+            stream,
+            incoming: Vec::new(),
+            outgoing: Vec::new(),
+            ended: None,
+            bad_frame: None,
+        })
+    }
+}
+
+impl Transport for Connection {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+
+    fn send(&mut self, message: &Message) -> Result<(), ProtocolError> {
+        self.outgoing.extend(message.encode());
+        self.flush()
+    }
+
+    fn flush(&mut self) -> Result<(), ProtocolError> {
+        while !self.outgoing.is_empty() {
+            match self.stream.write(&self.outgoing) {
+                Ok(0) => {
+                    // Left for receive to report, once it has handed on what arrived
+                    self.ended.get_or_insert(ProtocolError::Closed);
+                    break;
+                }
+                Ok(written) => {
+                    self.outgoing.drain(..written);
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Result<Vec<Message>, ProtocolError> {
+        if let Some(err) = self.bad_frame.take() {
+            return Err(err);
+        }
+
+        let mut buf = [0u8; 1024];
+        while self.ended.is_none() {
+            match self.stream.read(&mut buf) {
+                Ok(0) => self.ended = Some(ProtocolError::Closed),
+                Ok(read) => self.incoming.extend_from_slice(&buf[..read]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => self.ended = Some(err.into()),
+            }
+        }
+
+        let mut messages = Vec::new();
+        loop {
+            let len = match complete_frame_len(&self.incoming) {
+                Ok(Some(len)) => len,
+                Ok(None) => break,
+                Err(err) => {
+                    // Nothing after a broken length can be framed again, and it came before
+                    // whatever else ended the stream
+                    self.incoming.clear();
+                    self.ended = Some(err);
+                    break;
+                }
+            };
+            let decoded = Message::decode(&self.incoming[LEN_PREFIX..LEN_PREFIX + len]);
+            self.incoming.drain(..LEN_PREFIX + len);
+            match decoded {
+                Ok(message) => messages.push(message),
+                Err(err) if messages.is_empty() => return Err(err),
+                Err(err) => {
+                    self.bad_frame = Some(err);
+                    break;
+                }
+            }
+        }
+        if messages.is_empty() {
+            if let Some(err) = self.ended.take() {
+                // Later calls keep finding the stream closed
+                self.ended = Some(ProtocolError::Closed);
+                return Err(err);
+            }
+        }
+        Ok(messages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    // A connection and the raw stream of its peer.
+    fn connected() -> (Connection, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let peer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        (Connection::new(stream).unwrap(), peer)
+    }
+
+    // The next call to receive that hands on messages or an error.
+    fn receive_some(connection: &mut Connection) -> Result<Vec<Message>, ProtocolError> {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        loop {
+            match connection.receive() {
+                Ok(messages) if messages.is_empty() && Instant::now() < deadline => {
+                    thread::sleep(Duration::from_millis(1))
+                }
+                result => return result,
+            }
+        }
+    }
+
+    #[test]
+    fn waits_for_the_rest_of_a_partial_frame() {
+        let (mut connection, mut peer) = connected();
+        let bytes = [Message::Ping(7).encode(), Message::Pong(8).encode()].concat();
+
+        peer.write_all(&bytes[..LEN_PREFIX + 3]).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        assert!(connection.receive().unwrap().is_empty());
+        peer.write_all(&bytes[LEN_PREFIX + 3..]).unwrap();
+
+        let mut messages = Vec::new();
+        while messages.len() < 2 {
+            messages.extend(receive_some(&mut connection).unwrap());
+        }
+        assert_eq!(messages, [Message::Ping(7), Message::Pong(8)]);
+    }
+
+    #[test]
+    fn hands_on_frames_before_an_oversize_length() {
+        let (mut connection, mut peer) = connected();
+        let too_long = (MAX_FRAME_LEN as u32 + 1).to_le_bytes();
+        peer.write_all(&[Message::Ping(1).encode(), too_long.to_vec()].concat())
+            .unwrap();
+        thread::sleep(Duration::from_millis(50));
+
+        assert_eq!(receive_some(&mut connection).unwrap(), [Message::Ping(1)]);
+        assert!(matches!(
+            connection.receive(),
+            Err(ProtocolError::FrameTooLarge(len)) if len == MAX_FRAME_LEN + 1
+        ));
+        assert!(matches!(connection.receive(), Err(ProtocolError::Closed)));
+    }
+
+    #[test]
+    fn skips_a_malformed_frame() {
+        let (mut connection, mut peer) = connected();
+        let malformed = frame(vec![TYPE_PING, 1, 2]);
+        peer.write_all(
+            &[
+                Message::Ping(1).encode(),
+                malformed.clone(),
+                Message::Ping(2).encode(),
+                malformed,
+                Message::Ping(3).encode(),
+            ]
+            .concat(),
+        )
+        .unwrap();
+        thread::sleep(Duration::from_millis(50));
+
+        assert_eq!(receive_some(&mut connection).unwrap(), [Message::Ping(1)]);
+        assert!(matches!(
+            connection.receive(),
+            Err(ProtocolError::BadLength { .. })
+        ));
+        assert_eq!(connection.receive().unwrap(), [Message::Ping(2)]);
+        assert!(matches!(
+            connection.receive(),
+            Err(ProtocolError::BadLength { .. })
+        ));
+        assert_eq!(connection.receive().unwrap(), [Message::Ping(3)]);
+    }
+
+    #[test]
+    fn hands_on_frames_sent_before_the_peer_closed() {
+        let (mut connection, mut peer) = connected();
+        peer.write_all(&[Message::Ping(1).encode(), Message::Ping(2).encode()].concat())
+            .unwrap();
+        drop(peer);
+        thread::sleep(Duration::from_millis(50));
+
+        assert_eq!(
+            receive_some(&mut connection).unwrap(),
+            [Message::Ping(1), Message::Ping(2)]
+        );
+        assert!(matches!(connection.receive(), Err(ProtocolError::Closed)));
+        assert!(matches!(connection.receive(), Err(ProtocolError::Closed)));
+    }
+}
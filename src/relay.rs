@@ -0,0 +1,281 @@
+//! Matches played through a relay server, for peers that cannot reach each other.
+//!
+//! Both players connect out to the relay. The host opens a room and gets a short room
+//! code to pass on; the guest joins with that code. Once paired, the relay forwards
+//! every byte between the two connections untouched, so each side holds an ordinary
+//! [`TcpStream`] to shake hands and play over as if it were connected directly. Only
+//! the TCP transport goes through a relay.
+//!
+//! Relay messages are framed like game messages (see [`crate::protocol`]) and are only
+//! exchanged before pairing: one [`RelayRequest`] from each player and the
+//! [`RelayReply`]s to it.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::handshake::HANDSHAKE_TIMEOUT;
+use crate::protocol::{frame, put_text, read_frame, FieldReader, ProtocolError};
+
+/// Port a relay listens on unless told otherwise.
+pub const DEFAULT_RELAY_PORT: u16 = 9996;
+
+/// Characters in a room code.
+pub const ROOM_CODE_LEN: usize = 5;
+
+// Letters and digits that cannot be mistaken for one another when read out
+const ROOM_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+const TYPE_OPEN: u8 = 1;
+const TYPE_JOIN: u8 = 2;
+
+const TYPE_OPENED: u8 = 1;
+const TYPE_PAIRED: u8 = 2;
+const TYPE_REFUSED: u8 = 3;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum RelayRequest {
+    /// Opens a room, under the given code when a host takes its room back after losing
+    /// the guest, or a fresh one otherwise.
+    Open(Option<String>),
+    Join(String),
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum RelayReply {
+    /// The room is open under this code. The host hears next when a guest joins.
+    Opened(String),
+    /// Sent to both players; everything after it comes from the other player.
+    Paired,
+    /// Why the request cannot be done.
+    Refused(String),
+}
+
+impl RelayRequest {
+    /// The whole frame, length prefix included.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        match self {
+            RelayRequest::Open(code) => {
+                body.push(TYPE_OPEN);
+                put_text(&mut body, code.as_deref().unwrap_or(""));
+            }
+            RelayRequest::Join(code) => {
+                body.push(TYPE_JOIN);
+                put_text(&mut body, code);
+            }
+        }
+        frame(body)
+    }
+
+    /// Decodes one frame body, without its length prefix.
+    pub fn decode(frame: &[u8]) -> Result<RelayRequest, ProtocolError> {
+        let (kind, body) = frame.split_first().ok_or(ProtocolError::EmptyFrame)?;
+        let mut reader = FieldReader {
+            message: "relay request",
+            body,
+        };
+        let request = match *kind {
+            TYPE_OPEN => {
+                let code = reader.text()?;
+                RelayRequest::Open((!code.is_empty()).then_some(code))
+            }
+            TYPE_JOIN => RelayRequest::Join(reader.text()?),
+            kind => return Err(ProtocolError::UnknownMessage(kind)),
+        };
+        reader.finish()?;
+        Ok(request)
+    }
+}
+
+impl RelayReply {
+    /// The whole frame, length prefix included.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        match self {
+            RelayReply::Opened(code) => {
+                body.push(TYPE_OPENED);
+                put_text(&mut body, code);
+            }
+            RelayReply::Paired => body.push(TYPE_PAIRED),
+            RelayReply::Refused(reason) => {
+                body.push(TYPE_REFUSED);
+                put_text(&mut body, reason);
+            }
+        }
+        frame(body)
+    }
+
+    /// Decodes one frame body, without its length prefix.
+    pub fn decode(frame: &[u8]) -> Result<RelayReply, ProtocolError> {
+        let (kind, body) = frame.split_first().ok_or(ProtocolError::EmptyFrame)?;
+        let mut reader = FieldReader {
+            message: "relay reply",
+            body,
+        };
+        let reply = match *kind {
+            TYPE_OPENED => RelayReply::Opened(reader.text()?),
+            TYPE_PAIRED => RelayReply::Paired,
+            TYPE_REFUSED => RelayReply::Refused(reader.text()?),
+            kind => return Err(ProtocolError::UnknownMessage(kind)),
+        };
+        reader.finish()?;
+        Ok(reply)
+    }
+}
+
+/// Writes one request to a blocking stream.
+pub fn write_request(stream: &mut impl Write, request: &RelayRequest) -> Result<(), ProtocolError> {
+    stream.write_all(&request.encode())?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Blocks until one whole request has been read from `stream`.
+pub fn read_request(stream: &mut impl Read) -> Result<RelayRequest, ProtocolError> {
+    RelayRequest::decode(&read_frame(stream)?)
+}
+
+/// Writes one reply to a blocking stream.
+pub fn write_reply(stream: &mut impl Write, reply: &RelayReply) -> Result<(), ProtocolError> {
+    stream.write_all(&reply.encode())?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Blocks until one whole reply has been read from `stream`.
+pub fn read_reply(stream: &mut impl Read) -> Result<RelayReply, ProtocolError> {
+    RelayReply::decode(&read_frame(stream)?)
+}
+
+/// A fresh room code.
+pub fn random_room_code(rng: &mut impl Rng) -> String {
+    (0..ROOM_CODE_LEN)
+        .map(|_| ROOM_CODE_ALPHABET[rng.gen_range(0..ROOM_CODE_ALPHABET.len())] as char)
+        .collect()
+}
+
+/// `code` as typed, in the form rooms are opened under, or `None` if it cannot be a
+/// room code. Case does not matter.
+pub fn parse_room_code(code: &str) -> Option<String> {
+    let code = code.trim().to_ascii_uppercase();
+    let valid =
+        code.len() == ROOM_CODE_LEN && code.bytes().all(|byte| ROOM_CODE_ALPHABET.contains(&byte));
+    valid.then_some(code)
+}
+
+#[derive(Debug)]
+pub enum RelayError {
+    Protocol(ProtocolError),
+    /// The relay turned the request down.
+    Refused(String),
+    /// The relay answered with something else than the request calls for.
+    Unexpected(&'static str),
+}
+
+impl fmt::Display for RelayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RelayError::Protocol(err) => write!(f, "{}", err),
+            RelayError::Refused(reason) => write!(f, "relay refused: {}", reason),
+            RelayError::Unexpected(expected) => write!(f, "expected {} from the relay", expected),
+        }
+    }
+}
+
+impl std::error::Error for RelayError {}
+
+impl From<ProtocolError> for RelayError {
+    fn from(err: ProtocolError) -> Self {
+        RelayError::Protocol(err)
+    }
+}
+
+impl From<io::Error> for RelayError {
+    fn from(err: io::Error) -> Self {
+        RelayError::Protocol(ProtocolError::Io(err))
+    }
+}
+
+// Sends `request` on a new connection to `relay` and reads the first reply.
+fn request(
+    relay: SocketAddr,
+    request: &RelayRequest,
+) -> Result<(TcpStream, RelayReply), RelayError> {
+    let mut stream = TcpStream::connect_timeout(&relay, HANDSHAKE_TIMEOUT)?;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    write_request(&mut stream, request)?;
+    match read_reply(&mut stream)? {
+        RelayReply::Refused(reason) => Err(RelayError::Refused(reason)),
+        reply => Ok((stream, reply)),
+    }
+}
+
+/// A room opened at a relay, waiting for its guest.
+pub struct RelayRoom {
+    stream: TcpStream,
+    code: String,
+}
+
+impl RelayRoom {
+    /// Opens a room at `relay`, under `code` if given.
+    pub fn open(relay: SocketAddr, code: Option<&str>) -> Result<RelayRoom, RelayError> {
+        let (stream, reply) = request(relay, &RelayRequest::Open(code.map(String::from)))?;
+        match reply {
+            RelayReply::Opened(code) => Ok(RelayRoom { stream, code }),
+            _ => Err(RelayError::Unexpected("a room code")),
+        }
+    }
+
+    /// The code the guest joins with.
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    /// Waits up to `timeout` for the guest, and returns whether it has joined.
+    pub fn wait_for_guest(&mut self, timeout: Duration) -> Result<bool, RelayError> {
+        self.stream.set_read_timeout(Some(timeout))?;
+        // Peeked, so that a timeout never leaves half a reply read
+        match self.stream.peek(&mut [0]) {
+            Ok(0) => return Err(ProtocolError::Closed.into()),
+            Ok(_) => {}
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                return Ok(false)
+            }
+            Err(err) => return Err(err.into()),
+        }
+        self.stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        match read_reply(&mut self.stream)? {
+            RelayReply::Paired => Ok(true),
+            RelayReply::Refused(reason) => Err(RelayError::Refused(reason)),
+            RelayReply::Opened(_) => Err(RelayError::Unexpected("a guest")),
+        }
+    }
+
+    /// The connection to the guest, once [`wait_for_guest`](Self::wait_for_guest) said
+    /// it has joined.
+    pub fn into_stream(self) -> io::Result<TcpStream> {
+        self.stream.set_read_timeout(None)?;
+        Ok(self.stream)
+    }
+}
+
+/// Joins the room opened under `code` at `relay`, and returns the connection to its host.
+pub fn join_room(relay: SocketAddr, code: &str) -> Result<TcpStream, RelayError> {
+    let (stream, reply) = request(relay, &RelayRequest::Join(String::from(code)))?;
+    match reply {
+        RelayReply::Paired => {
+            stream.set_read_timeout(None)?;
+            Ok(stream)
+        }
+        _ => Err(RelayError::Unexpected("a host")),
+    }
+}
@@ -0,0 +1,242 @@
+use ggez::graphics::{draw, Color, DrawParam, Image, Text};
+use ggez::mint::Point2;
+use ggez::{Context, GameResult};
+
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::rc::Rc;
+
+use crate::character::{Character, Side};
+use crate::helper::load_image;
+use crate::transform::Transform;
+use crate::world::World;
+
+/// Draws a [`World`] from the point of view of one side.
+pub struct Renderer {
+    background_image: Rc<Image>,
+    foreground_image: Rc<Image>,
+    default_image: Rc<Image>,
+    motion_image: Rc<Image>,
+    target_image: Rc<Image>,
+    hand_image: Rc<Image>,
+    string_image: Rc<Image>,
+    flash_image: Rc<Image>,
+    cooldown_image: Rc<Image>,
+    effect_images: [Rc<Image>; 5],
+}
+
+impl Renderer {
+    pub fn new(ctx: &mut Context, image_pool: &mut HashMap<String, Rc<Image>>) -> Renderer {
+        Renderer {
+            background_image: load_image(ctx, String::from("/background.png"), image_pool),
+            foreground_image: load_image(ctx, String::from("/foreground.png"), image_pool),
+            default_image: load_image(ctx, String::from("/player.png"), image_pool),
+            motion_image: load_image(ctx, String::from("/player_grab.png"), image_pool),
+            target_image: load_image(ctx, String::from("/target.png"), image_pool),
+            hand_image: load_image(ctx, String::from("/grab_hand.png"), image_pool),
+            string_image: load_image(ctx, String::from("/grab_string.png"), image_pool),
+            flash_image: load_image(ctx, String::from("/flash.png"), image_pool),
+            cooldown_image: load_image(ctx, String::from("/cooldown.png"), image_pool),
+            effect_images: [
+                load_image(ctx, String::from("/flash0.png"), image_pool),
+                load_image(ctx, String::from("/flash1.png"), image_pool),
+                load_image(ctx, String::from("/flash2.png"), image_pool),
+                load_image(ctx, String::from("/flash3.png"), image_pool),
+                load_image(ctx, String::from("/flash4.png"), image_pool),
+            ],
+        }
+    }
+
+    /// World to screen transform. The local side is always drawn at the bottom.
+    pub fn view(local: Side) -> Transform {
+        let mut view = Transform::new();
+        view.position = Point2 { x: 640.0, y: 360.0 };
+        if local == Side::Top {
+            view.rotation = PI;
+        }
+        view
+    }
+
+    pub fn draw(&self, ctx: &mut Context, world: &World, local: Side) -> GameResult<()> {
+        draw(ctx, self.background_image.as_ref(), DrawParam::new())?;
+        draw(ctx, self.foreground_image.as_ref(), DrawParam::new())?;
+
+        let player = world.character(local);
+        let opponent = world.character(local.opponent());
+
+        // Draw Score
+        let opponent_score_draw_params = DrawParam::new()
+            .dest(Point2 { x: 640.0, y: 220.0 })
+            .offset(Point2 { x: 0.5, y: 0.5 })
+            .scale([2.0, 2.0])
+            .color(Color::WHITE);
+        draw(
+            ctx,
+            &Text::new(format!("{}", opponent.score)),
+            opponent_score_draw_params,
+        )?;
+
+        let player_score_draw_params = DrawParam::new()
+            .dest(Point2 { x: 640.0, y: 450.0 })
+            .offset(Point2 { x: 0.5, y: 0.5 })
+            .scale([3.0, 3.0])
+            .color(Color::WHITE);
+        draw(
+            ctx,
+            &Text::new(format!("{}", player.score)),
+            player_score_draw_params,
+        )?;
+
+        if world.is_game_end() {
+            let game_end_draw_params = DrawParam::new()
+                .dest(Point2 { x: 640.0, y: 260.0 })
+                .offset(Point2 { x: 0.5, y: 0.5 })
+                .scale([5.0, 5.0])
+                .color(Color::WHITE);
+            let text = if world.winner() == Some(local) {
+                "You Win!\nPress ESC to Exit"
+            } else {
+                "Opponent Win!\nPress ESC to Exit"
+            };
+            draw(ctx, &Text::new(text), game_end_draw_params)?;
+        } else {
+            let view = Renderer::view(local);
+            self.draw_character(ctx, player, &view, false)?;
+            self.draw_character(ctx, opponent, &view, true)?;
+        }
+        Ok(())
+    }
+
+    fn draw_character(
+        &self,
+        ctx: &mut Context,
+        character: &Character,
+        view: &Transform,
+        is_opponent: bool,
+    ) -> GameResult<()> {
+        let global_transform = character.gameobject.global_transform.to_global(view);
+        let target_transform = character
+            .target
+            .gameobject
+            .global_transform
+            .to_global(view);
+
+        if !character.is_grabbed_by {
+            let draw_param = DrawParam::new()
+                .dest(target_transform.position)
+                .rotation(target_transform.rotation - PI / 2.0)
+                .offset(Point2 { x: 0.5, y: 0.0 })
+                .color(if character.target.speed > 0.0 {
+                    Color::CYAN
+                } else {
+                    Color::BLACK
+                });
+            draw(ctx, self.target_image.as_ref(), draw_param)?;
+
+            self.draw_grab(ctx, character, view)?;
+        }
+
+        let (draw_image, rotation) = if character.grab.state == 0.0 {
+            (
+                &self.default_image,
+                global_transform.rotation - (2.0 - character.move_state) * PI / 4.0,
+            )
+        } else {
+            (&self.motion_image, target_transform.rotation - PI / 2.0)
+        };
+        let draw_param = DrawParam::new()
+            .dest(global_transform.position)
+            .rotation(rotation)
+            .offset(Point2 { x: 0.5, y: 0.5 });
+        draw(ctx, draw_image.as_ref(), draw_param)?;
+
+        self.draw_flash(ctx, character, view, is_opponent)
+    }
+
+    fn draw_grab(&self, ctx: &mut Context, character: &Character, view: &Transform) -> GameResult<()> {
+        let grab = &character.grab;
+        if grab.state == 0.0 {
+            return Ok(());
+        }
+        let hand_transform = grab.gameobject.global_transform.to_global(view);
+
+        let scale_y =
+            grab.gameobject.transform.magnitude() / (self.string_image.as_ref().height() as f32);
+
+        let string_draw_param = DrawParam::new()
+            .dest(hand_transform.position)
+            .rotation(hand_transform.rotation - PI / 2.0)
+            .offset(Point2 { x: 0.5, y: 1.0 })
+            .scale(Point2 { x: 1.0, y: scale_y });
+        draw(ctx, self.string_image.as_ref(), string_draw_param)?;
+
+        let hand_draw_param = DrawParam::new()
+            .dest(hand_transform.position)
+            .rotation(hand_transform.rotation - PI / 2.0)
+            .offset(Point2 { x: 0.5, y: 0.5 });
+        draw(ctx, self.hand_image.as_ref(), hand_draw_param)
+    }
+
+    fn draw_flash(
+        &self,
+        ctx: &mut Context,
+        character: &Character,
+        view: &Transform,
+        is_opponent: bool,
+    ) -> GameResult<()> {
+        let flash = &character.flash;
+
+        // Player HUD sits bottom-left, opponent HUD top-right
+        let (hud_x, hud_y) = if is_opponent {
+            (1100.0, -550.0)
+        } else {
+            (0.0, 0.0)
+        };
+
+        let text_draw_params = DrawParam::new()
+            .dest(Point2 { x: hud_x + 50.0, y: hud_y + 685.0 })
+            .scale([1.4, 1.4])
+            .color(Color::YELLOW);
+        draw(ctx, &Text::new("Shift"), text_draw_params)?;
+
+        let draw_param = DrawParam::new()
+            .dest(Point2 { x: hud_x + 80.0, y: hud_y + 640.0 })
+            .offset(Point2 { x: 0.5, y: 0.5 })
+            .scale(Point2 { x: 0.2, y: 0.2 });
+        draw(ctx, self.flash_image.as_ref(), draw_param)?;
+
+        if flash.cooldown > 0.0 {
+            // Draw Cooldown
+            let cooldown_rect_draw_params = DrawParam::new()
+                .dest(Point2 { x: hud_x + 80.0, y: hud_y + 605.0 })
+                .offset(Point2 { x: 0.5, y: 0.0 })
+                .scale([0.32, 0.32])
+                .color(Color::from_rgba(0, 0, 0, 200));
+            draw(ctx, self.cooldown_image.as_ref(), cooldown_rect_draw_params)?;
+
+            let cooldown_text_draw_params = DrawParam::new()
+                .dest(Point2 { x: hud_x + 50.0, y: hud_y + 620.0 })
+                .scale([2.4, 2.4])
+                .color(Color::WHITE);
+            draw(
+                ctx,
+                &Text::new(format!("{:.1}", flash.cooldown)),
+                cooldown_text_draw_params,
+            )?;
+        }
+
+        // Draw Effect
+        let inverse_cooldown = flash.cooltime - flash.cooldown;
+        if inverse_cooldown < 0.25 {
+            let effect_draw_params = DrawParam::new()
+                .dest(flash.gameobject.global_transform.to_global(view).position)
+                .offset([0.5, 0.5]);
+            draw(
+                ctx,
+                self.effect_images[(inverse_cooldown * 20.0) as usize].as_ref(),
+                effect_draw_params,
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,647 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::character::Side;
+use crate::input::Input;
+use crate::world::{MatchRules, ScoreEvent, World, TICK_DT};
+
+/// Version written by [`ReplayWriter`]. Older versions stay loadable.
+pub const REPLAY_FORMAT_VERSION: u32 = 4;
+/// First version keyed by [`World::tick`](crate::World::tick); earlier ones were timed in
+/// seconds on each side's own clock.
+const FIRST_TICK_VERSION: u32 = 2;
+/// First version recording releases of Space and LShift.
+const FIRST_RELEASE_VERSION: u32 = 3;
+/// Version of the game that produced a recording.
+pub const GAME_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Directory finished and running matches are archived in, relative to the working
+/// directory.
+pub const DEFAULT_ARCHIVE_DIR: &str = "replays";
+
+const MAGIC: &str = "ggez-replay";
+const ARCHIVE_PREFIX: &str = "match-";
+const ARCHIVE_EXTENSION: &str = "txt";
+
+/// Everything needed to set up the match again before its inputs are replayed.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ReplayHeader {
+    pub game_version: String,
+    /// Match seed. Recordings made before seeds existed have none.
+    pub seed: Option<u64>,
+    pub rules: MatchRules,
+    /// Player names indexed by [`Side::index`].
+    pub names: [String; 2],
+}
+
+/// One button change of one side. `action` is 1/2/3/4 to press Left/Right/Space/LShift and
+/// the negative to release it; see [`Input::apply_action`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ReplayEvent {
+    pub side: Side,
+    /// Step the change applies to. See [`Replay::uses_side_clock`] for older files.
+    pub tick: u64,
+    pub action: i32,
+}
+
+impl ReplayEvent {
+    /// Every press and release that turns `previous` into `current` on `tick`.
+    pub fn changes(side: Side, tick: u64, previous: Input, current: Input) -> Vec<ReplayEvent> {
+        previous
+            .buttons()
+            .into_iter()
+            .zip(current.buttons())
+            .zip(1..)
+            .filter(|((was, is), _)| was != is)
+            .map(|((_, is), button)| ReplayEvent {
+                side,
+                tick,
+                action: if is { button } else { -button },
+            })
+            .collect()
+    }
+}
+
+/// A loaded recording holding the inputs of both sides in the order they happened.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Replay {
+    /// Format version the file was written with; 0 is the headerless
+    /// `"<seconds> <action>"` format of `player.txt` and `opponent.txt`.
+    pub version: u32,
+    pub header: ReplayHeader,
+    pub events: Vec<ReplayEvent>,
+    /// Inputs of both sides for every step, indexed by tick. Empty before version 2.
+    pub inputs: Vec<[Input; 2]>,
+    /// Points as they were scored while recording. Empty before version 4.
+    pub scores: Vec<ScoreEvent>,
+    /// How the recorded match ended, if it was played to the end from version 4 on.
+    pub outcome: Option<MatchOutcome>,
+}
+
+/// Final state of a finished match, stored so replays can be checked against it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MatchOutcome {
+    pub tick: u64,
+    /// Scores indexed by [`Side::index`].
+    pub scores: [i32; 2],
+    /// [`World::state_hash`] of the last step.
+    pub state_hash: u64,
+}
+
+impl MatchOutcome {
+    pub fn of(world: &World) -> MatchOutcome {
+        MatchOutcome {
+            tick: world.tick,
+            scores: [world.characters[0].score, world.characters[1].score],
+            state_hash: world.state_hash(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    /// The file was written by a newer game than this one.
+    UnsupportedVersion(u32),
+    Malformed {
+        line: usize,
+        reason: String,
+    },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Io(err) => write!(f, "{}", err),
+            ReplayError::UnsupportedVersion(version) => write!(
+                f,
+                "replay format version {} is newer than the supported version {}",
+                version, REPLAY_FORMAT_VERSION
+            ),
+            ReplayError::Malformed { line, reason } => write!(f, "line {}: {}", line, reason),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<io::Error> for ReplayError {
+    fn from(err: io::Error) -> Self {
+        ReplayError::Io(err)
+    }
+}
+
+fn malformed(line: usize, reason: impl Into<String>) -> ReplayError {
+    ReplayError::Malformed {
+        line,
+        reason: reason.into(),
+    }
+}
+
+fn parse_field<T: std::str::FromStr>(
+    line: usize,
+    token: Option<&str>,
+    what: &str,
+) -> Result<T, ReplayError> {
+    let token = token.ok_or_else(|| malformed(line, format!("missing {}", what)))?;
+    token
+        .parse()
+        .map_err(|_| malformed(line, format!("invalid {} `{}`", what, token)))
+}
+
+fn parse_hash(line: usize, token: Option<&str>) -> Result<u64, ReplayError> {
+    let token = token.ok_or_else(|| malformed(line, "missing state hash"))?;
+    u64::from_str_radix(token, 16)
+        .map_err(|_| malformed(line, format!("invalid state hash `{}`", token)))
+}
+
+fn parse_side(line: usize, token: Option<&str>) -> Result<Side, ReplayError> {
+    match token {
+        Some("top") => Ok(Side::Top),
+        Some("bottom") => Ok(Side::Bottom),
+        Some(token) => Err(malformed(line, format!("invalid side `{}`", token))),
+        None => Err(malformed(line, "missing side")),
+    }
+}
+
+// An event at `time` seconds fired on the first step whose clock reached it.
+fn seconds_to_ticks(time: f32) -> u64 {
+    (time / TICK_DT).ceil().max(0.0) as u64
+}
+
+/// Name of `side` as recordings spell it.
+pub fn side_name(side: Side) -> &'static str {
+    match side {
+        Side::Top => "top",
+        Side::Bottom => "bottom",
+    }
+}
+
+impl Replay {
+    pub fn load(path: impl AsRef<Path>) -> Result<Replay, ReplayError> {
+        Replay::read(BufReader::new(File::open(path)?))
+    }
+
+    pub fn read(reader: impl BufRead) -> Result<Replay, ReplayError> {
+        let mut lines = reader
+            .lines()
+            .enumerate()
+            .map(|(idx, line)| line.map(|line| (idx + 1, line)));
+
+        let first = match lines.next() {
+            Some(line) => line?,
+            None => return Err(malformed(1, "empty file")),
+        };
+        let mut tokens = first.1.split_whitespace();
+        if tokens.next() != Some(MAGIC) {
+            return Replay::read_legacy(std::iter::once(Ok(first)).chain(lines));
+        }
+        let version: u32 = parse_field(first.0, tokens.next(), "format version")?;
+        if version > REPLAY_FORMAT_VERSION {
+            return Err(ReplayError::UnsupportedVersion(version));
+        }
+
+        let mut header = ReplayHeader {
+            game_version: String::new(),
+            seed: None,
+            rules: MatchRules::default(),
+            names: [String::new(), String::new()],
+        };
+        let mut in_events = false;
+        let mut events = Vec::new();
+        let mut inputs = Vec::new();
+        let mut scores = Vec::new();
+        let mut outcome = None;
+        for line in lines {
+            let (idx, line) = line?;
+            let mut tokens = line.split_whitespace();
+            let key = match tokens.next() {
+                Some(key) => key,
+                None => continue,
+            };
+            if in_events {
+                match key {
+                    "inputs" => {
+                        let tick: u64 = parse_field(idx, tokens.next(), "tick")?;
+                        if tick != inputs.len() as u64 {
+                            return Err(malformed(
+                                idx,
+                                format!(
+                                    "expected inputs for tick {}, found tick {}",
+                                    inputs.len(),
+                                    tick
+                                ),
+                            ));
+                        }
+                        let top = parse_field(idx, tokens.next(), "top input")?;
+                        let bottom = parse_field(idx, tokens.next(), "bottom input")?;
+                        inputs.push([Input::from_bits(top), Input::from_bits(bottom)]);
+                    }
+                    "score" => scores.push(ScoreEvent {
+                        tick: parse_field(idx, tokens.next(), "tick")?,
+                        side: parse_side(idx, tokens.next())?,
+                        score: parse_field(idx, tokens.next(), "score")?,
+                    }),
+                    "end" => {
+                        outcome = Some(MatchOutcome {
+                            tick: parse_field(idx, tokens.next(), "tick")?,
+                            scores: [
+                                parse_field(idx, tokens.next(), "top score")?,
+                                parse_field(idx, tokens.next(), "bottom score")?,
+                            ],
+                            state_hash: parse_hash(idx, tokens.next())?,
+                        })
+                    }
+                    "top" | "bottom" => {
+                        let side = parse_side(idx, Some(key))?;
+                        events.push(if version < FIRST_TICK_VERSION {
+                            ReplayEvent {
+                                side,
+                                tick: seconds_to_ticks(parse_field(idx, tokens.next(), "time")?),
+                                action: parse_field(idx, tokens.next(), "action")?,
+                            }
+                        } else {
+                            ReplayEvent {
+                                side,
+                                tick: parse_field(idx, tokens.next(), "tick")?,
+                                action: parse_field(idx, tokens.next(), "action")?,
+                            }
+                        });
+                    }
+                    // Lines added by later game versions are skipped
+                    _ => {}
+                }
+                continue;
+            }
+            match key {
+                "game" => header.game_version = parse_field(idx, tokens.next(), "game version")?,
+                "seed" => header.seed = Some(parse_field(idx, tokens.next(), "seed")?),
+                "winning_score" => {
+                    header.rules.winning_score = parse_field(idx, tokens.next(), "winning score")?
+                }
+                "name" => {
+                    let side = parse_side(idx, tokens.next())?;
+                    header.names[side.index()] = tokens.collect::<Vec<_>>().join(" ");
+                }
+                "events" => in_events = true,
+                // Keys added by later game versions are skipped
+                _ => {}
+            }
+        }
+
+        Ok(Replay {
+            version,
+            header,
+            events,
+            inputs,
+            scores,
+            outcome,
+        })
+    }
+
+    // Headerless files only ever held the inputs of the side that was replayed against.
+    fn read_legacy(
+        lines: impl Iterator<Item = io::Result<(usize, String)>>,
+    ) -> Result<Replay, ReplayError> {
+        let mut events = Vec::new();
+        for line in lines {
+            let (idx, line) = line?;
+            let mut tokens = line.split_whitespace();
+            if tokens.clone().next().is_none() {
+                continue;
+            }
+            events.push(ReplayEvent {
+                side: Side::Top,
+                tick: seconds_to_ticks(parse_field(idx, tokens.next(), "time")?),
+                action: parse_field(idx, tokens.next(), "action")?,
+            });
+        }
+        Ok(Replay {
+            version: 0,
+            header: ReplayHeader {
+                game_version: String::new(),
+                seed: None,
+                rules: MatchRules::default(),
+                names: [String::new(), String::new()],
+            },
+            events,
+            inputs: Vec::new(),
+            scores: Vec::new(),
+            outcome: None,
+        })
+    }
+
+    /// Whether event ticks count on each side's own clock, which pauses while that side is
+    /// grabbed, instead of [`World::tick`](crate::World::tick). True for files written in
+    /// seconds, which were played back that way.
+    pub fn uses_side_clock(&self) -> bool {
+        self.version < FIRST_TICK_VERSION
+    }
+
+    /// Whether Space and LShift releases were recorded. Without them each press counts for
+    /// a single step.
+    pub fn has_releases(&self) -> bool {
+        self.version >= FIRST_RELEASE_VERSION
+    }
+
+    /// Events of one side, in order.
+    pub fn events(&self, side: Side) -> impl Iterator<Item = &ReplayEvent> {
+        self.events.iter().filter(move |event| event.side == side)
+    }
+}
+
+/// Writes a recording as the match is played, flushing every event so a crash keeps
+/// everything up to it.
+pub struct ReplayWriter {
+    out: BufWriter<File>,
+}
+
+impl ReplayWriter {
+    pub fn create(path: impl AsRef<Path>, header: &ReplayHeader) -> io::Result<ReplayWriter> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "{} {}", MAGIC, REPLAY_FORMAT_VERSION)?;
+        writeln!(out, "game {}", header.game_version)?;
+        if let Some(seed) = header.seed {
+            writeln!(out, "seed {}", seed)?;
+        }
+        writeln!(out, "winning_score {}", header.rules.winning_score)?;
+        for side in [Side::Top, Side::Bottom] {
+            writeln!(
+                out,
+                "name {} {}",
+                side_name(side),
+                header.names[side.index()]
+            )?;
+        }
+        writeln!(out, "events")?;
+        out.flush()?;
+        Ok(ReplayWriter { out })
+    }
+
+    pub fn record(&mut self, event: &ReplayEvent) -> io::Result<()> {
+        writeln!(
+            self.out,
+            "{} {} {}",
+            side_name(event.side),
+            event.tick,
+            event.action
+        )?;
+        self.out.flush()
+    }
+
+    /// Records the inputs both sides stepped with on `tick`. Ticks must be written in order
+    /// starting at 0.
+    pub fn record_inputs(&mut self, tick: u64, inputs: [Input; 2]) -> io::Result<()> {
+        writeln!(
+            self.out,
+            "inputs {} {} {}",
+            tick,
+            inputs[0].to_bits(),
+            inputs[1].to_bits()
+        )?;
+        self.out.flush()
+    }
+
+    pub fn record_score(&mut self, score_event: &ScoreEvent) -> io::Result<()> {
+        writeln!(
+            self.out,
+            "score {} {} {}",
+            score_event.tick,
+            side_name(score_event.side),
+            score_event.score
+        )?;
+        self.out.flush()
+    }
+
+    /// Stores how the match ended; written once, after its last step.
+    pub fn record_outcome(&mut self, outcome: &MatchOutcome) -> io::Result<()> {
+        writeln!(
+            self.out,
+            "end {} {} {} {:016x}",
+            outcome.tick, outcome.scores[0], outcome.scores[1], outcome.state_hash
+        )?;
+        self.out.flush()
+    }
+}
+
+// Proleptic Gregorian date of a day count since 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// A file in `dir` for a match started at `time`, named by its UTC start time such as
+/// `match-20261017-153012.txt`. Matches started in the same second get a counter.
+pub fn archive_path(dir: &Path, time: SystemTime) -> PathBuf {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64);
+    let (year, month, day) = civil_from_days(secs.div_euclid(86_400));
+    let secs_of_day = secs.rem_euclid(86_400);
+    let stamp = format!(
+        "{}{:04}{:02}{:02}-{:02}{:02}{:02}",
+        ARCHIVE_PREFIX,
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60
+    );
+
+    let mut path = dir.join(format!("{}.{}", stamp, ARCHIVE_EXTENSION));
+    let mut count = 2;
+    while path.exists() {
+        path = dir.join(format!("{}_{}.{}", stamp, count, ARCHIVE_EXTENSION));
+        count += 1;
+    }
+    path
+}
+
+/// Up to `limit` archived matches in `dir`, newest first. A missing directory has none.
+pub fn recent_matches(dir: &Path, limit: usize) -> io::Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+
+    let mut paths = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let is_archive = path.extension().is_some_and(|ext| ext == ARCHIVE_EXTENSION)
+            && path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(ARCHIVE_PREFIX));
+        if is_archive {
+            paths.push(path);
+        }
+    }
+    // Timestamps are zero padded, so names sort by start time
+    paths.sort_unstable_by(|a, b| b.cmp(a));
+    paths.truncate(limit);
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> ReplayHeader {
+        ReplayHeader {
+            game_version: GAME_VERSION.to_string(),
+            seed: Some(42),
+            rules: MatchRules { winning_score: 3 },
+            names: ["Ann Lee".to_string(), "Bo".to_string()],
+        }
+    }
+
+    fn read(text: &str) -> Result<Replay, ReplayError> {
+        Replay::read(text.as_bytes())
+    }
+
+    #[test]
+    fn loads_what_the_writer_recorded() {
+        let path =
+            std::env::temp_dir().join(format!("replay-round-trip-{}.txt", std::process::id()));
+        let inputs = [
+            [Input::default(), Input::from_bits(1)],
+            [Input::from_bits(5), Input::from_bits(2)],
+            [Input::from_bits(15), Input::default()],
+        ];
+        let score = ScoreEvent {
+            tick: 2,
+            side: Side::Bottom,
+            score: 1,
+        };
+        let outcome = MatchOutcome {
+            tick: 3,
+            scores: [0, 1],
+            state_hash: 0x0123_4567_89ab_cdef,
+        };
+
+        let mut writer = ReplayWriter::create(&path, &header()).unwrap();
+        for (tick, inputs) in inputs.iter().enumerate() {
+            writer.record_inputs(tick as u64, *inputs).unwrap();
+        }
+        writer.record_score(&score).unwrap();
+        writer.record_outcome(&outcome).unwrap();
+        drop(writer);
+        let replay = Replay::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        let replay = replay.unwrap();
+        assert_eq!(replay.version, REPLAY_FORMAT_VERSION);
+        assert_eq!(replay.header, header());
+        assert_eq!(replay.inputs, inputs);
+        assert_eq!(replay.scores, [score]);
+        assert_eq!(replay.outcome, Some(outcome));
+        assert!(!replay.uses_side_clock());
+        assert!(replay.has_releases());
+    }
+
+    #[test]
+    fn loads_headerless_files() {
+        let replay = read("0.5 1\n\n1 -1\n").unwrap();
+
+        assert_eq!(replay.version, 0);
+        assert!(replay.uses_side_clock());
+        assert!(!replay.has_releases());
+        assert_eq!(
+            replay.events,
+            [
+                ReplayEvent {
+                    side: Side::Top,
+                    tick: seconds_to_ticks(0.5),
+                    action: 1,
+                },
+                ReplayEvent {
+                    side: Side::Top,
+                    tick: seconds_to_ticks(1.0),
+                    action: -1,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn loads_version_1_files_timed_in_seconds() {
+        let replay = read(
+            "ggez-replay 1\ngame 0.1.0\nwinning_score 5\nname top Ann\nname bottom Bo\n\
+             events\ntop 0.25 3\nbottom 1.5 2\n",
+        )
+        .unwrap();
+
+        assert_eq!(replay.version, 1);
+        assert_eq!(replay.header.game_version, "0.1.0");
+        assert_eq!(replay.header.seed, None);
+        assert_eq!(replay.header.rules.winning_score, 5);
+        assert_eq!(replay.header.names, ["Ann".to_string(), "Bo".to_string()]);
+        assert!(replay.uses_side_clock());
+        assert_eq!(
+            replay.events(Side::Bottom).collect::<Vec<_>>(),
+            [&ReplayEvent {
+                side: Side::Bottom,
+                tick: seconds_to_ticks(1.5),
+                action: 2,
+            }]
+        );
+        assert!(replay.inputs.is_empty());
+        assert_eq!(replay.outcome, None);
+    }
+
+    #[test]
+    fn refuses_newer_versions() {
+        let err = read(&format!(
+            "ggez-replay {}\nevents\n",
+            REPLAY_FORMAT_VERSION + 1
+        ));
+
+        assert!(matches!(err, Err(ReplayError::UnsupportedVersion(version))
+            if version == REPLAY_FORMAT_VERSION + 1));
+    }
+
+    #[test]
+    fn reports_truncated_and_garbled_lines() {
+        let cases = [
+            ("", 1),
+            ("ggez-replay\n", 1),
+            ("ggez-replay four\n", 1),
+            ("ggez-replay 4\nseed\n", 2),
+            ("ggez-replay 4\nwinning_score many\n", 2),
+            ("ggez-replay 4\nname left Ann\n", 2),
+            ("ggez-replay 4\nevents\ninputs 0 1\n", 3),
+            ("ggez-replay 4\nevents\ninputs 0 1 999\n", 3),
+            ("ggez-replay 4\nevents\ninputs 1 0 0\n", 3),
+            ("ggez-replay 4\nevents\ninputs 0 0 0\ninputs 0 0 0\n", 4),
+            ("ggez-replay 4\nevents\nscore 3 left 1\n", 3),
+            ("ggez-replay 4\nevents\nend 9 1 2\n", 3),
+            ("ggez-replay 4\nevents\nend 9 1 2 not-hex\n", 3),
+            ("ggez-replay 4\nevents\ntop 5\n", 3),
+            ("ggez-replay 1\nevents\nbottom soon 1\n", 3),
+            ("0.5\n", 1),
+            ("0.5 1\nhalf 1\n", 2),
+        ];
+
+        for (text, line) in cases {
+            match read(text) {
+                Err(ReplayError::Malformed { line: found, .. }) => {
+                    assert_eq!(found, line, "wrong line for {:?}", text)
+                }
+                other => panic!("{:?} loaded as {:?}", text, other),
+            }
+        }
+    }
+}
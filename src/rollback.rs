@@ -0,0 +1,268 @@
+//! Rollback netcode: only inputs cross the network, and the remote side's input is
+//! predicted until it arrives.
+//!
+//! Each peer steps its [`World`] right away with its own input and a guess for the
+//! remote one. When a remote input turns out to differ from the guess, the world is
+//! restored to the snapshot taken before that tick and stepped again with what is now
+//! known. [`World::step`] is deterministic, so both peers end on the same state once
+//! every input has arrived.
+
+use std::collections::VecDeque;
+
+use crate::character::Side;
+use crate::input::Input;
+use crate::world::{ScoreEvent, World, TICK_DT};
+
+/// How far the local simulation may run ahead of the last confirmed remote input.
+/// Past it the session waits instead of predicting further.
+pub const MAX_PREDICTION_TICKS: u64 = 12;
+
+// What is known of the inputs of one tick
+#[derive(Clone, Copy)]
+struct TickInputs {
+    local: Input,
+    // Remote input, once it has arrived
+    remote: Option<Input>,
+    // Remote input the tick was last stepped with, confirmed or predicted
+    used: Input,
+}
+
+const UNKNOWN_INPUTS: TickInputs = TickInputs {
+    local: Input::NONE,
+    remote: None,
+    used: Input::NONE,
+};
+
+pub struct RollbackSession {
+    world: World,
+    local: Side,
+    // Inputs of each tick from `first_snapshot` on, as far as any have been seen
+    inputs: VecDeque<TickInputs>,
+    // Ticks the local side has played
+    local_tick: u64,
+    // Ticks from the start whose remote input is known
+    confirmed: u64,
+    // Remote input of the last confirmed tick, held down while predicting
+    held_input: Input,
+    // World before each tick from `first_snapshot` on
+    snapshots: VecDeque<World>,
+    first_snapshot: u64,
+    // Earliest tick stepped with a wrong prediction
+    rollback_from: Option<u64>,
+}
+
+impl RollbackSession {
+    /// Starts from `world`, which may be mid-match as when resuming after a reconnect.
+    /// Ticks before it count as confirmed.
+    pub fn new(world: World, local: Side) -> RollbackSession {
+        RollbackSession {
+            first_snapshot: world.tick,
+            local_tick: world.tick,
+            confirmed: world.tick,
+            world,
+            local,
+            inputs: VecDeque::new(),
+            held_input: Input::NONE,
+            snapshots: VecDeque::new(),
+            rollback_from: None,
+        }
+    }
+
+    /// Latest state, including predicted ticks.
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    /// State one tick before [`world`](Self::world), to draw blended towards it.
+    pub fn previous_world(&self) -> &World {
+        self.snapshots.back().unwrap_or(&self.world)
+    }
+
+    /// Ticks whose remote input has arrived. The world is final up to here.
+    pub fn confirmed_tick(&self) -> u64 {
+        self.confirmed
+    }
+
+    /// Latest state that no rollback can change any more, after correcting any
+    /// mispredicted ticks.
+    pub fn confirmed_world(&mut self) -> &World {
+        self.roll_back();
+        if self.world.tick <= self.confirmed {
+            return &self.world;
+        }
+        &self.snapshots[(self.confirmed - self.first_snapshot) as usize]
+    }
+
+    /// False while the local side is [`MAX_PREDICTION_TICKS`] ahead and must wait.
+    pub fn can_advance(&self) -> bool {
+        self.world.tick < self.confirmed + MAX_PREDICTION_TICKS
+    }
+
+    // Inputs of `tick`, which must not be before `first_snapshot`.
+    fn tick_inputs(&mut self, tick: u64) -> &mut TickInputs {
+        let idx = (tick - self.first_snapshot) as usize;
+        if idx >= self.inputs.len() {
+            self.inputs.resize(idx + 1, UNKNOWN_INPUTS);
+        }
+        &mut self.inputs[idx]
+    }
+
+    /// Records the remote side's input for `tick`, scheduling a rollback when it was
+    /// stepped with a different guess. Ticks already confirmed, or further ahead than
+    /// the remote side may predict, are ignored.
+    pub fn add_remote_input(&mut self, tick: u64, input: Input) {
+        if tick < self.confirmed || tick > self.world.tick + MAX_PREDICTION_TICKS {
+            return;
+        }
+        let stepped = tick < self.world.tick;
+        let tick_inputs = self.tick_inputs(tick);
+        if tick_inputs.remote.is_some() {
+            return;
+        }
+        tick_inputs.remote = Some(input);
+
+        if stepped && tick_inputs.used != input {
+            self.rollback_from = Some(self.rollback_from.map_or(tick, |from| from.min(tick)));
+        }
+        while let Some(remote) = self
+            .inputs
+            .get((self.confirmed - self.first_snapshot) as usize)
+            .and_then(|tick_inputs| tick_inputs.remote)
+        {
+            self.held_input = remote;
+            self.confirmed += 1;
+        }
+    }
+
+    // Known remote input for `tick`, or the last confirmed one held down.
+    fn remote_input(&self, tick: u64) -> Input {
+        self.inputs
+            .get((tick - self.first_snapshot) as usize)
+            .and_then(|tick_inputs| tick_inputs.remote)
+            .unwrap_or(self.held_input)
+    }
+
+    fn step_tick(&mut self, tick: u64) -> Vec<ScoreEvent> {
+        if self.world.is_game_end() {
+            return Vec::new();
+        }
+        let remote = self.remote_input(tick);
+        let tick_inputs = self.tick_inputs(tick);
+        tick_inputs.used = remote;
+        let mut inputs = [Input::NONE; 2];
+        inputs[self.local.index()] = tick_inputs.local;
+        inputs[self.local.opponent().index()] = remote;
+
+        self.snapshots.push_back(self.world.clone());
+        self.world.step(TICK_DT, inputs)
+    }
+
+    /// Restores the snapshot before the first mispredicted tick and steps again up to now
+    /// with the inputs that have arrived. [`advance`](Self::advance) does this first.
+    pub fn roll_back(&mut self) {
+        let from = match self.rollback_from.take() {
+            Some(from) => from,
+            None => return,
+        };
+        let now = self.local_tick;
+        self.snapshots
+            .truncate((from - self.first_snapshot + 1) as usize);
+        self.world = self.snapshots.pop_back().unwrap();
+        for tick in from..now {
+            self.step_tick(tick);
+        }
+    }
+
+    /// Steps one tick with the local `input`, after correcting any mispredicted ticks.
+    /// Returns the points scored on it, which a later rollback may still take back.
+    pub fn advance(&mut self, input: Input) -> Vec<ScoreEvent> {
+        self.roll_back();
+        if self.world.is_game_end() {
+            return Vec::new();
+        }
+
+        let tick = self.world.tick;
+        self.tick_inputs(tick).local = input;
+        self.local_tick += 1;
+        let scores = self.step_tick(tick);
+
+        // Ticks before the confirmed one can no longer be rolled back to
+        while self.first_snapshot < self.confirmed && self.snapshots.len() > 1 {
+            self.snapshots.pop_front();
+            self.inputs.pop_front();
+            self.first_snapshot += 1;
+        }
+        scores
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEED: u64 = 7;
+    // The bottom input changes on tick 84, while its inputs are still on the way
+    const TICKS: u64 = 88;
+    // Ticks each remote input arrives after it was played
+    const LATENCY: u64 = 5;
+
+    fn top_input(tick: u64) -> Input {
+        Input::from_bits(if (tick / 5).is_multiple_of(3) { 2 } else { 0 })
+    }
+
+    // Changes often enough that holding the last input mispredicts it
+    fn bottom_input(tick: u64) -> Input {
+        Input::from_bits(if (tick / 7).is_multiple_of(2) { 1 } else { 6 })
+    }
+
+    fn in_order_world(ticks: u64) -> World {
+        let mut world = World::new(SEED);
+        for tick in 0..ticks {
+            world.step(TICK_DT, [top_input(tick), bottom_input(tick)]);
+        }
+        world
+    }
+
+    #[test]
+    fn late_remote_inputs_roll_back_to_the_in_order_state() {
+        let mut session = RollbackSession::new(World::new(SEED), Side::Top);
+        for tick in 0..TICKS {
+            if let Some(played) = tick.checked_sub(LATENCY) {
+                session.add_remote_input(played, bottom_input(played));
+            }
+            assert!(session.can_advance());
+            session.advance(top_input(tick));
+        }
+        let expected = in_order_world(TICKS);
+        assert_eq!(session.world().tick, expected.tick);
+        assert_eq!(session.confirmed_tick(), TICKS - LATENCY);
+        assert_ne!(session.world().state_hash(), expected.state_hash());
+
+        for played in TICKS - LATENCY..TICKS {
+            session.add_remote_input(played, bottom_input(played));
+        }
+        assert_eq!(session.confirmed_tick(), TICKS);
+        assert_eq!(
+            session.confirmed_world().state_hash(),
+            expected.state_hash()
+        );
+        assert_eq!(session.world().state_hash(), expected.state_hash());
+    }
+
+    #[test]
+    fn waits_once_predictions_run_too_far_ahead() {
+        let mut session = RollbackSession::new(World::new(SEED), Side::Top);
+        for tick in 0..MAX_PREDICTION_TICKS {
+            assert!(session.can_advance());
+            session.advance(top_input(tick));
+        }
+        assert!(!session.can_advance());
+
+        session.add_remote_input(0, bottom_input(0));
+        assert!(session.can_advance());
+        assert_eq!(
+            session.confirmed_world().state_hash(),
+            in_order_world(1).state_hash()
+        );
+    }
+}
@@ -0,0 +1,67 @@
+use crate::communication::Communication;
+use crate::game_object::GameObject;
+use crate::transform::Transform;
+
+/// Aim indicator that sweeps along the opponent's line.
+#[derive(Clone, Debug)]
+pub struct Target {
+    pub gameobject: GameObject,
+    pub speed: f32,
+    pub direction: f32,
+    pub look_at_x: f32,
+}
+
+impl Default for Target {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Target {
+    pub fn new() -> Target {
+        Target {
+            gameobject: GameObject::new(),
+            direction: 1.0,
+            speed: 800.0,
+            look_at_x: 0.0,
+        }
+    }
+
+    pub fn get_rotation(&self) -> f32 {
+        self.gameobject.transform.rotation
+    }
+
+    /// Advances the sweep by `dt` seconds. `parent` is the owning character's transform.
+    pub fn update(&mut self, dt: f32, parent: &Transform) {
+        let delta_dist = dt * self.direction * self.speed;
+
+        self.look_at_x += delta_dist;
+
+        if self.look_at_x > 440.0 {
+            self.direction = -1.0;
+            self.look_at_x = 440.0;
+        } else if self.look_at_x < -440.0 {
+            self.direction = 1.0;
+            self.look_at_x = -440.0;
+        }
+
+        let offset = parent.position.y / parent.position.y.abs();
+        let dist_x = offset * (self.look_at_x - parent.position.x);
+        self.gameobject.transform.rotation = dist_x.atan2(580.0_f32);
+        self.gameobject.update_global_transform(Some(parent));
+    }
+}
+
+impl Communication for Target {
+    fn get_send_data(&self) -> Vec<u8> {
+        let mut data = vec![];
+        let la = self.look_at_x.to_le_bytes();
+
+        data.extend_from_slice(&la);
+        data
+    }
+
+    fn set_recv_data(&mut self, buf: &[u8]) {
+        self.look_at_x = f32::from_le_bytes(buf[0..4].try_into().unwrap());
+    }
+}
@@ -0,0 +1,39 @@
+use crate::world::TICK_DT;
+
+// Frames longer than this drop the remaining time instead of catching up.
+const MAX_STEPS_PER_FRAME: u32 = 8;
+
+/// Accumulates variable frame times into a whole number of fixed simulation steps.
+#[derive(Clone, Debug, Default)]
+pub struct FixedTimestep {
+    accumulator: f32,
+}
+
+impl FixedTimestep {
+    pub fn new() -> FixedTimestep {
+        FixedTimestep::default()
+    }
+
+    /// Adds one frame's elapsed time and returns how many steps of [`TICK_DT`] to run.
+    pub fn advance(&mut self, frame_dt: f32) -> u32 {
+        self.accumulator += frame_dt;
+        let mut steps = 0;
+        while self.accumulator >= TICK_DT && steps < MAX_STEPS_PER_FRAME {
+            self.accumulator -= TICK_DT;
+            steps += 1;
+        }
+        if steps == MAX_STEPS_PER_FRAME {
+            self.accumulator = self.accumulator.min(TICK_DT);
+        }
+        steps
+    }
+
+    /// How far the current frame is between the last step and the next one, in `[0, 1]`.
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator / TICK_DT).clamp(0.0, 1.0)
+    }
+
+    pub fn reset(&mut self) {
+        self.accumulator = 0.0;
+    }
+}
@@ -0,0 +1,147 @@
+use ggez::mint::Point2;
+
+use std::f32::consts::PI;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Transform {
+    pub position: Point2<f32>,
+    pub rotation: f32,
+    pub scale: Point2<f32>,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transform {
+    pub fn new() -> Transform {
+        Transform {
+            position: Point2 { x: 0.0, y: 0.0 },
+            rotation: 0.0,
+            scale: Point2 { x: 1.0, y: 1.0 },
+        }
+    }
+
+    pub fn forward(&self) -> Point2<f32> {
+        Point2 {
+            x: self.rotation.cos(),
+            y: self.rotation.sin(),
+        }
+    }
+
+    pub fn back(&self) -> Point2<f32> {
+        Point2 {
+            x: -self.rotation.cos(),
+            y: -self.rotation.sin(),
+        }
+    }
+
+    pub fn right(&self) -> Point2<f32> {
+        Point2 {
+            x: (self.rotation + PI / 2.0).cos(),
+            y: (self.rotation + PI / 2.0).sin(),
+        }
+    }
+
+    pub fn left(&self) -> Point2<f32> {
+        Point2 {
+            x: (self.rotation - PI / 2.0).cos(),
+            y: (self.rotation - PI / 2.0).sin(),
+        }
+    }
+
+    pub fn rotate(&mut self, rotation: f32) {
+        self.rotation += rotation
+    }
+
+    pub fn move_offset(&mut self, offset: Point2<f32>) {
+        self.position.x += offset.x;
+        self.position.y += offset.y;
+    }
+
+    pub fn move_offset_x(&mut self, offset_x: f32) {
+        self.position.x += offset_x;
+    }
+
+    pub fn move_offset_y(&mut self, offset_y: f32) {
+        self.position.y += offset_y;
+    }
+
+    pub fn magnitude(&self) -> f32 {
+        (self.position.x.powf(2.0) + self.position.y.powf(2.0)).sqrt()
+    }
+
+    pub fn rotate_point(point: Point2<f32>, rotation: f32) -> Point2<f32> {
+        let magnitude = (point.x.powf(2.0) + point.y.powf(2.0)).sqrt();
+        let radian = point.y.atan2(point.x) + rotation;
+        Point2 {
+            x: magnitude * radian.cos(),
+            y: magnitude * radian.sin(),
+        }
+    }
+
+    /// Places this transform, given relative to `parent`, into the parent's space.
+    pub fn to_global(&self, parent: &Transform) -> Transform {
+        let scale = Point2 {
+            x: self.scale.x * parent.scale.x,
+            y: self.scale.y * parent.scale.y,
+        };
+        let relative_position = Transform::rotate_point(
+            Point2 {
+                x: self.position.x * scale.x,
+                y: self.position.y * scale.y,
+            },
+            parent.rotation,
+        );
+        Transform {
+            position: Point2 {
+                x: parent.position.x + relative_position.x,
+                y: parent.position.y + relative_position.y,
+            },
+            rotation: self.rotation + parent.rotation,
+            scale,
+        }
+    }
+
+    /// Inverse of [`Transform::to_global`].
+    pub fn to_local(&self, parent: &Transform) -> Transform {
+        let scale = Point2 {
+            x: self.scale.x / parent.scale.x,
+            y: self.scale.y / parent.scale.y,
+        };
+        let local_scaled_position = Transform::rotate_point(
+            Point2 {
+                x: self.position.x - parent.position.x,
+                y: self.position.y - parent.position.y,
+            },
+            -parent.rotation,
+        );
+        Transform {
+            position: Point2 {
+                x: local_scaled_position.x / scale.x,
+                y: local_scaled_position.y / scale.y,
+            },
+            rotation: self.rotation - parent.rotation,
+            scale,
+        }
+    }
+
+    /// Blends towards `other` by `alpha` in `[0, 1]`, turning the short way round.
+    pub fn lerp(&self, other: &Transform, alpha: f32) -> Transform {
+        let lerp = |a: f32, b: f32| a + (b - a) * alpha;
+        let turn = (other.rotation - self.rotation + PI).rem_euclid(2.0 * PI) - PI;
+        Transform {
+            position: Point2 {
+                x: lerp(self.position.x, other.position.x),
+                y: lerp(self.position.y, other.position.y),
+            },
+            rotation: self.rotation + turn * alpha,
+            scale: Point2 {
+                x: lerp(self.scale.x, other.scale.x),
+                y: lerp(self.scale.y, other.scale.y),
+            },
+        }
+    }
+}
@@ -0,0 +1,439 @@
+//! Messages over UDP, so one lost packet does not hold up the ones after it.
+//!
+//! Every datagram starts with a header: its sequence number, the newest sequence number
+//! received from the peer, and a bitfield acking the 32 before that, all `u16`/`u32`
+//! little-endian. Then come entries, each a one byte kind and a message frame as in
+//! [`protocol`](crate::protocol):
+//!
+//! - unreliable entries carry states and pings. They are dropped when a newer packet
+//!   has already arrived.
+//! - reliable entries carry a `u16` id before the frame. They are sent again until a
+//!   packet that carried them is acked, and handed out in id order exactly once.
+
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::protocol::{
+    complete_frame_len, Message, ProtocolError, Transport, LEN_PREFIX, MAX_FRAME_LEN,
+};
+
+/// Largest datagram sent; small enough not to be fragmented on common links.
+pub const MAX_PACKET_LEN: usize = 1200;
+
+/// How long an unacked reliable message waits before it is sent again.
+pub const RESEND_INTERVAL: Duration = Duration::from_millis(100);
+
+const HEADER_LEN: usize = 8;
+
+const ENTRY_UNRELIABLE: u8 = 0;
+const ENTRY_RELIABLE: u8 = 1;
+
+// Whether sequence number `a` comes after `b`, allowing for wrap around.
+fn is_newer(a: u16, b: u16) -> bool {
+    a != b && a.wrapping_sub(b) < 0x8000
+}
+
+struct PendingReliable {
+    id: u16,
+    frame: Vec<u8>,
+    sent_at: Option<Instant>,
+}
+
+pub struct UdpConnection {
+    socket: UdpSocket,
+    peer: SocketAddr,
+
+    next_sequence: u16,
+    // Unreliable frames waiting for the next packet
+    unreliable: Vec<Vec<u8>>,
+    // Reliable messages not acked yet, oldest first
+    pending: VecDeque<PendingReliable>,
+    next_reliable_id: u16,
+    // Reliable ids carried by each packet still waiting for an ack
+    in_flight: HashMap<u16, Vec<u16>>,
+
+    // Newest sequence number received and which of the 32 before it arrived
+    remote_sequence: Option<u16>,
+    ack_bits: u32,
+    ack_due: bool,
+    // Reliable messages that arrived ahead of `next_delivery`
+    early: HashMap<u16, Message>,
+    next_delivery: u16,
+    recv_buf: Vec<u8>,
+}
+
+impl UdpConnection {
+    /// Talks to `peer` from `socket`, which is switched to nonblocking.
+    pub fn new(socket: UdpSocket, peer: SocketAddr) -> io::Result<UdpConnection> {
+        socket.set_nonblocking(true)?;
+        socket.connect(peer)?;
+        Ok(UdpConnection {
+            socket,
+            peer,
+            next_sequence: 0,
+            unreliable: Vec::new(),
+            pending: VecDeque::new(),
+            next_reliable_id: 0,
+            in_flight: HashMap::new(),
+            remote_sequence: None,
+            ack_bits: 0,
+            ack_due: false,
+            early: HashMap::new(),
+            next_delivery: 0,
+            // Room for a lone frame of the largest size, beyond MAX_PACKET_LEN
+            recv_buf: vec![0; HEADER_LEN + 3 + LEN_PREFIX + MAX_FRAME_LEN],
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    fn header(&mut self) -> Vec<u8> {
+        let sequence = self.next_sequence;
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        let mut packet = Vec::with_capacity(MAX_PACKET_LEN);
+        packet.extend_from_slice(&sequence.to_le_bytes());
+        packet.extend_from_slice(&self.remote_sequence.unwrap_or(0).to_le_bytes());
+        packet.extend_from_slice(&self.ack_bits.to_le_bytes());
+        packet
+    }
+
+    fn send_packet(&mut self, packet: &[u8]) -> Result<(), ProtocolError> {
+        match self.socket.send(packet) {
+            Ok(_) => Ok(()),
+            // A full send buffer is just another lost packet
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    // Marks `sequence` as received. Returns false when it is older than one already seen.
+    fn note_sequence(&mut self, sequence: u16) -> bool {
+        let remote = match self.remote_sequence {
+            Some(remote) => remote,
+            None => {
+                self.remote_sequence = Some(sequence);
+                return true;
+            }
+        };
+        if is_newer(sequence, remote) {
+            let shift = sequence.wrapping_sub(remote) as u32;
+            self.ack_bits = match shift {
+                1..=31 => (self.ack_bits << shift) | (1 << (shift - 1)),
+                32 => 1 << 31,
+                _ => 0,
+            };
+            self.remote_sequence = Some(sequence);
+            true
+        } else {
+            let behind = remote.wrapping_sub(sequence) as u32;
+            if (1..=32).contains(&behind) {
+                self.ack_bits |= 1 << (behind - 1);
+            }
+            false
+        }
+    }
+
+    fn acknowledge(&mut self, sequence: u16) {
+        if let Some(ids) = self.in_flight.remove(&sequence) {
+            self.pending.retain(|pending| !ids.contains(&pending.id));
+        }
+    }
+
+    fn process_acks(&mut self, ack: u16, ack_bits: u32) {
+        self.acknowledge(ack);
+        for bit in 0..32 {
+            if ack_bits & (1 << bit) != 0 {
+                self.acknowledge(ack.wrapping_sub(bit + 1));
+            }
+        }
+    }
+
+    // Parses one datagram, queueing what it delivers into `messages`.
+    fn process_packet(
+        &mut self,
+        packet: &[u8],
+        messages: &mut Vec<Message>,
+    ) -> Result<(), ProtocolError> {
+        if packet.len() < HEADER_LEN {
+            return Err(ProtocolError::Malformed {
+                message: "UDP packet",
+                reason: "shorter than its header",
+            });
+        }
+        let sequence = u16::from_le_bytes(packet[0..2].try_into().unwrap());
+        let ack = u16::from_le_bytes(packet[2..4].try_into().unwrap());
+        let ack_bits = u32::from_le_bytes(packet[4..8].try_into().unwrap());
+        let is_latest = self.note_sequence(sequence);
+        self.process_acks(ack, ack_bits);
+        // Bare acks need no ack of their own
+        self.ack_due |= packet.len() > HEADER_LEN;
+
+        let mut rest = &packet[HEADER_LEN..];
+        while let Some((&kind, body)) = rest.split_first() {
+            let (id, body) = match kind {
+                ENTRY_UNRELIABLE => (None, body),
+                ENTRY_RELIABLE if body.len() >= 2 => {
+                    let (id, body) = body.split_at(2);
+                    (Some(u16::from_le_bytes(id.try_into().unwrap())), body)
+                }
+                _ => {
+                    return Err(ProtocolError::Malformed {
+                        message: "UDP packet",
+                        reason: "bad entry",
+                    })
+                }
+            };
+            let len = complete_frame_len(body)?.ok_or(ProtocolError::Malformed {
+                message: "UDP packet",
+                reason: "truncated frame",
+            })?;
+            let message = Message::decode(&body[LEN_PREFIX..LEN_PREFIX + len])?;
+            rest = &body[LEN_PREFIX + len..];
+
+            match id {
+                None if is_latest => messages.push(message),
+                None => {}
+                Some(id) if id == self.next_delivery || is_newer(id, self.next_delivery) => {
+                    self.early.insert(id, message);
+                }
+                Some(_) => {}
+            }
+        }
+
+        while let Some(message) = self.early.remove(&self.next_delivery) {
+            messages.push(message);
+            self.next_delivery = self.next_delivery.wrapping_add(1);
+        }
+        Ok(())
+    }
+}
+
+impl Transport for UdpConnection {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.peer)
+    }
+
+    fn send(&mut self, message: &Message) -> Result<(), ProtocolError> {
+        if message.is_reliable() {
+            self.pending.push_back(PendingReliable {
+                id: self.next_reliable_id,
+                frame: message.encode(),
+                sent_at: None,
+            });
+            self.next_reliable_id = self.next_reliable_id.wrapping_add(1);
+        } else {
+            self.unreliable.push(message.encode());
+        }
+        Ok(())
+    }
+
+    /// Packs queued and due reliable messages into as few datagrams as fit, and sends at
+    /// least an ack when something arrived since the last packet.
+    fn flush(&mut self) -> Result<(), ProtocolError> {
+        let now = Instant::now();
+        // Encoded entries, with the reliable id each one carries
+        let mut entries: Vec<(Option<u16>, Vec<u8>)> = Vec::new();
+        for pending in &mut self.pending {
+            if pending
+                .sent_at
+                .is_some_and(|sent_at| now - sent_at < RESEND_INTERVAL)
+            {
+                continue;
+            }
+            pending.sent_at = Some(now);
+            let mut entry = vec![ENTRY_RELIABLE];
+            entry.extend_from_slice(&pending.id.to_le_bytes());
+            entry.extend_from_slice(&pending.frame);
+            entries.push((Some(pending.id), entry));
+        }
+        for frame in self.unreliable.drain(..) {
+            let mut entry = vec![ENTRY_UNRELIABLE];
+            entry.extend(frame);
+            entries.push((None, entry));
+        }
+        if entries.is_empty() && !self.ack_due {
+            return Ok(());
+        }
+
+        let mut entries = entries.into_iter().peekable();
+        loop {
+            let sequence = self.next_sequence;
+            let mut packet = self.header();
+            let mut carried = Vec::new();
+            while let Some((id, entry)) = entries.next_if(|(_, entry)| {
+                packet.len() == HEADER_LEN || packet.len() + entry.len() <= MAX_PACKET_LEN
+            }) {
+                packet.extend(entry);
+                carried.extend(id);
+            }
+            if !carried.is_empty() {
+                self.in_flight.insert(sequence, carried);
+            }
+            // Acks reach 33 packets back, so older packets will never be acked
+            self.in_flight
+                .retain(|&carrier, _| sequence.wrapping_sub(carrier) <= 32);
+            self.send_packet(&packet)?;
+            if entries.peek().is_none() {
+                break;
+            }
+        }
+        self.ack_due = false;
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Result<Vec<Message>, ProtocolError> {
+        let mut messages = Vec::new();
+        let mut buf = std::mem::take(&mut self.recv_buf);
+        let result = loop {
+            match self.socket.recv(&mut buf) {
+                Ok(len) => {
+                    if let Err(err) = self.process_packet(&buf[..len], &mut messages) {
+                        break Err(err);
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break Ok(messages),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                // An earlier packet found no one listening yet; nothing to read
+                Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => {}
+                Err(err) => break Err(err.into()),
+            }
+        };
+        self.recv_buf = buf;
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::input::Input;
+
+    // A connection and the raw socket of its peer, which sees and forges every datagram.
+    fn connected() -> (UdpConnection, UdpSocket) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let wire = UdpSocket::bind("127.0.0.1:0").unwrap();
+        wire.connect(socket.local_addr().unwrap()).unwrap();
+        wire.set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        let connection = UdpConnection::new(socket, wire.local_addr().unwrap()).unwrap();
+        (connection, wire)
+    }
+
+    fn input(tick: u64) -> Message {
+        Message::Input {
+            tick,
+            input: Input::NONE,
+        }
+    }
+
+    fn packet(
+        sequence: u16,
+        ack: u16,
+        ack_bits: u32,
+        entries: &[(Option<u16>, Message)],
+    ) -> Vec<u8> {
+        let mut packet = Vec::new();
+        packet.extend_from_slice(&sequence.to_le_bytes());
+        packet.extend_from_slice(&ack.to_le_bytes());
+        packet.extend_from_slice(&ack_bits.to_le_bytes());
+        for (id, message) in entries {
+            match id {
+                Some(id) => {
+                    packet.push(ENTRY_RELIABLE);
+                    packet.extend_from_slice(&id.to_le_bytes());
+                }
+                None => packet.push(ENTRY_UNRELIABLE),
+            }
+            packet.extend(message.encode());
+        }
+        packet
+    }
+
+    // Sequence number, ack, ack bits and entries of the next datagram on the wire.
+    fn next_packet(wire: &UdpSocket) -> Option<(u16, u16, u32, Vec<u8>)> {
+        let mut buf = [0; MAX_PACKET_LEN];
+        let len = wire.recv(&mut buf).ok()?;
+        Some((
+            u16::from_le_bytes(buf[0..2].try_into().unwrap()),
+            u16::from_le_bytes(buf[2..4].try_into().unwrap()),
+            u32::from_le_bytes(buf[4..8].try_into().unwrap()),
+            buf[HEADER_LEN..len].to_vec(),
+        ))
+    }
+
+    fn deliver(wire: &UdpSocket, connection: &mut UdpConnection, packet: &[u8]) -> Vec<Message> {
+        wire.send(packet).unwrap();
+        thread::sleep(Duration::from_millis(20));
+        connection.receive().unwrap()
+    }
+
+    #[test]
+    fn numbers_packets_and_acks_those_that_arrived() {
+        let (mut connection, wire) = connected();
+
+        connection.send(&Message::Ping(1)).unwrap();
+        connection.flush().unwrap();
+        let (sequence, ..) = next_packet(&wire).unwrap();
+        assert_eq!(sequence, 0);
+
+        let pinged = |sequence, token| packet(sequence, 0, 0, &[(None, Message::Ping(token))]);
+        assert_eq!(
+            deliver(&wire, &mut connection, &pinged(0, 10)),
+            [Message::Ping(10)]
+        );
+        assert_eq!(
+            deliver(&wire, &mut connection, &pinged(3, 13)),
+            [Message::Ping(13)]
+        );
+        // Overtaken by packet 3, so its unreliable entry is stale
+        assert!(deliver(&wire, &mut connection, &pinged(1, 11)).is_empty());
+
+        connection.flush().unwrap();
+        assert_eq!(next_packet(&wire), Some((1, 3, 0b110, Vec::new())));
+        // Nothing arrived since, so there is nothing to ack
+        connection.flush().unwrap();
+        assert_eq!(next_packet(&wire), None);
+    }
+
+    #[test]
+    fn resends_reliable_messages_until_acked() {
+        let (mut connection, wire) = connected();
+
+        connection.send(&input(5)).unwrap();
+        connection.flush().unwrap();
+        let (_, _, _, entries) = next_packet(&wire).unwrap();
+        connection.flush().unwrap();
+        assert_eq!(next_packet(&wire), None);
+
+        thread::sleep(RESEND_INTERVAL);
+        connection.flush().unwrap();
+        let (sequence, _, _, resent) = next_packet(&wire).unwrap();
+        assert_eq!(sequence, 1);
+        assert_eq!(resent, entries);
+
+        deliver(&wire, &mut connection, &packet(0, sequence, 0, &[]));
+        thread::sleep(RESEND_INTERVAL);
+        connection.flush().unwrap();
+        assert_eq!(next_packet(&wire), None);
+    }
+
+    #[test]
+    fn hands_out_reliable_messages_once_in_order() {
+        let (mut connection, wire) = connected();
+
+        let early = packet(0, 0, 0, &[(Some(1), input(1))]);
+        assert!(deliver(&wire, &mut connection, &early).is_empty());
+        let both = packet(1, 0, 0, &[(Some(0), input(0)), (Some(1), input(1))]);
+        assert_eq!(deliver(&wire, &mut connection, &both), [input(0), input(1)]);
+        // Resent because the acks were lost
+        assert!(deliver(&wire, &mut connection, &both).is_empty());
+        let next = packet(2, 0, 0, &[(Some(1), input(1)), (Some(2), input(2))]);
+        assert_eq!(deliver(&wire, &mut connection, &next), [input(2)]);
+    }
+}
@@ -0,0 +1,321 @@
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+use crate::character::{Character, Side};
+use crate::communication::Communication;
+use crate::input::Input;
+
+pub const WINNING_SCORE: i32 = 3;
+
+/// Simulation steps per second.
+pub const TICK_RATE: u32 = 60;
+/// Length of one simulation step in seconds.
+pub const TICK_DT: f32 = 1.0 / TICK_RATE as f32;
+
+/// Length of [`World::state_bytes`].
+pub const WORLD_STATE_LEN: usize = 32 + 2 * 79;
+
+/// Settings agreed for a match before it starts.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MatchRules {
+    /// Score that ends the match.
+    pub winning_score: i32,
+}
+
+impl Default for MatchRules {
+    fn default() -> Self {
+        MatchRules {
+            winning_score: WINNING_SCORE,
+        }
+    }
+}
+
+/// A point was scored on `tick`, bringing `side` to `score`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ScoreEvent {
+    pub tick: u64,
+    pub side: Side,
+    pub score: i32,
+}
+
+/// Something only the peer with grab authority decides, for the other one to apply with
+/// [`World::apply_match_event`].
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MatchEvent {
+    /// The hand thrown by `grabber` caught the opponent.
+    Hit { grabber: Side },
+    /// The hand thrown by `grabber` came back empty.
+    Miss { grabber: Side },
+    /// `side` was put back on its spawn line at `x`.
+    Respawn { side: Side, x: f32 },
+    /// `side` now has `score` points.
+    Score { side: Side, score: i32 },
+}
+
+impl From<ScoreEvent> for MatchEvent {
+    fn from(event: ScoreEvent) -> Self {
+        MatchEvent::Score {
+            side: event.side,
+            score: event.score,
+        }
+    }
+}
+
+/// Random source for everything the simulation decides by chance. It is portable, so the
+/// same seed gives the same draws on every peer and in every replay.
+pub type MatchRng = ChaCha8Rng;
+
+/// Complete match state, stepped without a ggez `Context`.
+///
+/// Coordinates are world space with the arena centre at the origin; the top character
+/// spawns at `y = -290` and the bottom one at `y = 290`.
+#[derive(Clone, Debug)]
+pub struct World {
+    pub characters: [Character; 2],
+    /// Number of steps taken so far.
+    pub tick: u64,
+    /// Seed the match was started with; peers and recordings share it.
+    pub seed: u64,
+    pub rules: MatchRules,
+    /// Whether grab hits, respawns and scores are decided here. A world without it leaves
+    /// them to [`MatchEvent`]s from a peer that has it.
+    pub resolves_grabs: bool,
+    rng: MatchRng,
+}
+
+impl World {
+    pub fn new(seed: u64) -> World {
+        World::with_rules(seed, MatchRules::default())
+    }
+
+    pub fn with_rules(seed: u64, rules: MatchRules) -> World {
+        World {
+            characters: [Character::new(Side::Top), Character::new(Side::Bottom)],
+            tick: 0,
+            seed,
+            rules,
+            resolves_grabs: true,
+            rng: MatchRng::seed_from_u64(seed),
+        }
+    }
+
+    /// A fresh seed for a match that is not reproducing an earlier one.
+    pub fn random_seed() -> u64 {
+        rand::random()
+    }
+
+    pub fn character(&self, side: Side) -> &Character {
+        &self.characters[side.index()]
+    }
+
+    pub fn character_mut(&mut self, side: Side) -> &mut Character {
+        &mut self.characters[side.index()]
+    }
+
+    pub fn winner(&self) -> Option<Side> {
+        self.characters
+            .iter()
+            .find(|character| character.score >= self.rules.winning_score)
+            .map(|character| character.side)
+    }
+
+    pub fn is_game_end(&self) -> bool {
+        self.winner().is_some()
+    }
+
+    /// Advances the match by `dt` seconds and returns the points scored on the way.
+    /// `inputs` is indexed by [`Side::index`].
+    pub fn step(&mut self, dt: f32, inputs: [Input; 2]) -> Vec<ScoreEvent> {
+        if self.is_game_end() {
+            return Vec::new();
+        }
+
+        for (character, input) in self.characters.iter_mut().zip(inputs) {
+            character.apply_input(input);
+        }
+
+        let scores = [self.characters[0].score, self.characters[1].score];
+        let [top, bottom] = &mut self.characters;
+        top.update(dt, bottom, &mut self.rng, self.resolves_grabs);
+        bottom.update(dt, top, &mut self.rng, self.resolves_grabs);
+
+        let tick = self.tick;
+        self.tick += 1;
+        self.characters
+            .iter()
+            .zip(scores)
+            .filter(|(character, score)| character.score != *score)
+            .map(|(character, _)| ScoreEvent {
+                tick,
+                side: character.side,
+                score: character.score,
+            })
+            .collect()
+    }
+
+    /// Grab outcomes between this state and `next`, one step later. Scores come from
+    /// [`step`](Self::step).
+    pub fn grab_events(&self, next: &World) -> Vec<MatchEvent> {
+        let mut events = Vec::new();
+        for (before, after) in self.characters.iter().zip(&next.characters) {
+            if before.grab.state == 1.0 && after.grab.state == -1.0 {
+                events.push(MatchEvent::Hit {
+                    grabber: after.side,
+                });
+            } else if before.grab.state == 1.0 && after.grab.state == 0.0 {
+                events.push(MatchEvent::Miss {
+                    grabber: after.side,
+                });
+            }
+            if before.is_grabbed_by && !after.is_grabbed_by {
+                events.push(MatchEvent::Respawn {
+                    side: after.side,
+                    x: after.gameobject.transform.position.x,
+                });
+            }
+        }
+        events
+    }
+
+    /// Applies an outcome decided by the peer with grab authority.
+    pub fn apply_match_event(&mut self, event: MatchEvent) {
+        match event {
+            MatchEvent::Hit { grabber } => {
+                let [top, bottom] = &mut self.characters;
+                let (grabber, target) = match grabber {
+                    Side::Top => (top, bottom),
+                    Side::Bottom => (bottom, top),
+                };
+                grabber.grab.catch(target);
+            }
+            MatchEvent::Miss { grabber } => self.character_mut(grabber).grab.state = 0.0,
+            MatchEvent::Respawn { side, x } => self.character_mut(side).rebirth_at(x),
+            MatchEvent::Score { side, score } => self.character_mut(side).score = score,
+        }
+    }
+
+    /// Mirrors the character on `side` from its owner's
+    /// [`get_send_data`](Communication::get_send_data). With grab authority its hand and
+    /// score stay as resolved here, and so does its position while it is held.
+    pub fn set_remote_character(&mut self, side: Side, data: &[u8]) {
+        let character = &mut self.characters[side.index()];
+        if !self.resolves_grabs {
+            character.set_recv_data(data);
+            return;
+        }
+
+        let resolved = character.clone();
+        character.set_recv_data(data);
+        character.grab = resolved.grab;
+        character.score = resolved.score;
+        if resolved.is_grabbed_by {
+            character.gameobject = resolved.gameobject;
+        }
+        character.update_children_global_transform();
+    }
+
+    /// Everything the simulation carries from one step to the next, little-endian,
+    /// [`WORLD_STATE_LEN`] bytes. The rules and grab authority are not included; they are
+    /// agreed before the match.
+    pub fn state_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(WORLD_STATE_LEN);
+        data.extend_from_slice(&self.tick.to_le_bytes());
+        data.extend_from_slice(&self.seed.to_le_bytes());
+        data.extend_from_slice(&self.rng.get_word_pos().to_le_bytes());
+        for character in &self.characters {
+            let transforms = [
+                character.gameobject.transform,
+                character.target.gameobject.transform,
+                character.grab.gameobject.transform,
+                character.flash.gameobject.transform,
+            ];
+            for transform in transforms {
+                data.extend_from_slice(&transform.position.x.to_le_bytes());
+                data.extend_from_slice(&transform.position.y.to_le_bytes());
+                data.extend_from_slice(&transform.rotation.to_le_bytes());
+            }
+            data.extend_from_slice(&[
+                character.input.to_bits(),
+                character.is_grabbed_by as u8,
+                character.grab.check_grab_once as u8,
+            ]);
+            data.extend_from_slice(&character.score.to_le_bytes());
+            data.extend_from_slice(&character.move_state.to_le_bytes());
+            data.extend_from_slice(&character.target.direction.to_le_bytes());
+            data.extend_from_slice(&character.target.speed.to_le_bytes());
+            data.extend_from_slice(&character.target.look_at_x.to_le_bytes());
+            data.extend_from_slice(&character.grab.state.to_le_bytes());
+            data.extend_from_slice(&character.flash.cooldown.to_le_bytes());
+        }
+        data
+    }
+
+    /// Takes over the state from another world's [`state_bytes`](Self::state_bytes), so
+    /// stepping on from here matches stepping on from there.
+    pub fn restore_state(&mut self, data: &[u8]) {
+        let (header, rest) = data.split_at(32);
+        self.tick = u64::from_le_bytes(header[0..8].try_into().unwrap());
+        self.seed = u64::from_le_bytes(header[8..16].try_into().unwrap());
+        self.rng = MatchRng::seed_from_u64(self.seed);
+        self.rng
+            .set_word_pos(u128::from_le_bytes(header[16..32].try_into().unwrap()));
+
+        for (character, data) in self.characters.iter_mut().zip(rest.chunks_exact(79)) {
+            let (transforms, data) = data.split_at(48);
+            let gameobjects = [
+                &mut character.gameobject,
+                &mut character.target.gameobject,
+                &mut character.grab.gameobject,
+                &mut character.flash.gameobject,
+            ];
+            for (gameobject, transform) in gameobjects.into_iter().zip(transforms.chunks_exact(12))
+            {
+                let value =
+                    |idx: usize| f32::from_le_bytes(transform[idx..idx + 4].try_into().unwrap());
+                gameobject.transform.position.x = value(0);
+                gameobject.transform.position.y = value(4);
+                gameobject.transform.rotation = value(8);
+            }
+            let (flags, data) = data.split_at(3);
+            character.input = Input::from_bits(flags[0]);
+            character.is_grabbed_by = flags[1] != 0;
+            character.grab.check_grab_once = flags[2] != 0;
+            let value = |idx: usize| data[idx..idx + 4].try_into().unwrap();
+            character.score = i32::from_le_bytes(value(0));
+            character.move_state = f32::from_le_bytes(value(4));
+            character.target.direction = f32::from_le_bytes(value(8));
+            character.target.speed = f32::from_le_bytes(value(12));
+            character.target.look_at_x = f32::from_le_bytes(value(16));
+            character.grab.state = f32::from_le_bytes(value(20));
+            character.flash.cooldown = f32::from_le_bytes(value(24));
+
+            character.update_children_global_transform();
+            character.flash.gameobject.update_global_transform(None);
+        }
+    }
+
+    /// FNV-1a hash of [`state_bytes`](Self::state_bytes). It only depends on the state, so
+    /// two runs of a match agree on it on any platform.
+    pub fn state_hash(&self) -> u64 {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for byte in self.state_bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+        hash
+    }
+
+    /// State to draw `alpha` of the way from this step to `next`.
+    pub fn lerp(&self, next: &World, alpha: f32) -> World {
+        let [top, bottom] = &self.characters;
+        let [next_top, next_bottom] = &next.characters;
+        World {
+            characters: [top.lerp(next_top, alpha), bottom.lerp(next_bottom, alpha)],
+            tick: next.tick,
+            seed: next.seed,
+            rules: next.rules,
+            resolves_grabs: next.resolves_grabs,
+            rng: next.rng.clone(),
+        }
+    }
+}